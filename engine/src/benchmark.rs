@@ -5,16 +5,27 @@
 //!
 //! To use this code, create a [`Runner`] object using [`Runner::new`],
 //! then call [`Runner::suites`] to run a set of benchmark suits.
+//!
+//! [`Runner::differential`] instead uses the benchmark suites to check that
+//! the egglog and semi-naive Datalog engines agree on every oracle query.
 
+use crate::datalog::{self, Engine};
+use crate::top_down::InhabitationOracle;
 use crate::util::{EarlyCutoff, Timer};
-use crate::{core, menu, parse, top_down, typecheck};
+use crate::{
+    core, dl_oracle, egglog, menu, parse, seminaive, top_down, typecheck,
+};
 
+use indexmap::IndexSet;
 use instant::{Duration, Instant};
 use pbn::Step;
 use rayon::prelude::*;
 use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::io;
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 /// Benchmark configuration.
@@ -65,6 +76,129 @@ struct EntryResult {
     duration: u128,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct DifferentialResult {
+    // Key
+    suite_name: String,
+    entry_name: String,
+    // Value
+    sketches: usize,
+    queries: usize,
+    mismatches: usize,
+    first_mismatch: String,
+    error: String,
+    egglog_duration: u128,
+    seminaive_duration: u128,
+}
+
+/// A Datalog engine that runs every query on two engines and records the
+/// queries on which they disagree (answering with the first engine), along
+/// with the total time spent in each engine.
+struct Differential<A: Engine, B: Engine> {
+    a: A,
+    b: B,
+    queries: Rc<Cell<usize>>,
+    mismatches: Rc<RefCell<Vec<String>>>,
+    durations: Rc<Cell<(Duration, Duration)>>,
+}
+
+impl<A: Engine, B: Engine> Differential<A, B> {
+    fn timed<T>(
+        &mut self,
+        mut fa: impl FnMut(&mut A) -> T,
+        mut fb: impl FnMut(&mut B) -> T,
+    ) -> (T, T) {
+        let (mut da, mut db) = self.durations.get();
+        let now = Instant::now();
+        let a = fa(&mut self.a);
+        da += now.elapsed();
+        let now = Instant::now();
+        let b = fb(&mut self.b);
+        db += now.elapsed();
+        self.durations.set((da, db));
+        (a, b)
+    }
+
+    fn compare<T: Eq + std::hash::Hash>(
+        &self,
+        rule: &datalog::Rule,
+        a: IndexSet<T>,
+        b: IndexSet<T>,
+    ) {
+        self.queries.set(self.queries.get() + 1);
+
+        if a != b {
+            self.mismatches.borrow_mut().push(format!(
+                "{} ({} answers only from the first engine, {} only from the \
+                 second)",
+                rule.name,
+                a.difference(&b).count(),
+                b.difference(&a).count(),
+            ));
+        }
+    }
+}
+
+impl<A: Engine, B: Engine> Engine for Differential<A, B> {
+    fn load(
+        &mut self,
+        program: datalog::Program,
+    ) -> Result<(), datalog::Error> {
+        let (a, b) = self
+            .timed(|a| a.load(program.clone()), |b| b.load(program.clone()));
+        a?;
+        b
+    }
+
+    fn query(
+        &mut self,
+        signature: &datalog::RelationSignature,
+        rule: &datalog::Rule,
+    ) -> Result<Vec<Vec<datalog::Value>>, datalog::Error> {
+        let (a, b) = self
+            .timed(|a| a.query(signature, rule), |b| b.query(signature, rule));
+        let (a, b) = (a?, b?);
+        self.compare(rule, a.iter().collect(), b.iter().collect());
+        Ok(a)
+    }
+
+    fn query_with_provenance(
+        &mut self,
+        signature: &datalog::RelationSignature,
+        rule: &datalog::Rule,
+    ) -> Result<
+        Vec<(Vec<datalog::Value>, datalog::DerivationTree)>,
+        datalog::Error,
+    > {
+        let (a, b) = self.timed(
+            |a| a.query_with_provenance(signature, rule),
+            |b| b.query_with_provenance(signature, rule),
+        );
+        let (a, b) = (a?, b?);
+        self.compare(
+            rule,
+            a.iter().map(|(vals, _)| vals).collect(),
+            b.iter().map(|(vals, _)| vals).collect(),
+        );
+        Ok(a)
+    }
+
+    fn exists(
+        &mut self,
+        signature: &datalog::RelationSignature,
+        rule: &datalog::Rule,
+        bound: &[Option<datalog::Value>],
+    ) -> Result<bool, datalog::Error> {
+        let (a, b) = self.timed(
+            |a| a.exists(signature, rule, bound),
+            |b| b.exists(signature, rule, bound),
+        );
+        let (a, b) = (a?, b?);
+        self.compare(rule, IndexSet::from([a]), IndexSet::from([b]));
+        Ok(a)
+    }
+}
+
 impl Runner {
     /// Create a new benchmark runner (start here!). The `writer` argument is
     /// the location that the benchmark results will get written to
@@ -83,15 +217,17 @@ impl Runner {
         }
     }
 
-    fn load_entries(&self, suite_paths: &Vec<PathBuf>) -> Vec<Entry> {
-        let mut entries = vec![];
+    fn load_problems(
+        &self,
+        suite_paths: &Vec<PathBuf>,
+    ) -> Vec<(String, String, PathBuf, core::Problem)> {
+        let mut problems = vec![];
 
         for suite_path in suite_paths {
             let suite_name = suite_path.file_name().unwrap().to_str().unwrap();
 
             let lib_path = suite_path.join("_suite.hblib.toml");
-            let lib_string = std::fs::read_to_string(lib_path).unwrap();
-            let library = parse::library(&lib_string).unwrap();
+            let (library, _) = parse::library_file(&lib_path).unwrap();
 
             for prog_path in
                 glob::glob(suite_path.join("*.hb.toml").to_str().unwrap())
@@ -122,44 +258,61 @@ impl Runner {
 
                 typecheck::problem(&problem).unwrap();
 
-                let mut solutions = vec![("<ANY>".to_owned(), None)];
+                problems.push((
+                    suite_name.to_owned(),
+                    entry_name,
+                    prog_path_noext,
+                    problem,
+                ));
+            }
+        }
+
+        problems
+    }
 
-                for (i, solution_path) in
-                    glob::glob(prog_path_noext.join("*.json").to_str().unwrap())
-                        .unwrap()
-                        .filter_map(Result::ok)
-                        .enumerate()
-                {
-                    if i >= self.config.particular_solution_limit {
-                        break;
-                    }
+    fn load_entries(&self, suite_paths: &Vec<PathBuf>) -> Vec<Entry> {
+        let mut entries = vec![];
+
+        for (suite_name, entry_name, prog_path_noext, problem) in
+            self.load_problems(suite_paths)
+        {
+            let mut solutions = vec![("<ANY>".to_owned(), None)];
 
-                    let solution_name = solution_path
-                        .file_name()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_owned();
-                    let solution_string =
-                        std::fs::read_to_string(&solution_path).unwrap();
-                    let solution = parse::exp(&solution_string).unwrap();
-
-                    solutions.push((solution_name, Some(solution)));
+            for (i, solution_path) in
+                glob::glob(prog_path_noext.join("*.json").to_str().unwrap())
+                    .unwrap()
+                    .filter_map(Result::ok)
+                    .enumerate()
+            {
+                if i >= self.config.particular_solution_limit {
+                    break;
                 }
 
-                for (solution_name, solution) in solutions {
-                    for algorithm in &self.config.algorithms {
-                        for replicate in 0..self.config.replicates {
-                            entries.push(Entry {
-                                suite_name: suite_name.to_owned(),
-                                entry_name: entry_name.clone(),
-                                solution_name: solution_name.to_owned(),
-                                algorithm: algorithm.clone(),
-                                replicate,
-                                problem: problem.clone(),
-                                solution: solution.clone(),
-                            });
-                        }
+                let solution_name = solution_path
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_owned();
+                let solution_string =
+                    std::fs::read_to_string(&solution_path).unwrap();
+                let solution = parse::exp(&solution_string).unwrap();
+
+                solutions.push((solution_name, Some(solution)));
+            }
+
+            for (solution_name, solution) in solutions {
+                for algorithm in &self.config.algorithms {
+                    for replicate in 0..self.config.replicates {
+                        entries.push(Entry {
+                            suite_name: suite_name.clone(),
+                            entry_name: entry_name.clone(),
+                            solution_name: solution_name.to_owned(),
+                            algorithm: algorithm.clone(),
+                            replicate,
+                            problem: problem.clone(),
+                            solution: solution.clone(),
+                        });
                     }
                 }
             }
@@ -175,7 +328,7 @@ impl Runner {
         solution: core::Exp,
     ) -> Result<bool, EarlyCutoff> {
         let timer = Timer::finite(self.config.timeout);
-        let mut controller = algorithm.controller(timer, problem, false);
+        let mut controller = algorithm.controller(timer, problem, false)?;
 
         loop {
             if *controller.working_expression() == solution {
@@ -204,8 +357,8 @@ impl Runner {
         problem: core::Problem,
     ) -> Result<bool, EarlyCutoff> {
         let timer = Timer::finite(self.config.timeout);
-        let start = top_down::Sketch::blank();
-        let mut synth = algorithm.any_synthesizer(problem);
+        let start = problem.start();
+        let mut synth = algorithm.any_synthesizer(problem)?;
         Ok(synth.provide_any(&timer, &start)?.is_some())
     }
 
@@ -248,4 +401,163 @@ impl Runner {
             entries.into_iter().for_each(|e| self.entry(e));
         }
     }
+
+    /// Explores the sketches reachable from the start sketch breadth-first,
+    /// counting the explored sketches in `sketches`.
+    fn differential_explore<Eng: Engine>(
+        engine: Eng,
+        problem: core::Problem,
+        sketch_limit: usize,
+        sketches: &mut usize,
+    ) -> Result<(), EarlyCutoff> {
+        let goals = problem.program.goals.clone();
+        let start = problem.start();
+        let mut oracle = dl_oracle::Oracle::new(engine, problem)?;
+        let timer = Timer::infinite();
+
+        for goal in &goals {
+            oracle.valid_goal_metadata(goal)?;
+            oracle.goal_solvable(goal)?;
+        }
+
+        let mut frontier = VecDeque::from([start]);
+        while let Some(e) = frontier.pop_front() {
+            *sketches += 1;
+
+            let expansions = oracle.expansions(&timer, &e)?;
+            if expansions.is_empty() && !e.ground() {
+                oracle.explain(&e)?;
+            }
+
+            for (h, f) in expansions {
+                if *sketches == 1 {
+                    oracle.witness(&f)?;
+                }
+                if *sketches + frontier.len() < sketch_limit {
+                    frontier.push_back(
+                        e.substitute(h, &top_down::Sketch::free(&e, &f)),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn differential_entry(
+        &self,
+        suite_name: String,
+        entry_name: String,
+        problem: core::Problem,
+        sketch_limit: usize,
+    ) -> bool {
+        let queries = Rc::new(Cell::new(0));
+        let mismatches = Rc::new(RefCell::new(vec![]));
+        let durations = Rc::new(Cell::new((Duration::ZERO, Duration::ZERO)));
+
+        let engine = Differential {
+            a: egglog::Egglog::new(true),
+            b: seminaive::SemiNaive::new(),
+            queries: Rc::clone(&queries),
+            mismatches: Rc::clone(&mismatches),
+            durations: Rc::clone(&durations),
+        };
+
+        let mut sketches = 0;
+        let error = match Self::differential_explore(
+            engine,
+            problem,
+            sketch_limit,
+            &mut sketches,
+        ) {
+            Ok(()) => String::new(),
+            Err(e) => e.to_string(),
+        };
+
+        let mismatches = mismatches.borrow();
+        let (egglog_duration, seminaive_duration) = durations.get();
+
+        let r = DifferentialResult {
+            suite_name,
+            entry_name,
+            sketches,
+            queries: queries.get(),
+            mismatches: mismatches.len(),
+            first_mismatch: mismatches.first().cloned().unwrap_or_default(),
+            error,
+            egglog_duration: egglog_duration.as_millis(),
+            seminaive_duration: seminaive_duration.as_millis(),
+        };
+
+        let agree = r.mismatches == 0 && r.error.is_empty();
+
+        let wtr = Arc::clone(&self.wtr);
+        let mut wtr = wtr.lock().unwrap();
+        wtr.serialize(r).unwrap();
+        wtr.flush().unwrap();
+
+        agree
+    }
+
+    /// Check that the egglog and semi-naive Datalog engines agree on every
+    /// oracle query made while exploring (at most `sketch_limit` sketches of)
+    /// each entry of a set of benchmark suites, returning the number of
+    /// entries on which they disagree (or fail)
+    pub fn differential(
+        &self,
+        suite_paths: &Vec<PathBuf>,
+        sketch_limit: usize,
+    ) -> usize {
+        let problems = self.load_problems(suite_paths);
+
+        let run = |(suite_name, entry_name, _, problem)| {
+            self.differential_entry(
+                suite_name,
+                entry_name,
+                problem,
+                sketch_limit,
+            )
+        };
+
+        if self.config.parallel {
+            problems
+                .into_par_iter()
+                .map(run)
+                .filter(|agree| !agree)
+                .count()
+        } else {
+            problems.into_iter().map(run).filter(|agree| !agree).count()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn differential(suite: &str) -> usize {
+        let config = Config {
+            replicates: 1,
+            timeout: Duration::MAX,
+            entry_filter: String::new(),
+            parallel: true,
+            algorithms: vec![],
+            particular_solution_limit: 0,
+        };
+        let runner = Runner::new(config, io::sink());
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../pldi25/benchmark/suites")
+            .join(suite);
+        runner.differential(&vec![path], 20)
+    }
+
+    #[test]
+    fn engines_agree_on_finite_suite() {
+        assert_eq!(differential("fin"), 0);
+    }
+
+    #[test]
+    fn engines_agree_on_infinite_suite() {
+        assert_eq!(differential("inf"), 0);
+    }
 }
@@ -8,6 +8,7 @@
 
 use crate::core::*;
 use crate::top_down;
+use crate::util;
 
 use convert_case::Casing;
 use indexmap::{IndexMap, IndexSet};
@@ -22,6 +23,8 @@ use std::collections::HashMap;
 pub struct MetadataChoice {
     pub metadata: IndexMap<String, Value>,
     pub choice_index: usize,
+    pub cost: Option<f64>,
+    pub completions: Option<util::Count>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +34,8 @@ pub struct FunctionChoice {
     pub code: Option<String>,
     pub metadata_choices: Vec<MetadataChoice>,
     pub info: Option<toml::Table>,
+    pub cost: Option<f64>,
+    pub completions: Option<util::Count>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +49,11 @@ pub enum Cell {
         has_path: bool,
         priority: usize,
         number_id: Option<String>,
+        /// The path of the function application that the cell computes in the
+        /// working expression (if any), as used to retract it; for a shared
+        /// sub-expression, this is the path of its first occurrence, so
+        /// retracting it un-shares it (see [`top_down::Sketch::retract`])
+        exp_path: Option<Vec<String>>,
     },
     Hole {
         var_name: String,
@@ -85,7 +95,16 @@ pub fn python_value(v: &Value) -> String {
         Value::Bool(true) => "True".to_owned(),
         Value::Bool(false) => "False".to_owned(),
         Value::Int(i) => i.to_string(),
-        Value::Str(s) => format!("\"{}\"", s).to_owned(),
+        Value::Float(x) => {
+            if x.is_nan() {
+                "float(\"nan\")".to_owned()
+            } else if x.is_infinite() {
+                format!("float(\"{}inf\")", if *x < 0.0 { "-" } else { "" })
+            } else {
+                format!("{:?}", x)
+            }
+        }
+        Value::Str(s) => util::quote(s),
     }
 }
 
@@ -115,6 +134,14 @@ fn make_var_name(s: &str) -> String {
     return s.to_case(convert_case::Case::Constant);
 }
 
+/// The Python name of a (possibly) qualified name.
+///
+/// The namespace is mangled into the name (`qc.Reads` becomes `qc_Reads`) so
+/// that names imported from different libraries do not collide.
+fn python_name(name: &str) -> String {
+    name.replace('.', "_")
+}
+
 fn make_code_preview(code: &str) -> String {
     return bashify(code);
 }
@@ -129,10 +156,19 @@ struct Context<'a> {
     used_types: IndexSet<MetName>,
     used_functions: IndexSet<BaseFunction>,
     paths: HashMap<String, String>,
+    computed: Vec<(Exp, String)>,
     erase_static: bool,
 }
 
 impl<'a> Context<'a> {
+    /// The variable that an (already computed) expression is stored in
+    fn computed_var(&self, e: &Exp) -> Option<String> {
+        self.computed
+            .iter()
+            .find(|(e2, _)| e2 == e)
+            .map(|(_, var_name)| var_name.clone())
+    }
+
     fn fresh_var(&mut self, prefix: &str) -> String {
         let c = self.fresh_counter.entry(prefix.to_owned()).or_insert(1);
         let s = format!(
@@ -250,7 +286,12 @@ impl<'a> Context<'a> {
         s.trim().to_owned()
     }
 
-    fn exp(&mut self, var_name: &str, e: &Exp) {
+    fn exp(
+        &mut self,
+        var_name: &str,
+        exp_path: Vec<top_down::FunParam>,
+        e: &Exp,
+    ) {
         match e {
             top_down::Sketch::Hole(h) => {
                 self.cells.push(Cell::Hole {
@@ -268,14 +309,20 @@ impl<'a> Context<'a> {
 
                 let mut arg_strings = vec![];
                 for (fp, arg) in args {
+                    if let Some(arg_var) = self.computed_var(arg) {
+                        arg_strings.push((fp.0.clone(), arg_var));
+                        continue;
+                    }
                     let mn = f_sig.params.get(fp).unwrap().clone();
                     let arg_sig = self.library.types.get(&mn).unwrap();
                     let arg_var = self.fresh_var(
                         &arg_sig
                             .info_string("var_name")
-                            .unwrap_or(make_var_name(&mn.0)),
+                            .unwrap_or(make_var_name(&python_name(&mn.0))),
                     );
-                    self.exp(&arg_var, arg);
+                    let mut arg_path = exp_path.clone();
+                    arg_path.push(fp.clone());
+                    self.exp(&arg_var, arg_path, arg);
                     arg_strings.push((fp.0.clone(), arg_var));
                 }
 
@@ -291,7 +338,7 @@ impl<'a> Context<'a> {
 
                 let path_prefix = format!("output/{}-", number_id);
 
-                let function_name = &f.name.0;
+                let function_name = python_name(&f.name.0);
 
                 let path = format!("{}{}", path_prefix, function_name);
 
@@ -321,7 +368,7 @@ impl<'a> Context<'a> {
                     description: Self::description(f_sig),
                     code: Self::body_code(
                         var_name,
-                        &f_sig.ret.0,
+                        &python_name(&f_sig.ret.0),
                         &f.metadata
                             .iter()
                             .map(|(mp, v)| (mp.0.clone(), python_value(v)))
@@ -333,13 +380,49 @@ impl<'a> Context<'a> {
                     ),
                     open_when_editing: true,
                     open_when_exporting: true,
+                    exp_path: Some(
+                        exp_path.into_iter().map(|fp| fp.0).collect(),
+                    ),
                 });
 
                 self.paths.insert(var_name.to_owned(), path);
+                self.computed.push((e.clone(), var_name.to_owned()));
             }
         }
     }
 
+    /// Binds a goal to the variable of an expression that is already computed
+    /// (as part of an earlier goal)
+    fn alias(&mut self, var_name: &str, computed: &str, e: &Exp) {
+        let title = match e {
+            top_down::Sketch::App(f, _) => self
+                .library
+                .functions
+                .get(&f.name)
+                .unwrap()
+                .info_string("title")
+                .unwrap_or(f.name.0.clone()),
+            top_down::Sketch::Hole(_) => computed.to_owned(),
+        };
+
+        self.cells.push(Cell::Code {
+            title,
+            description: "".to_owned(),
+            code: format!("{} = {}", var_name, computed),
+            open_when_editing: true,
+            open_when_exporting: true,
+            has_path: false,
+            // After every computation (so after the aliased one)
+            priority: 3,
+            number_id: None,
+            exp_path: None,
+        });
+
+        if let Some(path) = self.paths.get(computed).cloned() {
+            self.paths.insert(var_name.to_owned(), path);
+        }
+    }
+
     fn preamble(&mut self) {
         // Hyperparameters
 
@@ -400,6 +483,7 @@ impl<'a> Context<'a> {
                 has_path: false,
                 priority: 0,
                 number_id: None,
+                exp_path: None,
             },
         );
 
@@ -424,9 +508,15 @@ impl<'a> Context<'a> {
             if self.erase_static && !is_input(&self.library, t) {
                 continue;
             }
-            match self.library.types.get(t).unwrap().info_string("code") {
-                Some(type_code) => pr_code += &format!("{}\n\n", type_code),
-                None => (),
+            if let Some(type_code) =
+                self.library.types.get(t).unwrap().info_string("code")
+            {
+                pr_code += &format!("{}\n\n", type_code);
+                // The type code refers to the type by its local name
+                if t.local() != t.0 {
+                    pr_code +=
+                        &format!("{} = {}\n\n", python_name(&t.0), t.local());
+                }
             }
         }
 
@@ -441,20 +531,38 @@ impl<'a> Context<'a> {
                 has_path: false,
                 priority: 0,
                 number_id: None,
+                exp_path: None,
             },
         );
     }
 }
 
+/// Whether a type is an input type (that is, whether there is a `P_`-prefixed
+/// prop for it in the same namespace)
 fn is_input(library: &Library, mn: &MetName) -> bool {
-    library.props.contains_key(&MetName(format!("P_{}", mn.0)))
+    let namespace = &mn.0[..mn.0.len() - mn.local().len()];
+    library.props.contains_key(&MetName(format!(
+        "{}P_{}",
+        namespace,
+        mn.local()
+    )))
 }
 
 fn get_erase_static(library: &Library) -> Option<bool> {
     library.config.as_ref()?.get("erase_static")?.as_bool()
 }
 
+/// Generate the cells for a working expression.
+///
+/// If the expression has multiple goals, the cells for all of them are emitted
+/// in one notebook (as `GOAL`, `GOAL2`, ...), and every sub-expression that
+/// the goals share is computed only once (a goal that is computed as part of
+/// an earlier goal is bound to the variable it is already stored in).
 pub fn exp(library: &Library, e: &Exp) -> Vec<Cell> {
+    cells(generate(library, e))
+}
+
+fn generate<'a>(library: &'a Library, e: &Exp) -> Context<'a> {
     let mut ctx = Context {
         library,
         cells: vec![],
@@ -462,12 +570,26 @@ pub fn exp(library: &Library, e: &Exp) -> Vec<Cell> {
         used_types: IndexSet::new(),
         used_functions: IndexSet::new(),
         paths: HashMap::new(),
+        computed: vec![],
         erase_static: get_erase_static(library) == Some(true),
     };
 
-    ctx.exp("GOAL", e);
+    for (i, (path, root)) in Goal::roots(e).into_iter().enumerate() {
+        let var_name = if i == 0 {
+            "GOAL".to_owned()
+        } else {
+            format!("GOAL{}", i + 1)
+        };
+        match ctx.computed_var(root) {
+            Some(computed) => ctx.alias(&var_name, &computed, root),
+            None => ctx.exp(&var_name, path, root),
+        }
+    }
     ctx.preamble();
+    ctx
+}
 
+fn cells(ctx: Context) -> Vec<Cell> {
     let mut cells = ctx.cells;
 
     for cell in &mut cells {
@@ -516,9 +638,22 @@ fn post_process(
 ////////////////////////////////////////////////////////////////////////////////
 // Cell filling
 
+/// Orders options by cost (options without a cost come last).
+fn cmp_cost(c1: Option<f64>, c2: Option<f64>) -> std::cmp::Ordering {
+    match (c1, c2) {
+        (Some(c1), Some(c2)) => c1.total_cmp(&c2),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }
+}
+
 fn collate_choices(
     lib: &Library,
+    computed: &[(Exp, String)],
     choices: &Vec<top_down::TopDownStep<ParameterizedFunction>>,
+    costs: &[Option<f64>],
+    counts: &[Option<util::Count>],
 ) -> Result<
     HashMap<top_down::HoleName, (String, Option<String>, Vec<FunctionChoice>)>,
     String,
@@ -529,48 +664,97 @@ fn collate_choices(
     > = HashMap::new();
 
     for (choice_index, choice) in choices.iter().enumerate() {
-        match choice {
-            top_down::TopDownStep::Extend(h, f, _args) => {
-                let f_sig = lib.functions.get(&f.name).unwrap();
-                let ret_sig = lib.types.get(&f_sig.ret).unwrap();
-
-                let type_title =
-                    ret_sig.info_string("title").unwrap_or(f_sig.ret.0.clone());
-                let type_description = ret_sig.info_string("description");
-
-                let function_title =
-                    f_sig.info_string("title").unwrap_or(f.name.0.clone());
-                let function_description = f_sig.info_string("description");
-
-                let (_, _, fc_map) = ret.entry(*h).or_insert_with(|| {
-                    (type_title, type_description, HashMap::new())
-                });
-
-                let fc = fc_map.entry(f.name.0.clone()).or_insert_with(|| {
-                    FunctionChoice {
-                        function_title,
-                        function_description,
-                        code: f_sig
-                            .info_string("code")
-                            .map(|s| make_code_preview(&s)),
-                        metadata_choices: vec![],
-                        info: f_sig.info.clone(),
-                    }
-                });
-
-                fc.metadata_choices.push(MetadataChoice {
-                    metadata: f
-                        .metadata
+        let (h, f, reused) = match choice {
+            top_down::TopDownStep::Extend(h, f, _args) => (h, f, None),
+            top_down::TopDownStep::Reuse(
+                h,
+                e @ top_down::Sketch::App(f, _),
+            ) => (
+                h,
+                f,
+                Some(
+                    computed
                         .iter()
-                        .map(|(mp, v)| (mp.0.clone(), v.clone()))
-                        .collect(),
-                    choice_index,
-                });
+                        .find(|(e2, _)| e2 == e)
+                        .map(|(_, var_name)| var_name.clone())
+                        .ok_or("Reused expressions must be computed")?,
+                ),
+            ),
+            top_down::TopDownStep::Reuse(_, top_down::Sketch::Hole(_)) => {
+                return Err("Reused expressions must be applications".to_owned())
+            }
+            top_down::TopDownStep::Retract(..) => {
+                return Err("Retraction steps unsupported".to_owned())
             }
+            // Attaching steps are only shown in the terminal interface
+            top_down::TopDownStep::Wrap(..) => continue,
             top_down::TopDownStep::Seq(..) => {
                 return Err("Sequenced steps unsupported".to_owned())
             }
+        };
+
+        let f_sig = lib.functions.get(&f.name).unwrap();
+        let ret_sig = lib.types.get(&f_sig.ret).unwrap();
+
+        let type_title =
+            ret_sig.info_string("title").unwrap_or(f_sig.ret.0.clone());
+        let type_description = ret_sig.info_string("description");
+
+        let function_title =
+            f_sig.info_string("title").unwrap_or(f.name.0.clone());
+        let function_description = f_sig.info_string("description");
+
+        let (_, _, fc_map) = ret
+            .entry(*h)
+            .or_insert_with(|| (type_title, type_description, HashMap::new()));
+
+        // Reusing an already-computed value runs no new code, and there is
+        // one choice per reused value (named by the variable it is stored in)
+        let key = match &reused {
+            Some(var_name) => format!("&reuse {}", var_name),
+            None => f.name.0.clone(),
+        };
+
+        let fc = fc_map.entry(key).or_insert_with(|| FunctionChoice {
+            function_title: match &reused {
+                Some(var_name) => {
+                    format!("Reuse: {} ({})", function_title, var_name)
+                }
+                None => function_title,
+            },
+            function_description,
+            code: match &reused {
+                Some(_) => None,
+                None => {
+                    f_sig.info_string("code").map(|s| make_code_preview(&s))
+                }
+            },
+            metadata_choices: vec![],
+            info: f_sig.info.clone(),
+            cost: None,
+            completions: None,
+        });
+
+        let cost = costs.get(choice_index).cloned().flatten();
+        if cmp_cost(cost, fc.cost).is_lt() {
+            fc.cost = cost;
         }
+
+        let completions = counts.get(choice_index).cloned().flatten();
+        if let Some(c) = completions {
+            fc.completions = Some(fc.completions.map_or(c, |total| total + c));
+        }
+
+        fc.metadata_choices.push(MetadataChoice {
+            metadata: f
+                .metadata
+                .iter()
+                .map(|(mp, v)| (mp.0.clone(), v.clone()))
+                .collect(),
+            choice_index,
+            cost,
+            completions,
+        });
     }
 
     Ok(ret
@@ -580,10 +764,16 @@ fn collate_choices(
                 h,
                 (t, d, {
                     let mut v = fmap.into_values().collect::<Vec<_>>();
+                    for fc in &mut v {
+                        fc.metadata_choices
+                            .sort_by(|mc1, mc2| cmp_cost(mc1.cost, mc2.cost));
+                    }
                     v.sort_by(|fc1, fc2| {
-                        fc1.function_title
-                            .to_lowercase()
-                            .cmp(&fc2.function_title.to_lowercase())
+                        cmp_cost(fc1.cost, fc2.cost).then_with(|| {
+                            fc1.function_title
+                                .to_lowercase()
+                                .cmp(&fc2.function_title.to_lowercase())
+                        })
                     });
                     v
                 }),
@@ -592,12 +782,28 @@ fn collate_choices(
         .collect::<HashMap<_, _>>())
 }
 
+/// Generates the cells for a working expression with the hole cells filled
+/// with the choices for their holes, ranked by the minimal total cost to
+/// complete them and annotated with the number of ways to complete them
+/// (`costs` and `counts` are parallel to `choices`).
 pub fn fill(
     lib: &Library,
+    e: &Exp,
     choices: &Vec<top_down::TopDownStep<ParameterizedFunction>>,
-    mut cells: Vec<Cell>,
+    costs: &[Option<f64>],
+    counts: &[Option<util::Count>],
 ) -> Result<Vec<Cell>, String> {
-    let mut collated_choices = collate_choices(lib, choices)?;
+    let ctx = generate(lib, e);
+    let computed: Vec<_> = ctx
+        .computed
+        .iter()
+        .map(|(e, var_name)| {
+            (e.clone(), post_process(&HashMap::new(), var_name, false))
+        })
+        .collect();
+    let mut cells = cells(ctx);
+    let mut collated_choices =
+        collate_choices(lib, &computed, choices, costs, counts)?;
     for cell in &mut cells {
         match cell {
             Cell::Hole {
@@ -627,3 +833,379 @@ pub fn fill(
     }
     Ok(cells)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menu;
+    use crate::parse;
+
+    const BIO: &str = r#"
+        [[Preamble]]
+        content = "def already_exists(path):\n    return False"
+
+        [Prop.P_SeqReads]
+        params.sample = "Str"
+
+        [Type.SeqReads]
+        params.sample = "Str"
+        info.code = "class SeqReads:\n    def __init__(self, sample):\n        self.sample = sample"
+
+        [Type.Report]
+        params = {}
+        info.code = "class Report:\n    def __init__(self, path):\n        self.path = path"
+
+        [Function.F_SeqReads]
+        params = {}
+        ret = "SeqReads"
+        condition = ["P_SeqReads { sample = ret.sample }"]
+
+        [Function.report]
+        params.reads = "SeqReads"
+        ret = "Report"
+        condition = []
+        info.code = "print(__hb_reads.sample)"
+    "#;
+
+    fn notebook(lib: &str, prog: &str) -> (String, Vec<String>) {
+        let (library, _) = parse::library_with_imports(
+            lib,
+            &IndexMap::from([(
+                "libs/bio.hblib.toml".to_owned(),
+                BIO.to_owned(),
+            )]),
+        )
+        .unwrap();
+        let program = parse::program(prog).unwrap();
+        let problem = Problem { library, program };
+        crate::typecheck::problem(&problem).unwrap();
+
+        let start = problem.start();
+        let hf = menu::Algorithm::PBNHoneybee
+            .any_synthesizer(problem.clone())
+            .unwrap()
+            .provide_any(&util::Timer::infinite(), &start)
+            .unwrap()
+            .unwrap();
+        let mut e = start;
+        for (h, rhs) in hf {
+            e = e.substitute(h, &rhs);
+        }
+
+        let cells = exp(&problem.library, &e);
+        let mut code = String::new();
+        let mut titles = vec![];
+        for cell in &cells {
+            match cell {
+                Cell::Code { title, code: c, .. } => {
+                    titles.push(title.clone());
+                    code += c;
+                    code += "\n";
+                }
+                _ => panic!("unexpected cell {:?}", cell),
+            }
+        }
+        (code, titles)
+    }
+
+    #[test]
+    fn namespaced_import_notebook() {
+        let (code, titles) = notebook(
+            r#"
+            [[Import]]
+            path = "libs/bio.hblib.toml"
+            namespace = "bio"
+            "#,
+            r#"
+            [[Prop]]
+            name = "bio.P_SeqReads"
+            args.sample = "s1"
+
+            [Goal]
+            name = "bio.Report"
+            "#,
+        );
+
+        // The type code defines the local names, which the generated code
+        // refers to with the namespace mangled in
+        assert!(!code.contains("bio."), "{}", code);
+        assert!(code.contains("def already_exists(path)"));
+        assert!(code.contains("class SeqReads:"));
+        assert!(code.contains("bio_SeqReads = SeqReads\n"));
+        assert!(code.contains("class Report:"));
+        assert!(code.contains("bio_Report = Report\n"));
+        assert!(code
+            .contains("BIO_SEQ_READS = bio_SeqReads(\n    sample=\"s1\",\n)"));
+        assert!(code.contains("GOAL = bio_Report(\n    path=\"output/"));
+        assert!(code.contains("-bio_report\","));
+        assert!(code.contains("print(BIO_SEQ_READS.sample)"));
+
+        // ... and namespaced input types are still recognized as inputs
+        assert!(titles.contains(&"Input: bio.F_SeqReads".to_owned()));
+    }
+
+    #[test]
+    fn colliding_imports_notebook() {
+        let (code, _) = notebook(
+            r#"
+            [[Import]]
+            path = "libs/bio.hblib.toml"
+            namespace = "qc"
+
+            [[Import]]
+            path = "libs/bio.hblib.toml"
+            namespace = "aln"
+
+            [Type.Comparison]
+            params = {}
+
+            [Function.compare]
+            params.a = "qc.SeqReads"
+            params.b = "aln.SeqReads"
+            ret = "Comparison"
+            condition = []
+            "#,
+            r#"
+            [[Prop]]
+            name = "qc.P_SeqReads"
+            args.sample = "s1"
+
+            [[Prop]]
+            name = "aln.P_SeqReads"
+            args.sample = "s2"
+
+            [Goal]
+            name = "Comparison"
+            "#,
+        );
+
+        // Each import's class is bound to its own mangled name right after it
+        // is defined, so the two `SeqReads` classes do not collide
+        assert_eq!(code.matches("class SeqReads:").count(), 2);
+        for ns in ["qc", "aln"] {
+            assert!(code.contains(&format!(
+                "self.sample = sample\n\n{}_SeqReads = SeqReads\n",
+                ns
+            )));
+        }
+        assert!(
+            code.contains("QC_SEQ_READS = qc_SeqReads(\n    sample=\"s1\",\n)")
+        );
+        assert!(code
+            .contains("ALN_SEQ_READS = aln_SeqReads(\n    sample=\"s2\",\n)"));
+    }
+
+    const SHARED: &str = r#"
+        [[Prop]]
+        name = "P_SeqReads"
+        args.sample = "s1"
+    "#;
+
+    #[test]
+    fn multiple_goals_share_computation() {
+        let (code, _) = notebook(
+            BIO,
+            &format!(
+                r#"
+                {}
+
+                [[Goal]]
+                name = "Report"
+
+                [[Goal]]
+                name = "SeqReads"
+                args.sample = "s1"
+                "#,
+                SHARED
+            ),
+        );
+
+        // The reads are computed once, for the report, and the second goal
+        // refers to them (after they are computed)
+        assert_eq!(code.matches("= SeqReads(").count(), 1, "{}", code);
+        assert!(code.contains("GOAL = Report(\n    path=\"output/"));
+        assert!(code.contains("print(SEQ_READS.sample)"));
+        let alias = code.find("GOAL2 = SEQ_READS\n").expect(&code);
+        assert!(code.find("SEQ_READS = SeqReads(").unwrap() < alias);
+    }
+
+    #[test]
+    fn identical_goals_are_computed_once() {
+        let (code, titles) = notebook(
+            BIO,
+            &format!(
+                r#"
+                {}
+
+                [[Goal]]
+                name = "Report"
+
+                [[Goal]]
+                name = "Report"
+                "#,
+                SHARED
+            ),
+        );
+
+        assert_eq!(code.matches("= Report(").count(), 1, "{}", code);
+        assert_eq!(code.matches("print(SEQ_READS.sample)").count(), 1);
+        assert!(code.ends_with("GOAL2 = GOAL\n"), "{}", code);
+        assert_eq!(titles.last().unwrap(), "report");
+    }
+
+    #[test]
+    fn goals_are_synthesized_together() {
+        let solve = |sample: &str| {
+            let library = parse::library(BIO).unwrap();
+            let program = parse::program(&format!(
+                r#"
+                {}
+
+                [[Goal]]
+                name = "Report"
+
+                [[Goal]]
+                name = "SeqReads"
+                args.sample = "{}"
+                "#,
+                SHARED, sample
+            ))
+            .unwrap();
+            let problem = Problem { library, program };
+            let start = problem.start();
+            menu::Algorithm::PBNHoneybee
+                .any_synthesizer(problem)
+                .unwrap()
+                .provide_any(&util::Timer::infinite(), &start)
+                .unwrap()
+        };
+
+        // The pipeline fills both goals, so it exists only if each goal is
+        // solvable
+        assert!(solve("s1").is_some());
+        assert!(solve("s2").is_none());
+    }
+
+    const TRIMMING: &str = r#"
+        [Prop.P_SeqReads]
+        params.sample = "Str"
+
+        [Type.SeqReads]
+        params.sample = "Str"
+
+        [Type.Trimmed]
+        params.sample = "Str"
+
+        [Type.Comparison]
+        params = {}
+
+        [Function.F_SeqReads]
+        params = {}
+        ret = "SeqReads"
+        condition = ["P_SeqReads { sample = ret.sample }"]
+
+        [Function.trim]
+        params.reads = "SeqReads"
+        ret = "Trimmed"
+        condition = ["ret.sample = reads.sample"]
+
+        [Function.compare]
+        params.a = "Trimmed"
+        params.b = "Trimmed"
+        params.c = "Trimmed"
+        ret = "Comparison"
+        condition = []
+    "#;
+
+    /// Takes the extension step with the given function (and sample, if any)
+    fn choose(controller: &mut menu::Controller, name: &str, sample: &str) {
+        let step = controller
+            .provide()
+            .unwrap()
+            .into_iter()
+            .find(|step| match step {
+                top_down::TopDownStep::Extend(_, f, _) => {
+                    f.name.0 == name
+                        && f.metadata
+                            .get(&MetParam("sample".to_owned()))
+                            .is_none_or(|v| *v == Value::Str(sample.to_owned()))
+                }
+                _ => false,
+            })
+            .unwrap();
+        controller.decide(step);
+    }
+
+    #[test]
+    fn reuse_choices_are_per_subexpression() {
+        let library = parse::library(TRIMMING).unwrap();
+        let program = parse::program(
+            r#"
+            [[Prop]]
+            name = "P_SeqReads"
+            args.sample = "s1"
+
+            [[Prop]]
+            name = "P_SeqReads"
+            args.sample = "s2"
+
+            [Goal]
+            name = "Comparison"
+            "#,
+        )
+        .unwrap();
+        let problem = Problem { library, program };
+
+        let mut controller = menu::Algorithm::PBNHoneybee
+            .controller(util::Timer::infinite(), problem.clone(), false)
+            .unwrap();
+        choose(&mut controller, "compare", "");
+        choose(&mut controller, "trim", "s1");
+        choose(&mut controller, "trim", "s2");
+        choose(&mut controller, "F_SeqReads", "s1");
+        choose(&mut controller, "F_SeqReads", "s2");
+
+        // Both trimmed reads can be reused for the last argument, each as its
+        // own choice named by the variable that it is stored in
+        let options = controller.provide().unwrap();
+        let e = controller.working_expression();
+        let cells = fill(&problem.library, e, &options, &[], &[]).unwrap();
+        let function_choices = cells
+            .into_iter()
+            .find_map(|c| match c {
+                Cell::Choice {
+                    function_choices, ..
+                } => Some(function_choices),
+                _ => None,
+            })
+            .unwrap();
+
+        let mut titles: Vec<_> = function_choices
+            .iter()
+            .map(|fc| fc.function_title.as_str())
+            .collect();
+        titles.sort();
+        assert_eq!(
+            titles,
+            vec!["Reuse: trim (TRIMMED)", "Reuse: trim (TRIMMED2)", "trim"]
+        );
+
+        for fc in &function_choices {
+            let var_name = match fc.function_title.strip_prefix("Reuse: trim ")
+            {
+                Some(var_name) => var_name,
+                None => continue,
+            };
+            assert_eq!(fc.metadata_choices.len(), 1);
+            let reused = match &options[fc.metadata_choices[0].choice_index] {
+                top_down::TopDownStep::Reuse(_, reused) => reused,
+                step => panic!("expected a reuse step, got {:?}", step),
+            };
+            let expected = if var_name == "(TRIMMED)" { "a" } else { "b" };
+            assert_eq!(
+                e.at(&[top_down::FunParam(expected.to_owned())]),
+                Some(reused)
+            );
+        }
+    }
+}
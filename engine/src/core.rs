@@ -3,6 +3,7 @@
 //! This module defines the core syntax for Honeybee.
 
 use crate::top_down::*;
+use crate::util;
 
 use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
@@ -11,22 +12,79 @@ use serde::{Deserialize, Serialize};
 // Values
 
 /// The types that values may take on.
+///
+/// Enum types are string types whose values are restricted to a declared
+/// list of strings.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ValueType {
     Bool,
     Int,
+    Float,
     Str,
+    Enum(Vec<String>),
+}
+
+impl ValueType {
+    /// Returns the set of values declared by a value type.
+    pub fn vals(&self) -> IndexSet<Value> {
+        match self {
+            ValueType::Bool
+            | ValueType::Int
+            | ValueType::Float
+            | ValueType::Str => IndexSet::new(),
+            ValueType::Enum(ss) => {
+                ss.iter().map(|s| Value::Str(s.clone())).collect()
+            }
+        }
+    }
 }
 
 /// The possible values.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+///
+/// Floats are compared (and hashed) by their bit patterns so that values can
+/// be used as keys in sets and maps (see [`util::float_bits`]); in particular,
+/// `-0.0` and `0.0` are equal, but NaNs are equal only to themselves.
+///
+/// Integer literals (such as `1`) are always parsed as `Int` values, so
+/// `Float` metadata must be written with a fractional part or an exponent
+/// (such as `1.0` or `1e0`).
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Value {
     Bool(bool),
     Int(i64),
+    Float(f64),
     Str(String),
 }
 
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Int(x1), Value::Int(x2)) => x1 == x2,
+            (Value::Float(x1), Value::Float(x2)) => {
+                util::float_bits(*x1) == util::float_bits(*x2)
+            }
+            (Value::Str(s1), Value::Str(s2)) => s1 == s2,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl std::hash::Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Bool(b) => b.hash(state),
+            Value::Int(x) => x.hash(state),
+            Value::Float(x) => util::float_bits(*x).hash(state),
+            Value::Str(s) => s.hash(state),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Types
 
@@ -37,6 +95,21 @@ pub enum Value {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct MetName(pub String);
 
+impl MetName {
+    /// The name without the namespace of the import it comes from (if any)
+    pub fn local(&self) -> &str {
+        local_name(&self.0)
+    }
+}
+
+/// Strip the namespace from a (possibly) qualified name.
+///
+/// Namespaces only disambiguate names within Honeybee; the code that a
+/// library provides refers to its types and functions by their local names.
+fn local_name(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(_, local)| local)
+}
+
 /// The type of metadata-indexed tuple parameter keys.
 ///
 /// Types, type signatures, and atomic proposition formulas contain maps indexed
@@ -56,6 +129,11 @@ pub struct MetSignature {
 }
 
 impl MetSignature {
+    /// Returns the set of values declared by a signature.
+    pub fn vals(&self) -> IndexSet<Value> {
+        self.params.values().flat_map(|vt| vt.vals()).collect()
+    }
+
     /// Retrieve info string
     pub fn info_string(&self, key: &str) -> Option<String> {
         match &self.info {
@@ -98,6 +176,8 @@ pub enum FormulaAtom {
     Param(FunParam, MetParam),
     Ret(MetParam),
     Lit(Value),
+    Add(Box<FormulaAtom>, Box<FormulaAtom>),
+    Sub(Box<FormulaAtom>, Box<FormulaAtom>),
 }
 
 impl FormulaAtom {
@@ -107,6 +187,84 @@ impl FormulaAtom {
             FormulaAtom::Param(_, _) => IndexSet::new(),
             FormulaAtom::Ret(_) => IndexSet::new(),
             FormulaAtom::Lit(v) => IndexSet::from([v.clone()]),
+            FormulaAtom::Add(fa1, fa2) | FormulaAtom::Sub(fa1, fa2) => {
+                let mut ret = fa1.vals();
+                ret.extend(fa2.vals());
+                ret
+            }
+        }
+    }
+
+    /// Returns the number of metadata parameter occurrences in a formula atom.
+    pub fn param_count(&self) -> usize {
+        match self {
+            FormulaAtom::Param(_, _) | FormulaAtom::Ret(_) => 1,
+            FormulaAtom::Lit(_) => 0,
+            FormulaAtom::Add(fa1, fa2) | FormulaAtom::Sub(fa1, fa2) => {
+                fa1.param_count() + fa2.param_count()
+            }
+        }
+    }
+
+    /// Applies the arithmetic operation of an `Add` or `Sub` formula atom to
+    /// two integers.
+    ///
+    /// Arithmetic is checked: an operation that overflows has no result
+    /// (which is also how the Datalog engines treat it).
+    pub fn arithmetic(&self, x1: i64, x2: i64) -> Option<i64> {
+        match self {
+            FormulaAtom::Add(_, _) => x1.checked_add(x2),
+            FormulaAtom::Sub(_, _) => x1.checked_sub(x2),
+            _ => panic!("not an arithmetic formula atom: {:?}", self),
+        }
+    }
+
+    /// Returns the integers that a formula atom may evaluate to, assuming that
+    /// all metadata parameters range over the provided integers.
+    ///
+    /// Each parameter occurrence ranges independently, so a term with `k`
+    /// parameter occurrences may evaluate to up to `|ints|^k` integers (the
+    /// typechecker bounds `k` by [`util::MAX_ARITHMETIC_PARAMS`]).
+    fn range(&self, ints: &IndexSet<i64>) -> IndexSet<i64> {
+        let (fa1, fa2) = match self {
+            FormulaAtom::Param(_, _) | FormulaAtom::Ret(_) => {
+                return ints.clone()
+            }
+            FormulaAtom::Lit(Value::Int(x)) => return IndexSet::from([*x]),
+            FormulaAtom::Lit(_) => return IndexSet::new(),
+            FormulaAtom::Add(fa1, fa2) | FormulaAtom::Sub(fa1, fa2) => {
+                (fa1, fa2)
+            }
+        };
+        let range2 = fa2.range(ints);
+        let mut ret = IndexSet::new();
+        for x1 in fa1.range(ints) {
+            ret.extend(range2.iter().filter_map(|x2| self.arithmetic(x1, *x2)));
+        }
+        ret
+    }
+
+    /// Returns the set of values that the arithmetic subterms of a formula
+    /// atom may evaluate to, relative to a domain.
+    pub fn arithmetic_vals(&self, dom: &IndexSet<Value>) -> IndexSet<Value> {
+        match self {
+            FormulaAtom::Param(_, _)
+            | FormulaAtom::Ret(_)
+            | FormulaAtom::Lit(_) => IndexSet::new(),
+            FormulaAtom::Add(fa1, fa2) | FormulaAtom::Sub(fa1, fa2) => {
+                let ints = dom
+                    .iter()
+                    .filter_map(|v| match v {
+                        Value::Int(x) => Some(*x),
+                        _ => None,
+                    })
+                    .collect();
+                let mut ret: IndexSet<Value> =
+                    self.range(&ints).into_iter().map(Value::Int).collect();
+                ret.extend(fa1.arithmetic_vals(dom));
+                ret.extend(fa2.arithmetic_vals(dom));
+                ret
+            }
         }
     }
 }
@@ -128,6 +286,16 @@ impl AtomicProposition {
         }
         ret
     }
+
+    /// Returns the set of values that the arithmetic in an atomic proposition
+    /// may evaluate to, relative to a domain.
+    pub fn arithmetic_vals(&self, dom: &IndexSet<Value>) -> IndexSet<Value> {
+        let mut ret = IndexSet::new();
+        for fa in self.args.values().flatten() {
+            ret.extend(fa.arithmetic_vals(dom));
+        }
+        ret
+    }
 }
 
 /// The type of formulas.
@@ -137,9 +305,14 @@ pub enum Formula {
     True,
     Eq(FormulaAtom, FormulaAtom),
     Lt(FormulaAtom, FormulaAtom),
+    Le(FormulaAtom, FormulaAtom),
+    Gt(FormulaAtom, FormulaAtom),
+    Ge(FormulaAtom, FormulaAtom),
     Neq(FormulaAtom, FormulaAtom),
     Ap(AtomicProposition),
     And(Box<Formula>, Box<Formula>),
+    Or(Box<Formula>, Box<Formula>),
+    Not(Box<Formula>),
 }
 
 impl Formula {
@@ -152,23 +325,148 @@ impl Formula {
         phi
     }
 
+    /// Returns the conditions that a formula is a conjunct of (see
+    /// [`Formula::conjunct`]).
+    pub fn conditions(&self) -> Vec<&Formula> {
+        match self {
+            Formula::True => vec![],
+            Formula::And(phi1, phi2) => {
+                let mut ret = phi1.conditions();
+                ret.push(phi2);
+                ret
+            }
+            _ => vec![self],
+        }
+    }
+
     /// Returns the set of values in a formula.
     pub fn vals(&self) -> IndexSet<Value> {
         match self {
             Formula::True => IndexSet::new(),
             Formula::Eq(fa1, fa2)
             | Formula::Lt(fa1, fa2)
+            | Formula::Le(fa1, fa2)
+            | Formula::Gt(fa1, fa2)
+            | Formula::Ge(fa1, fa2)
             | Formula::Neq(fa1, fa2) => {
                 let mut ret = fa1.vals();
                 ret.extend(fa2.vals());
                 ret
             }
             Formula::Ap(ap) => ap.vals(),
-            Formula::And(phi1, phi2) => {
+            Formula::And(phi1, phi2) | Formula::Or(phi1, phi2) => {
                 let mut ret = phi1.vals();
                 ret.extend(phi2.vals());
                 ret
             }
+            Formula::Not(phi) => phi.vals(),
+        }
+    }
+
+    /// Returns the set of values that the arithmetic in a formula may evaluate
+    /// to, relative to a domain.
+    pub fn arithmetic_vals(&self, dom: &IndexSet<Value>) -> IndexSet<Value> {
+        match self {
+            Formula::True => IndexSet::new(),
+            Formula::Eq(fa1, fa2)
+            | Formula::Lt(fa1, fa2)
+            | Formula::Le(fa1, fa2)
+            | Formula::Gt(fa1, fa2)
+            | Formula::Ge(fa1, fa2)
+            | Formula::Neq(fa1, fa2) => {
+                let mut ret = fa1.arithmetic_vals(dom);
+                ret.extend(fa2.arithmetic_vals(dom));
+                ret
+            }
+            Formula::Ap(ap) => ap.arithmetic_vals(dom),
+            Formula::And(phi1, phi2) | Formula::Or(phi1, phi2) => {
+                let mut ret = phi1.arithmetic_vals(dom);
+                ret.extend(phi2.arithmetic_vals(dom));
+                ret
+            }
+            Formula::Not(phi) => phi.arithmetic_vals(dom),
+        }
+    }
+
+    /// Add a prefix to the names of all atomic propositions in a formula.
+    pub fn prefix_names(&self, prefix: &str) -> Formula {
+        match self {
+            Formula::True
+            | Formula::Eq(_, _)
+            | Formula::Lt(_, _)
+            | Formula::Le(_, _)
+            | Formula::Gt(_, _)
+            | Formula::Ge(_, _)
+            | Formula::Neq(_, _) => self.clone(),
+            Formula::Ap(ap) => Formula::Ap(Met {
+                name: MetName(format!("{}{}", prefix, ap.name.0)),
+                args: ap.args.clone(),
+            }),
+            Formula::And(phi1, phi2) => Formula::And(
+                Box::new(phi1.prefix_names(prefix)),
+                Box::new(phi2.prefix_names(prefix)),
+            ),
+            Formula::Or(phi1, phi2) => Formula::Or(
+                Box::new(phi1.prefix_names(prefix)),
+                Box::new(phi2.prefix_names(prefix)),
+            ),
+            Formula::Not(phi) => {
+                Formula::Not(Box::new(phi.prefix_names(prefix)))
+            }
+        }
+    }
+
+    /// Returns the atomic propositions that occur in a formula.
+    pub fn propositions(&self) -> Vec<&AtomicProposition> {
+        match self {
+            Formula::True
+            | Formula::Eq(_, _)
+            | Formula::Lt(_, _)
+            | Formula::Le(_, _)
+            | Formula::Gt(_, _)
+            | Formula::Ge(_, _)
+            | Formula::Neq(_, _) => vec![],
+            Formula::Ap(ap) => vec![ap],
+            Formula::And(phi1, phi2) | Formula::Or(phi1, phi2) => {
+                let mut ret = phi1.propositions();
+                ret.extend(phi2.propositions());
+                ret
+            }
+            Formula::Not(phi) => phi.propositions(),
+        }
+    }
+
+    /// Returns the atomic propositions that occur negatively in a formula
+    /// (that is, under an odd number of negations).
+    pub fn negated_propositions(&self) -> Vec<&AtomicProposition> {
+        let mut ret = vec![];
+        self.negated_propositions_helper(false, &mut ret);
+        ret
+    }
+
+    fn negated_propositions_helper<'a>(
+        &'a self,
+        negated: bool,
+        acc: &mut Vec<&'a AtomicProposition>,
+    ) {
+        match self {
+            Formula::True
+            | Formula::Eq(_, _)
+            | Formula::Lt(_, _)
+            | Formula::Le(_, _)
+            | Formula::Gt(_, _)
+            | Formula::Ge(_, _)
+            | Formula::Neq(_, _) => (),
+            Formula::Ap(ap) => {
+                if negated {
+                    acc.push(ap)
+                }
+            }
+            Formula::And(phi1, phi2) | Formula::Or(phi1, phi2) => {
+                phi1.negated_propositions_helper(negated, acc);
+                phi2.negated_propositions_helper(negated, acc);
+            }
+            Formula::Not(phi) => phi.negated_propositions_helper(!negated, acc),
         }
     }
 }
@@ -180,6 +478,13 @@ impl Formula {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct BaseFunction(pub String);
 
+impl BaseFunction {
+    /// The name without the namespace of the import it comes from (if any)
+    pub fn local(&self) -> &str {
+        local_name(&self.0)
+    }
+}
+
 /// The type of signatures of parameterized functions.
 ///
 /// The condition formula refers to the metadata values on the parameter types
@@ -201,6 +506,12 @@ impl FunctionSignature {
         self.condition.vals()
     }
 
+    /// Returns the set of values that the arithmetic in a function signature
+    /// may evaluate to, relative to a domain.
+    pub fn arithmetic_vals(&self, dom: &IndexSet<Value>) -> IndexSet<Value> {
+        self.condition.arithmetic_vals(dom)
+    }
+
     /// Retrieve info string
     pub fn info_string(&self, key: &str) -> Option<String> {
         match &self.info {
@@ -222,6 +533,16 @@ impl FunctionSignature {
             None => None,
         }
     }
+
+    /// Retrieve the cost of using the function (declared as the info number
+    /// `cost`, defaulting to 1)
+    pub fn cost(&self) -> f64 {
+        match self.info.as_ref().and_then(|inf| inf.get("cost")) {
+            Some(toml::Value::Integer(n)) => *n as f64,
+            Some(toml::Value::Float(x)) => *x,
+            _ => 1.0,
+        }
+    }
 }
 
 impl PartialEq for FunctionSignature {
@@ -240,14 +561,29 @@ pub type FunctionLibrary = IndexMap<BaseFunction, FunctionSignature>;
 ////////////////////////////////////////////////////////////////////////////////
 // Composite libraries and programs
 
+/// The type of library imports.
+///
+/// The path of an import is relative to the importing library file. If a
+/// namespace is provided, all the names in the imported library are prefixed
+/// by the namespace (and a dot). The generated code still refers to imported
+/// types and functions by their local names (see [`MetName::local`]), since
+/// those are the names that the code of the imported library defines.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Import {
+    pub path: String,
+    pub namespace: Option<String>,
+}
+
 /// The libraries necessary for a Honeybee problem.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Library {
-    #[serde(rename = "Prop")]
+    #[serde(rename = "Import", default, skip_serializing)]
+    pub imports: Vec<Import>,
+    #[serde(rename = "Prop", default)]
     pub props: MetLibrary,
-    #[serde(rename = "Type")]
+    #[serde(rename = "Type", default)]
     pub types: MetLibrary,
-    #[serde(rename = "Function")]
+    #[serde(rename = "Function", default)]
     pub functions: FunctionLibrary,
     #[serde(rename = "Preamble")]
     pub preamble: Option<Vec<IndexMap<String, String>>>,
@@ -255,13 +591,208 @@ pub struct Library {
     pub config: Option<toml::Table>,
 }
 
+impl Library {
+    /// Returns the set of values in a library (including the values declared
+    /// by enum types).
+    pub fn vals(&self) -> IndexSet<Value> {
+        let mut dom = IndexSet::new();
+        for sig in self.props.values() {
+            dom.extend(sig.vals());
+        }
+        for sig in self.types.values() {
+            dom.extend(sig.vals());
+        }
+        for fs in self.functions.values() {
+            dom.extend(fs.vals());
+        }
+        dom
+    }
+
+    /// Returns the set of values in a library and a program, closed under the
+    /// arithmetic in the library up to the bounds of its integers.
+    ///
+    /// The values include those declared by enum types and those of the
+    /// program props and goals, as well as the values that the arithmetic in
+    /// the function conditions evaluates to (see
+    /// [`Library::extend_arithmetic`]). Since the Datalog oracle only
+    /// considers values in this domain, a chain of arithmetic (such as
+    /// `ret.n = x.n + 1` applied repeatedly) is derivable as long as each of
+    /// its intermediate results lies between the smallest and largest integer
+    /// literals of the library and program.
+    pub fn program_vals(&self, program: &Program) -> IndexSet<Value> {
+        let mut dom = self.vals();
+        for p in &program.props {
+            dom.extend(p.args.values().cloned());
+        }
+        for goal in &program.goals {
+            dom.extend(goal.fixed().into_values());
+        }
+        self.extend_arithmetic(&mut dom);
+        dom
+    }
+
+    /// Extends a domain with the values that the arithmetic in the library
+    /// evaluates to when applied any number of times to the values of the
+    /// domain, keeping only the integers between the smallest and largest
+    /// integer already in the domain.
+    ///
+    /// At most [`util::MAX_ARITHMETIC_VALS`] integers are added, so the domain
+    /// remains small enough for the Datalog engines even when the bounds are
+    /// far apart.
+    pub fn extend_arithmetic(&self, dom: &mut IndexSet<Value>) {
+        let ints = dom.iter().filter_map(|v| match v {
+            Value::Int(x) => Some(*x),
+            _ => None,
+        });
+        let (min, max) = match (ints.clone().min(), ints.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return,
+        };
+
+        let mut added = 0;
+        loop {
+            let mut arithmetic_dom = IndexSet::new();
+            for fs in self.functions.values() {
+                arithmetic_dom.extend(fs.arithmetic_vals(dom));
+            }
+            let new: Vec<_> = arithmetic_dom
+                .into_iter()
+                .filter(|v| match v {
+                    Value::Int(x) => min <= *x && *x <= max,
+                    _ => false,
+                })
+                .filter(|v| !dom.contains(v))
+                .take(util::MAX_ARITHMETIC_VALS - added)
+                .collect();
+            if new.is_empty() {
+                return;
+            }
+            added += new.len();
+            dom.extend(new);
+        }
+    }
+
+    /// Add a prefix to the names of all props, types, and functions in a
+    /// library (as well as all references to them).
+    pub fn prefix_names(self, prefix: &str) -> Library {
+        let prefix_mn = |mn: &MetName| MetName(format!("{}{}", prefix, mn.0));
+        Library {
+            imports: self.imports,
+            props: self
+                .props
+                .into_iter()
+                .map(|(mn, sig)| (prefix_mn(&mn), sig))
+                .collect(),
+            types: self
+                .types
+                .into_iter()
+                .map(|(mn, sig)| (prefix_mn(&mn), sig))
+                .collect(),
+            functions: self
+                .functions
+                .into_iter()
+                .map(|(f, fs)| {
+                    (
+                        BaseFunction(format!("{}{}", prefix, f.0)),
+                        FunctionSignature {
+                            params: fs
+                                .params
+                                .iter()
+                                .map(|(fp, mn)| (fp.clone(), prefix_mn(mn)))
+                                .collect(),
+                            ret: prefix_mn(&fs.ret),
+                            condition: fs.condition.prefix_names(prefix),
+                            info: fs.info,
+                        },
+                    )
+                })
+                .collect(),
+            preamble: self.preamble,
+            config: self.config,
+        }
+    }
+}
+
+/// The type of partial types (essentially, types that may have omitted
+/// arguments).
+///
+/// In a program, an argument is omitted by leaving it out or by giving the
+/// wildcard `{ any = true }` as its value (a table, so that it cannot be
+/// confused with any value).
+pub type PartialType = Met<Option<Value>>;
+
+impl PartialType {
+    /// Returns the arguments that are not omitted.
+    pub fn fixed(&self) -> IndexMap<MetParam, Value> {
+        self.args
+            .iter()
+            .filter_map(|(mp, ov)| Some((mp.clone(), ov.clone()?)))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Wildcard {
+    any: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawArg {
+    Value(Value),
+    Wildcard(Wildcard),
+}
+
+#[derive(Deserialize)]
+struct RawPartialType {
+    name: MetName,
+    #[serde(default)]
+    args: IndexMap<MetParam, RawArg>,
+}
+
+impl TryFrom<RawPartialType> for PartialType {
+    type Error = String;
+
+    fn try_from(raw: RawPartialType) -> Result<Self, Self::Error> {
+        Ok(Met {
+            name: raw.name,
+            args: raw
+                .args
+                .into_iter()
+                .map(|(mp, arg)| match arg {
+                    RawArg::Value(v) => Ok((mp, Some(v))),
+                    RawArg::Wildcard(Wildcard { any: true }) => Ok((mp, None)),
+                    RawArg::Wildcard(Wildcard { any: false }) => Err(format!(
+                        "argument '{}' must be a value or {{ any = true }}",
+                        mp.0
+                    )),
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn deserialize_goals<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PartialType>, D::Error> {
+    util::OneOrMany::<RawPartialType>::deserialize(deserializer)?
+        .into_vec()
+        .into_iter()
+        .map(|raw| PartialType::try_from(raw).map_err(serde::de::Error::custom))
+        .collect()
+}
+
 /// The type of Honeybee programs.
+///
+/// A program may have several goals (given as an array of tables), in which
+/// case they are synthesized together as a single pipeline.
 #[derive(Clone, Deserialize)]
 pub struct Program {
     #[serde(rename = "Prop")]
     pub props: Vec<Met<Value>>,
-    #[serde(rename = "Goal")]
-    pub goal: Met<Value>,
+    #[serde(rename = "Goal", deserialize_with = "deserialize_goals")]
+    pub goals: Vec<PartialType>,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -324,17 +855,9 @@ pub struct Problem {
 }
 
 impl Problem {
-    /// Returns the set of values in a problem.
+    /// Returns the set of values in a problem (see [`Library::program_vals`]).
     pub fn vals(&self) -> IndexSet<Value> {
-        let mut dom = IndexSet::new();
-        for fs in self.library.functions.values() {
-            dom.extend(fs.vals());
-        }
-        for p in &self.program.props {
-            dom.extend(p.args.values().cloned());
-        }
-        dom.extend(self.program.goal.args.values().cloned());
-        dom
+        self.library.program_vals(&self.program)
     }
 }
 
@@ -342,55 +865,445 @@ impl Problem {
 // Goal convenience wrapper
 
 /// A convenience wrapper type for handling goal-related types and functions.
+///
+/// The goal function takes one parameter per goal of the program. With a
+/// single goal, working expressions are rooted at the goal type (and wrapped in
+/// the goal function when needed); with multiple goals, working expressions
+/// are rooted at the goal function itself, so that all the goals are
+/// synthesized together.
 pub struct Goal {
     pub function: BaseFunction,
-    pub param: FunParam,
+    pub params: Vec<FunParam>,
     pub signature: FunctionSignature,
 }
 
 impl Goal {
-    /// Create a new goal with the specified metadata
-    pub fn new(goal: &Met<Value>) -> Self {
-        let function = BaseFunction("&goal".to_owned());
-        let param = FunParam("&goalparam".to_owned());
+    const FUNCTION: &'static str = "&goal";
+
+    /// Create a new goal function for the specified goals (constraining only
+    /// the arguments that are not omitted)
+    pub fn new(goals: &[PartialType]) -> Self {
+        let function = BaseFunction(Self::FUNCTION.to_owned());
         let ret = MetName("&Goal".to_owned());
 
+        let params: Vec<FunParam> = if goals.len() == 1 {
+            vec![FunParam("&goalparam".to_owned())]
+        } else {
+            (0..goals.len())
+                .map(|i| FunParam(format!("&goalparam{}", i)))
+                .collect()
+        };
+
         let signature = FunctionSignature {
-            condition: Formula::conjunct(goal.args.iter().map(|(mp, v)| {
-                Formula::Eq(
-                    FormulaAtom::Param(param.clone(), mp.clone()),
-                    FormulaAtom::Lit(v.clone()),
-                )
-            })),
+            condition: Formula::conjunct(params.iter().zip(goals).flat_map(
+                |(param, goal)| {
+                    goal.fixed().into_iter().map(|(mp, v)| {
+                        Formula::Eq(
+                            FormulaAtom::Param(param.clone(), mp),
+                            FormulaAtom::Lit(v),
+                        )
+                    })
+                },
+            )),
             ret: ret.clone(),
-            params: IndexMap::from([(param.clone(), goal.name.clone())]),
+            params: params
+                .iter()
+                .cloned()
+                .zip(goals.iter().map(|goal| goal.name.clone()))
+                .collect(),
             info: None,
         };
 
         Self {
             function,
-            param,
+            params,
             signature,
         }
     }
 
+    /// Whether or not working expressions are rooted at the goal function
+    pub fn multiple(&self) -> bool {
+        self.params.len() > 1
+    }
+
     /// Add a goal to a library
     pub fn add_to_library(&self, functions: &mut FunctionLibrary) {
         functions.insert(self.function.clone(), self.signature.clone());
     }
 
+    fn function(&self) -> ParameterizedFunction {
+        ParameterizedFunction::from_sig(
+            &self.signature,
+            self.function.clone(),
+            IndexMap::new(),
+        )
+    }
+
+    /// The working expression that synthesis starts from
+    pub fn start(&self) -> Exp {
+        if self.multiple() {
+            Sketch::App(
+                self.function(),
+                self.params
+                    .iter()
+                    .cloned()
+                    .zip((0..).map(Sketch::Hole))
+                    .collect(),
+            )
+        } else {
+            Sketch::blank()
+        }
+    }
+
     /// Create an application whose head is the goal function
     pub fn app(
         &self,
         e: &Exp,
     ) -> (ParameterizedFunction, IndexMap<FunParam, Exp>) {
-        (
-            ParameterizedFunction::from_sig(
-                &self.signature,
-                self.function.clone(),
-                IndexMap::new(),
+        if !self.multiple() {
+            return (
+                self.function(),
+                IndexMap::from([(self.params[0].clone(), e.clone())]),
+            );
+        }
+
+        match e {
+            Sketch::App(f, args) if f.name == self.function => {
+                (f.clone(), args.clone())
+            }
+            _ => panic!("working expression must be rooted at goal function"),
+        }
+    }
+
+    /// Returns the expressions for each goal in a working expression, along
+    /// with their paths in the working expression
+    pub fn roots(e: &Exp) -> Vec<(Vec<FunParam>, &Exp)> {
+        match e {
+            Sketch::App(f, args) if f.name.0 == Self::FUNCTION => args
+                .iter()
+                .map(|(fp, arg)| (vec![fp.clone()], arg))
+                .collect(),
+            _ => vec![(vec![], e)],
+        }
+    }
+
+    /// Returns the step that retracts the function application at a path of
+    /// a working expression back into a hole (or `None` if there is no such
+    /// application, or if it is the goal function itself)
+    pub fn retraction(e: &Exp, path: Vec<FunParam>) -> Option<Step> {
+        match e.at(&path)? {
+            Sketch::App(f, _) if f.name.0 != Self::FUNCTION => {
+                Some(TopDownStep::Retract(path))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Problem {
+    /// The working expression that synthesis for a problem starts from
+    pub fn start(&self) -> Exp {
+        Goal::new(&self.program.goals).start()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dl_oracle;
+    use crate::egglog;
+    use crate::eval;
+    use crate::menu;
+    use crate::parse;
+    use crate::typecheck;
+
+    const LIBRARY: &str = r#"
+        [Type.Num]
+        params.n = "Int"
+
+        [Function.zero]
+        params = {}
+        ret = "Num"
+        condition = ["ret.n = 0"]
+
+        [Function.inc]
+        params.x = "Num"
+        ret = "Num"
+        condition = ["ret.n = x.n + 1"]
+    "#;
+
+    fn problem(goal: &str) -> Problem {
+        Problem {
+            library: parse::library(LIBRARY).unwrap(),
+            program: parse::program(&format!(
+                r#"
+                Prop = []
+
+                [Goal]
+                name = "Num"
+                {}
+                "#,
+                goal
+            ))
+            .unwrap(),
+        }
+    }
+
+    fn derived(problem: Problem) -> Vec<Value> {
+        let goal = problem.program.goals[0].clone();
+        let mut oracle =
+            dl_oracle::Oracle::new(egglog::Egglog::new(true), problem).unwrap();
+        let mut derived: Vec<_> = oracle
+            .valid_goal_metadata(&goal)
+            .unwrap()
+            .into_iter()
+            .map(|metadata| metadata[&MetParam("n".to_owned())].clone())
+            .collect();
+        derived.sort_by_key(|v| match v {
+            Value::Int(x) => *x,
+            _ => panic!("expected int, got {:?}", v),
+        });
+        derived
+    }
+
+    fn n(x: i64) -> IndexMap<MetParam, Value> {
+        IndexMap::from([(MetParam("n".to_owned()), Value::Int(x))])
+    }
+
+    const READS: &str = r#"
+        [Type.Reads]
+        params.n = "Int"
+        params.platform = "Str"
+
+        [Function.illumina]
+        params = {}
+        ret = "Reads"
+        condition = ['ret.platform = "illumina"', "ret.n = 1"]
+
+        [Function.nanopore]
+        params = {}
+        ret = "Reads"
+        condition = ['ret.platform = "nanopore"', "ret.n = 2"]
+    "#;
+
+    fn goals(goals: &[&str]) -> Result<Vec<PartialType>, String> {
+        let goals: Vec<String> = goals
+            .iter()
+            .map(|args| format!("[[Goal]]\nname = \"Reads\"\nargs = {}", args))
+            .collect();
+        parse::program(&format!("Prop = []\n\n{}", goals.join("\n\n")))
+            .map(|program| program.goals)
+    }
+
+    fn reads(n: i64, platform: &str) -> IndexMap<MetParam, Value> {
+        IndexMap::from([
+            (MetParam("n".to_owned()), Value::Int(n)),
+            (
+                MetParam("platform".to_owned()),
+                Value::Str(platform.to_owned()),
             ),
-            IndexMap::from([(self.param.clone(), e.clone())]),
-        )
+        ])
+    }
+
+    #[test]
+    fn goal_arguments_may_be_omitted_or_wildcards() {
+        let platform = MetParam("platform".to_owned());
+
+        let omitted = &goals(&["{ n = 1 }"]).unwrap()[0];
+        assert_eq!(omitted.args.get(&platform), None);
+        assert_eq!(omitted.fixed(), n(1));
+
+        let wildcard =
+            &goals(&["{ n = 1, platform = { any = true } }"]).unwrap()[0];
+        assert_eq!(wildcard.args.get(&platform), Some(&None));
+        assert_eq!(wildcard.fixed(), n(1));
+
+        // Strings are always values, even if they look like wildcards
+        let underscore = &goals(&[r#"{ n = 1, platform = "_" }"#]).unwrap()[0];
+        assert_eq!(underscore.fixed()[&platform], Value::Str("_".to_owned()));
+        assert_eq!(
+            underscore.fixed()[&MetParam("n".to_owned())],
+            Value::Int(1)
+        );
+
+        assert!(goals(&["{ platform = { any = false } }"]).is_err());
+        assert!(goals(&["{ platform = { all = true } }"]).is_err());
+    }
+
+    #[test]
+    fn goal_function_constrains_only_fixed_arguments() {
+        let goal =
+            Goal::new(&goals(&["{ platform = { any = true } }"]).unwrap());
+        assert_eq!(goal.signature.condition, Formula::True);
+
+        let goal = Goal::new(
+            &goals(&["{ n = 1 }", r#"{ platform = "nanopore" }"#]).unwrap(),
+        );
+        assert_eq!(
+            goal.signature.condition.conditions(),
+            vec![
+                &Formula::Eq(
+                    FormulaAtom::Param(
+                        FunParam("&goalparam0".to_owned()),
+                        MetParam("n".to_owned())
+                    ),
+                    FormulaAtom::Lit(Value::Int(1)),
+                ),
+                &Formula::Eq(
+                    FormulaAtom::Param(
+                        FunParam("&goalparam1".to_owned()),
+                        MetParam("platform".to_owned())
+                    ),
+                    FormulaAtom::Lit(Value::Str("nanopore".to_owned())),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn retraction_refuses_the_goal_function() {
+        let problem = Problem {
+            library: parse::library(READS).unwrap(),
+            program: parse::program(&format!(
+                "Prop = []\n\n{}",
+                ["[[Goal]]\nname = \"Reads\"", "[[Goal]]\nname = \"Reads\""]
+                    .join("\n\n")
+            ))
+            .unwrap(),
+        };
+        let mut controller = menu::Algorithm::PBNHoneybee
+            .controller(util::Timer::infinite(), problem, false)
+            .unwrap();
+        let step = controller
+            .provide()
+            .unwrap()
+            .into_iter()
+            .find(|step| match step {
+                TopDownStep::Extend(h, f, _) => {
+                    *h == 0 && f.name.0 == "illumina"
+                }
+                _ => false,
+            })
+            .unwrap();
+        controller.decide(step);
+        let e = controller.working_expression();
+        let goalparam = |i: usize| FunParam(format!("&goalparam{}", i));
+
+        assert_eq!(
+            Goal::retraction(e, vec![goalparam(0)]),
+            Some(TopDownStep::Retract(vec![goalparam(0)]))
+        );
+        assert_eq!(Goal::retraction(e, vec![]), None);
+        assert_eq!(Goal::retraction(e, vec![goalparam(1)]), None);
+        assert_eq!(Goal::retraction(e, vec![goalparam(2)]), None);
+        assert_eq!(
+            Goal::retraction(e, vec![goalparam(0), FunParam("x".to_owned())]),
+            None
+        );
+    }
+
+    #[test]
+    fn partial_goals_leave_the_rest_to_navigation() {
+        let valid = |args: &str| {
+            let program = parse::program(&format!(
+                "Prop = []\n\n[Goal]\nname = \"Reads\"\nargs = {}",
+                args
+            ))
+            .unwrap();
+            let goal = program.goals[0].clone();
+            let problem = Problem {
+                library: parse::library(READS).unwrap(),
+                program,
+            };
+            let mut oracle =
+                dl_oracle::Oracle::new(egglog::Egglog::new(true), problem)
+                    .unwrap();
+            (
+                oracle.goal_solvable(&goal).unwrap(),
+                oracle.valid_goal_metadata(&goal).unwrap(),
+            )
+        };
+
+        assert_eq!(
+            valid("{}"),
+            (true, vec![reads(1, "illumina"), reads(2, "nanopore")])
+        );
+        assert_eq!(
+            valid(r#"{ platform = "nanopore" }"#),
+            (true, vec![reads(2, "nanopore")])
+        );
+        assert_eq!(
+            valid("{ n = 1, platform = { any = true } }"),
+            (true, vec![reads(1, "illumina")])
+        );
+        assert_eq!(
+            valid(r#"{ n = 1, platform = "nanopore" }"#),
+            (false, vec![])
+        );
+    }
+
+    #[test]
+    fn arithmetic_overflow_never_holds() {
+        let phi =
+            Formula::try_from(vec!["ret.n = x.n + 1".to_owned()]).unwrap();
+        let not_phi =
+            Formula::try_from(vec!["not (ret.n = x.n + 1)".to_owned()])
+                .unwrap();
+
+        let props = vec![];
+        let args = IndexMap::from([(FunParam("x".to_owned()), n(i64::MAX))]);
+        for ret in [n(i64::MIN), n(0)] {
+            let ctx = eval::Context {
+                props: &props,
+                args: &args,
+                ret: &ret,
+            };
+            assert!(!ctx.sat(&phi));
+            assert!(!ctx.sat(&not_phi));
+        }
+
+        let args = IndexMap::from([(FunParam("x".to_owned()), n(1))]);
+        let ret = n(3);
+        let ctx = eval::Context {
+            props: &props,
+            args: &args,
+            ret: &ret,
+        };
+        assert!(!ctx.sat(&phi));
+        assert!(ctx.sat(&not_phi));
+    }
+
+    #[test]
+    fn arithmetic_chains_are_derivable_up_to_the_largest_literal() {
+        // `inc(inc(inc(inc(inc(zero)))))` has n = 5
+        let five = problem("args.n = 5");
+        let vals = five.vals();
+        for x in 0..=5 {
+            assert!(vals.contains(&Value::Int(x)));
+        }
+        assert!(!vals.contains(&Value::Int(6)));
+        assert_eq!(derived(five), vec![Value::Int(5)]);
+
+        // Without a goal literal, the largest literal of the library is 1
+        assert_eq!(derived(problem("")), vec![Value::Int(0), Value::Int(1)]);
+    }
+
+    #[test]
+    fn arithmetic_adds_a_bounded_number_of_values() {
+        assert!(
+            problem("args.n = 1000000").vals().len()
+                <= 3 + util::MAX_ARITHMETIC_VALS
+        );
+    }
+
+    #[test]
+    fn arithmetic_with_many_parameters_is_rejected() {
+        let check = |condition: &str| {
+            let lib = LIBRARY.replace("x.n + 1", condition);
+            let lib = parse::library(&lib).unwrap();
+            typecheck::library(&lib)
+        };
+        assert!(check("x.n + x.n").is_ok());
+        assert!(check("x.n + x.n - x.n").is_err());
     }
 }
@@ -5,31 +5,110 @@
 //! programs but does not define how they are executed, which is the job of a
 //! Datalog engine.
 
+use crate::util;
+
 use indexmap::IndexMap;
 use indexmap::IndexSet;
+use serde::Serialize;
 
 /// The type of errors used by this module.
-pub type Error = String;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Error {
+    /// The program (or query) is malformed
+    InvalidProgram(String),
+    /// The engine was queried before a program was loaded
+    NotLoaded,
+    /// The underlying engine failed to run a program or query
+    Backend(String),
+    /// The underlying engine produced output that could not be understood
+    UnexpectedOutput(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidProgram(msg) => {
+                write!(f, "invalid Datalog program: {}", msg)
+            }
+            Error::NotLoaded => {
+                write!(f, "Datalog engine queried before loading a program")
+            }
+            Error::Backend(msg) => write!(f, "Datalog engine error: {}", msg),
+            Error::UnexpectedOutput(msg) => {
+                write!(f, "unexpected Datalog engine output: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for String {
+    fn from(e: Error) -> Self {
+        e.to_string()
+    }
+}
 
 /// The types that primitive values may take on.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ValueType {
     Bool,
     Int,
+    Float,
     Str,
 }
 
 /// The possible primitive values.
 ///
 /// A value is considered *abstract* if it is a variable and *ground* otherwise.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+///
+/// As with core values, floats are compared (and hashed) by their bit
+/// patterns, with `-0.0` and `0.0` equal (see [`util::float_bits`]).
+#[derive(Debug, Clone)]
 pub enum Value {
     Bool(bool),
     Int(i64),
+    Float(f64),
     Str(String),
     Var { name: String, typ: ValueType },
 }
 
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Int(x1), Value::Int(x2)) => x1 == x2,
+            (Value::Float(x1), Value::Float(x2)) => {
+                util::float_bits(*x1) == util::float_bits(*x2)
+            }
+            (Value::Str(s1), Value::Str(s2)) => s1 == s2,
+            (
+                Value::Var { name: n1, typ: t1 },
+                Value::Var { name: n2, typ: t2 },
+            ) => n1 == n2 && t1 == t2,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl std::hash::Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Bool(b) => b.hash(state),
+            Value::Int(x) => x.hash(state),
+            Value::Float(x) => util::float_bits(*x).hash(state),
+            Value::Str(s) => s.hash(state),
+            Value::Var { name, typ } => {
+                name.hash(state);
+                typ.hash(state);
+            }
+        }
+    }
+}
+
 /// The type of value domains.
 ///
 /// This type is used to finitize the domain of possible values that program
@@ -37,10 +116,10 @@ pub enum Value {
 pub type Domain = IndexSet<Value>;
 
 impl Value {
-    fn check_domain(&self, dom: &Domain) -> Result<(), Error> {
+    fn check_domain(&self, dom: &Domain) -> Result<(), String> {
         match self {
             Value::Bool(_) | Value::Var { .. } => return Ok(()),
-            Value::Int(_) | Value::Str(_) => (),
+            Value::Int(_) | Value::Float(_) | Value::Str(_) => (),
         }
         if !dom.contains(self) {
             return Err(format!("value {:?} not in provided domain", self));
@@ -54,13 +133,14 @@ impl Value {
         match self {
             Value::Bool(_) => ValueType::Bool,
             Value::Int(_) => ValueType::Int,
+            Value::Float(_) => ValueType::Float,
             Value::Str(_) => ValueType::Str,
             Value::Var { typ, .. } => typ.clone(),
         }
     }
 
     /// Infer the type of a value (including a domain check).
-    pub fn infer(&self, dom: &Domain) -> Result<ValueType, Error> {
+    pub fn infer(&self, dom: &Domain) -> Result<ValueType, String> {
         self.check_domain(dom)?;
         Ok(self.unsafe_infer())
     }
@@ -69,6 +149,7 @@ impl Value {
         match self {
             Value::Bool(_) => true,
             Value::Int(_) => true,
+            Value::Float(_) => true,
             Value::Str(_) => true,
             Value::Var { .. } => false,
         }
@@ -78,6 +159,7 @@ impl Value {
         match self {
             Value::Bool(_) => false,
             Value::Int(_) => false,
+            Value::Float(_) => false,
             Value::Str(_) => false,
             Value::Var { .. } => true,
         }
@@ -85,7 +167,10 @@ impl Value {
 
     fn prefix_vars(&self, prefix: &str) -> Self {
         match self {
-            Value::Bool(_) | Value::Int(_) | Value::Str(_) => self.clone(),
+            Value::Bool(_)
+            | Value::Int(_)
+            | Value::Float(_)
+            | Value::Str(_) => self.clone(),
             Value::Var { name, typ } => Self::Var {
                 name: format!("{}{}", prefix, name),
                 typ: typ.clone(),
@@ -111,6 +196,7 @@ pub enum RelationKind {
 }
 
 /// Signatures for relations that define their arity and kind.
+#[derive(Debug, Clone)]
 pub struct RelationSignature {
     pub params: Vec<ValueType>,
     pub kind: RelationKind,
@@ -126,7 +212,7 @@ pub type RelationLibrary = IndexMap<Relation, RelationSignature>;
 ///
 /// A fact is considered *ground* if all its arguments are ground and *abstract*
 /// if all its arguments are abstract.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Fact {
     pub relation: Relation,
     pub args: Vec<Option<Value>>,
@@ -137,7 +223,7 @@ impl Fact {
         &'a self,
         lib: &'a RelationLibrary,
         dom: &'a Domain,
-    ) -> Result<&'a RelationSignature, Error> {
+    ) -> Result<&'a RelationSignature, String> {
         let sig = lib
             .get(&self.relation)
             .ok_or(format!("unknown relation {:?}", self.relation))?;
@@ -203,13 +289,19 @@ impl Fact {
 /// The possible predicates for the right-hand side (antecedent) of a rule.
 ///
 /// A predicate is either an abstract fact or a primitive such as built-in
-/// equality.
-#[derive(Debug, Clone)]
+/// equality. The arithmetic primitives relate their first two arguments to
+/// their third; for example, `PrimAdd(x, y, z)` holds when `x + y = z`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Predicate {
     Fact(Fact),
     PrimEq(Value, Value),
     PrimLt(Value, Value),
+    PrimLe(Value, Value),
+    PrimGt(Value, Value),
+    PrimGe(Value, Value),
     PrimNeq(Value, Value),
+    PrimAdd(Value, Value, Value),
+    PrimSub(Value, Value, Value),
 }
 
 impl Predicate {
@@ -217,7 +309,7 @@ impl Predicate {
         dom: &Domain,
         v1: &Value,
         v2: &Value,
-    ) -> Result<(), Error> {
+    ) -> Result<(), String> {
         let vt1 = v1.infer(dom)?;
         let vt2 = v2.infer(dom)?;
         if vt1 != vt2 {
@@ -229,7 +321,7 @@ impl Predicate {
         Ok(())
     }
 
-    fn check(&self, lib: &RelationLibrary, dom: &Domain) -> Result<(), Error> {
+    fn check(&self, lib: &RelationLibrary, dom: &Domain) -> Result<(), String> {
         match self {
             Predicate::Fact(f) => {
                 let _ = f.infer(lib, dom)?;
@@ -238,16 +330,31 @@ impl Predicate {
             Predicate::PrimEq(v1, v2) | Predicate::PrimNeq(v1, v2) => {
                 Self::check_equal_types(dom, v1, v2)
             }
-            Predicate::PrimLt(v1, v2) => {
+            Predicate::PrimLt(v1, v2)
+            | Predicate::PrimLe(v1, v2)
+            | Predicate::PrimGt(v1, v2)
+            | Predicate::PrimGe(v1, v2) => {
                 let vt1 = v1.infer(dom)?;
-                if vt1 != ValueType::Int {
+                if vt1 != ValueType::Int && vt1 != ValueType::Float {
                     return Err(format!(
-                        "value {:?} has type {:?}, expected Int",
+                        "value {:?} has type {:?}, expected Int or Float",
                         v1, vt1,
                     ));
                 }
                 Self::check_equal_types(dom, v1, v2)
             }
+            Predicate::PrimAdd(v1, v2, v3) | Predicate::PrimSub(v1, v2, v3) => {
+                for v in [v1, v2, v3] {
+                    let vt = v.infer(dom)?;
+                    if vt != ValueType::Int {
+                        return Err(format!(
+                            "value {:?} has type {:?}, expected Int",
+                            v, vt,
+                        ));
+                    }
+                }
+                Ok(())
+            }
         }
     }
 
@@ -262,21 +369,50 @@ impl Predicate {
                 left.prefix_vars(prefix),
                 right.prefix_vars(prefix),
             ),
+            Predicate::PrimLe(left, right) => Predicate::PrimLe(
+                left.prefix_vars(prefix),
+                right.prefix_vars(prefix),
+            ),
+            Predicate::PrimGt(left, right) => Predicate::PrimGt(
+                left.prefix_vars(prefix),
+                right.prefix_vars(prefix),
+            ),
+            Predicate::PrimGe(left, right) => Predicate::PrimGe(
+                left.prefix_vars(prefix),
+                right.prefix_vars(prefix),
+            ),
             Predicate::PrimNeq(left, right) => Predicate::PrimNeq(
                 left.prefix_vars(prefix),
                 right.prefix_vars(prefix),
             ),
+            Predicate::PrimAdd(left, right, result) => Predicate::PrimAdd(
+                left.prefix_vars(prefix),
+                right.prefix_vars(prefix),
+                result.prefix_vars(prefix),
+            ),
+            Predicate::PrimSub(left, right, result) => Predicate::PrimSub(
+                left.prefix_vars(prefix),
+                right.prefix_vars(prefix),
+                result.prefix_vars(prefix),
+            ),
         }
     }
 
-    fn vals(&self) -> IndexSet<Value> {
+    pub fn vals(&self) -> IndexSet<Value> {
         match self {
             Predicate::Fact(f) => f.vals(),
             Predicate::PrimEq(left, right)
             | Predicate::PrimLt(left, right)
+            | Predicate::PrimLe(left, right)
+            | Predicate::PrimGt(left, right)
+            | Predicate::PrimGe(left, right)
             | Predicate::PrimNeq(left, right) => {
                 IndexSet::from([left.clone(), right.clone()])
             }
+            Predicate::PrimAdd(left, right, result)
+            | Predicate::PrimSub(left, right, result) => {
+                IndexSet::from([left.clone(), right.clone(), result.clone()])
+            }
         }
     }
 }
@@ -294,7 +430,7 @@ pub struct Rule {
 }
 
 impl Rule {
-    fn check(&self, lib: &RelationLibrary, dom: &Domain) -> Result<(), Error> {
+    fn check(&self, lib: &RelationLibrary, dom: &Domain) -> Result<(), String> {
         let head_sig = self.head.infer(lib, dom)?;
 
         if head_sig.kind != RelationKind::IDB {
@@ -312,7 +448,7 @@ impl Rule {
         Ok(())
     }
 
-    const X_PREFIX: &'static str = "&x_";
+    pub const X_PREFIX: &'static str = "&x_";
     const Y_PREFIX: &'static str = "&y_";
 
     /// Cut one rule with another.
@@ -322,7 +458,12 @@ impl Rule {
             Predicate::Fact(f) => f,
             Predicate::PrimEq(_, _)
             | Predicate::PrimLt(_, _)
-            | Predicate::PrimNeq(_, _) => {
+            | Predicate::PrimLe(_, _)
+            | Predicate::PrimGt(_, _)
+            | Predicate::PrimGe(_, _)
+            | Predicate::PrimNeq(_, _)
+            | Predicate::PrimAdd(_, _, _)
+            | Predicate::PrimSub(_, _, _) => {
                 log::debug!(
                     "Can't cut {}/{}/{} because predicate {} of {} is a primitive",
                     other.name,
@@ -377,6 +518,22 @@ impl Rule {
         }
         ret
     }
+
+    /// Binds the head arguments of a rule, restricting the head to the facts
+    /// that agree with every bound (`Some`) argument.
+    pub fn bind(&self, bound: &[Option<Value>]) -> Rule {
+        let mut body = self.body.clone();
+        for (ox, ov) in self.head.args.iter().zip(bound.iter()) {
+            if let (Some(x), Some(v)) = (ox, ov) {
+                body.push(Predicate::PrimEq(x.clone(), v.clone()));
+            }
+        }
+        Rule {
+            name: self.name.clone(),
+            head: self.head.clone(),
+            body,
+        }
+    }
 }
 
 /// The type of Datalog programs.
@@ -387,6 +544,7 @@ impl Rule {
 ///   take on
 /// - A set of rules that define the inhabitation of IDB facts
 /// - A set of ground facts that define the inhabitation of EDB facts
+#[derive(Debug, Clone)]
 pub struct Program {
     pub lib: RelationLibrary,
     pub dom: Domain,
@@ -410,11 +568,11 @@ impl Program {
             ground_facts,
             _private: (),
         };
-        ret.check()?;
+        ret.check().map_err(Error::InvalidProgram)?;
         Ok(ret)
     }
 
-    fn check(&self) -> Result<(), Error> {
+    fn check(&self) -> Result<(), String> {
         for v in &self.dom {
             if !v.is_ground() {
                 return Err(format!("value {:?} in domain is not ground", v));
@@ -442,13 +600,370 @@ impl Program {
     }
 }
 
+/// The type of derivation trees (provenance) for ground facts.
+///
+/// A derivation tree records the rule that derived a fact along with the
+/// derivation trees of the facts in the body of that rule (in order). Ground
+/// facts given by the program itself are leaves and have no rule.
+#[derive(Debug, Clone)]
+pub struct DerivationTree {
+    pub fact: Fact,
+    pub rule: Option<String>,
+    pub children: Vec<DerivationTree>,
+}
+
 /// The interface for Datalog engines.
 pub trait Engine {
-    fn load(&mut self, program: Program);
+    fn load(&mut self, program: Program) -> Result<(), Error>;
 
     fn query(
         &mut self,
         signature: &RelationSignature,
         rule: &Rule,
-    ) -> Vec<Vec<Value>>;
+    ) -> Result<Vec<Vec<Value>>, Error>;
+
+    /// Like [`Engine::query`], but also returns a derivation tree for each
+    /// answer; the root of each tree is an application of the query rule.
+    fn query_with_provenance(
+        &mut self,
+        signature: &RelationSignature,
+        rule: &Rule,
+    ) -> Result<Vec<(Vec<Value>, DerivationTree)>, Error>;
+
+    /// Like [`Engine::query`], but only returns the answers that agree with
+    /// the bound (`Some`) arguments.
+    fn query_bound(
+        &mut self,
+        signature: &RelationSignature,
+        rule: &Rule,
+        bound: &[Option<Value>],
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        self.query(signature, &rule.bind(bound))
+    }
+
+    /// Returns whether the query has any answer that agrees with the bound
+    /// (`Some`) arguments; engines may stop as soon as one is found.
+    fn exists(
+        &mut self,
+        signature: &RelationSignature,
+        rule: &Rule,
+        bound: &[Option<Value>],
+    ) -> Result<bool, Error> {
+        Ok(!self.query_bound(signature, rule, bound)?.is_empty())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Pretty-printing
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Bool => write!(f, "bool"),
+            ValueType::Int => write!(f, "int"),
+            ValueType::Float => write!(f, "float"),
+            ValueType::Str => write!(f, "str"),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Var { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
+
+impl std::fmt::Display for Fact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|ov| match ov {
+                Some(v) => v.to_string(),
+                None => "_".to_owned(),
+            })
+            .collect();
+        write!(f, "{}({})", self.relation.0, args.join(", "))
+    }
+}
+
+impl std::fmt::Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Predicate::Fact(fact) => write!(f, "{}", fact),
+            Predicate::PrimEq(left, right) => write!(f, "{} = {}", left, right),
+            Predicate::PrimLt(left, right) => write!(f, "{} < {}", left, right),
+            Predicate::PrimLe(left, right) => {
+                write!(f, "{} <= {}", left, right)
+            }
+            Predicate::PrimGt(left, right) => write!(f, "{} > {}", left, right),
+            Predicate::PrimGe(left, right) => {
+                write!(f, "{} >= {}", left, right)
+            }
+            Predicate::PrimNeq(left, right) => {
+                write!(f, "{} != {}", left, right)
+            }
+            Predicate::PrimAdd(left, right, result) => {
+                write!(f, "{} = {} + {}", result, left, right)
+            }
+            Predicate::PrimSub(left, right, result) => {
+                write!(f, "{} = {} - {}", result, left, right)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "% {}", self.name)?;
+        write!(f, "{} :-", self.head)?;
+        for (i, p) in self.body.iter().enumerate() {
+            write!(f, "{}\n    {}", if i == 0 { "" } else { "," }, p)?;
+        }
+        write!(f, ".")
+    }
+}
+
+fn relation_signature(rel: &Relation, sig: &RelationSignature) -> String {
+    let params: Vec<String> =
+        sig.params.iter().map(|vt| vt.to_string()).collect();
+    format!("{:?} {}({})", sig.kind, rel.0, params.join(", "))
+}
+
+/// Pretty-print a Datalog program along with a set of queries.
+pub fn pretty(
+    program: &Program,
+    queries: &[(Rule, RelationSignature)],
+) -> String {
+    let mut lines = vec![];
+
+    lines.push("% Relations\n".to_owned());
+    for (rel, sig) in &program.lib {
+        lines.push(format!("% {}", relation_signature(rel, sig)));
+    }
+
+    lines.push("\n% Domain\n".to_owned());
+    for v in &program.dom {
+        lines.push(format!("% {}: {}", v, v.unsafe_infer()));
+    }
+
+    lines.push("\n% Rules\n".to_owned());
+    for r in &program.rules {
+        lines.push(format!("{}\n", r));
+    }
+
+    lines.push("% Facts\n".to_owned());
+    for f in &program.ground_facts {
+        lines.push(format!("{}.", f));
+    }
+
+    if !queries.is_empty() {
+        lines.push("\n% Queries\n".to_owned());
+        for (r, sig) in queries {
+            lines.push(format!(
+                "% {}\n{}\n",
+                relation_signature(&r.head.relation, sig),
+                r
+            ));
+        }
+    }
+
+    lines.join("\n")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Provenance reconstruction
+
+/// Reconstruct derivation trees for the answers to a query using only the
+/// (provenance-free) [`Engine::query`] interface of an engine on which
+/// `program` has been loaded.
+///
+/// Derivations are reconstructed in two phases. First, the facts that might
+/// be used to derive the answers are explored top-down: for each fact, each
+/// rule for its relation is instantiated with the arguments of the fact and
+/// the engine is asked for the bindings of the remaining variables of the
+/// rule, each of which yields the facts of the body as children. Second, trees
+/// are built bottom-up from these rule instances until a fixpoint, so every
+/// fact is queried at most once per rule (rather than once per path to it, as
+/// backtracking would) and the trees are always finite.
+pub fn reconstruct<Eng: Engine + ?Sized>(
+    engine: &mut Eng,
+    program: &Program,
+    signature: &RelationSignature,
+    rule: &Rule,
+) -> Result<Vec<(Vec<Value>, DerivationTree)>, Error> {
+    let answers: Vec<(Vec<Value>, Fact)> = engine
+        .query(signature, rule)?
+        .into_iter()
+        .map(|vals| {
+            let fact = Fact {
+                relation: rule.head.relation.clone(),
+                args: vals.iter().cloned().map(Some).collect(),
+            };
+            (vals, fact)
+        })
+        .collect();
+
+    // Phase 1: explore the rule instances of the relevant facts
+
+    let mut instances: IndexMap<Fact, Vec<(&Rule, Vec<Fact>)>> =
+        IndexMap::new();
+    let mut leaves: IndexMap<Fact, DerivationTree> = IndexMap::new();
+    let mut worklist = vec![];
+
+    for (_, fact) in &answers {
+        instances.insert(fact.clone(), instantiate(engine, rule, fact)?);
+        worklist.push(fact.clone());
+    }
+
+    while let Some(fact) = worklist.pop() {
+        let children: Vec<Fact> = instances[&fact]
+            .iter()
+            .flat_map(|(_, children)| children.iter().cloned())
+            .collect();
+        for child in children {
+            if instances.contains_key(&child) || leaves.contains_key(&child) {
+                continue;
+            }
+            match program.lib.get(&child.relation) {
+                Some(sig) if sig.kind == RelationKind::EDB => {
+                    if let Some(leaf) = ground(program, &child) {
+                        leaves.insert(child, leaf);
+                    }
+                }
+                Some(_) => {
+                    let mut child_instances = vec![];
+                    for r in &program.rules {
+                        if r.head.relation == child.relation {
+                            child_instances
+                                .extend(instantiate(engine, r, &child)?);
+                        }
+                    }
+                    instances.insert(child.clone(), child_instances);
+                    worklist.push(child);
+                }
+                None => (),
+            }
+        }
+    }
+
+    // Phase 2: build trees bottom-up
+
+    let mut trees = leaves;
+    loop {
+        let mut changed = false;
+        for (fact, fact_instances) in &instances {
+            if trees.contains_key(fact) {
+                continue;
+            }
+            let tree = fact_instances.iter().find_map(|(r, children)| {
+                Some(DerivationTree {
+                    fact: fact.clone(),
+                    rule: Some(r.name.clone()),
+                    children: children
+                        .iter()
+                        .map(|c| trees.get(c).cloned())
+                        .collect::<Option<_>>()?,
+                })
+            });
+            if let Some(tree) = tree {
+                trees.insert(fact.clone(), tree);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    Ok(answers
+        .into_iter()
+        .filter_map(|(vals, fact)| {
+            trees.swap_remove(&fact).map(|tree| (vals, tree))
+        })
+        .collect())
+}
+
+/// Returns the ground fact of the program that matches a fact as a leaf
+/// (wildcards in the fact are filled in by the ground fact)
+fn ground(program: &Program, fact: &Fact) -> Option<DerivationTree> {
+    program
+        .ground_facts
+        .iter()
+        .find(|gf| {
+            gf.relation == fact.relation
+                && gf.args.iter().zip(fact.args.iter()).all(|(gv, ov)| {
+                    ov.as_ref().is_none_or(|v| gv.as_ref() == Some(v))
+                })
+        })
+        .map(|gf| DerivationTree {
+            fact: gf.clone(),
+            rule: None,
+            children: vec![],
+        })
+}
+
+/// Returns the instances of a rule whose head is a fact, as the facts of
+/// their bodies
+fn instantiate<'a, Eng: Engine + ?Sized>(
+    engine: &mut Eng,
+    rule: &'a Rule,
+    fact: &Fact,
+) -> Result<Vec<(&'a Rule, Vec<Fact>)>, Error> {
+    let vars: Vec<Value> = rule
+        .vals()
+        .into_iter()
+        .filter(|v| v.is_abstract())
+        .collect();
+
+    let witness_signature = RelationSignature {
+        params: vars.iter().map(|v| v.unsafe_infer()).collect(),
+        kind: RelationKind::IDB,
+    };
+
+    let witness_rule = Rule {
+        name: "&witness".to_owned(),
+        head: Fact {
+            relation: Relation("&Witness".to_owned()),
+            args: vars.iter().cloned().map(Some).collect(),
+        },
+        body: rule.bind(&fact.args).body,
+    };
+
+    Ok(engine
+        .query(&witness_signature, &witness_rule)?
+        .into_iter()
+        .map(|vals| {
+            let binding: IndexMap<&Value, &Value> =
+                vars.iter().zip(vals.iter()).collect();
+            let children = rule
+                .body
+                .iter()
+                .filter_map(|p| match p {
+                    Predicate::Fact(f) => Some(Fact {
+                        relation: f.relation.clone(),
+                        args: f
+                            .args
+                            .iter()
+                            .map(|ov| {
+                                ov.as_ref().map(|v| {
+                                    binding
+                                        .get(v)
+                                        .map_or(v.clone(), |&w| w.clone())
+                                })
+                            })
+                            .collect(),
+                    }),
+                    _ => None,
+                })
+                .collect();
+            (rule, children)
+        })
+        .collect())
 }
@@ -7,16 +7,22 @@
 use crate::core::{self, *};
 use crate::datalog::{self, *};
 use crate::top_down::*;
+use crate::traditional_synthesis::CostOracle;
 use crate::typecheck;
+use crate::unparse;
 use crate::util;
 
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use pbn::Timer;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 ////////////////////////////////////////////////////////////////////////////////
 // Compilation to datalog
 
-struct CompileContext<'a>(typecheck::Context<'a>);
+/// The context for compiling a (well-typed) library to Datalog.
+pub struct CompileContext<'a>(pub typecheck::Context<'a>);
 
 impl CompileContext<'_> {
     fn ret() -> FunParam {
@@ -27,7 +33,10 @@ impl CompileContext<'_> {
         match vt {
             core::ValueType::Bool => datalog::ValueType::Bool,
             core::ValueType::Int => datalog::ValueType::Int,
-            core::ValueType::Str => datalog::ValueType::Str,
+            core::ValueType::Float => datalog::ValueType::Float,
+            core::ValueType::Str | core::ValueType::Enum(_) => {
+                datalog::ValueType::Str
+            }
         }
     }
 
@@ -35,6 +44,7 @@ impl CompileContext<'_> {
         match v {
             core::Value::Bool(b) => datalog::Value::Bool(*b),
             core::Value::Int(x) => datalog::Value::Int(*x),
+            core::Value::Float(x) => datalog::Value::Float(*x),
             core::Value::Str(s) => datalog::Value::Str(s.clone()),
         }
     }
@@ -55,9 +65,10 @@ impl CompileContext<'_> {
         &self,
         fs: &FunctionSignature,
         ap: &AtomicProposition,
-    ) -> Predicate {
+    ) -> Vec<Predicate> {
         let sig = self.0 .0.props.get(&ap.name).unwrap();
-        Predicate::Fact(Fact {
+        let mut ret = vec![];
+        let fact = Predicate::Fact(Fact {
             relation: Relation(ap.name.0.clone()),
             args: sig
                 .params
@@ -67,10 +78,12 @@ impl CompileContext<'_> {
                         .get(mp)
                         .unwrap()
                         .as_ref()
-                        .map(|fa| self.formula_atom(fs, &fa))
+                        .map(|fa| self.formula_atom(fs, fa, &mut ret))
                 })
                 .collect(),
-        })
+        });
+        ret.push(fact);
+        ret
     }
 
     fn var(
@@ -97,49 +110,289 @@ impl CompileContext<'_> {
         }
     }
 
+    /// Compiles a formula atom to a value, adding the predicates needed to
+    /// compute its arithmetic (if any) to `arithmetic`.
+    ///
+    /// Each arithmetic subterm is bound to a variable named after its
+    /// structure, so equal subterms share a variable.
     fn formula_atom(
         &self,
         fs: &FunctionSignature,
         fa: &FormulaAtom,
+        arithmetic: &mut Vec<Predicate>,
     ) -> datalog::Value {
         let vt = self.0.infer_formula_atom(fs, fa).unwrap();
-        match fa {
-            FormulaAtom::Param(fp, mp) => self.var(fp, mp, &vt),
-            FormulaAtom::Ret(mp) => self.var(&Self::ret(), mp, &vt),
-            FormulaAtom::Lit(v) => self.value(v),
+        let (op, fa1, fa2, prim): (_, _, _, fn(_, _, _) -> Predicate) = match fa
+        {
+            FormulaAtom::Param(fp, mp) => return self.var(fp, mp, &vt),
+            FormulaAtom::Ret(mp) => return self.var(&Self::ret(), mp, &vt),
+            FormulaAtom::Lit(v) => return self.value(v),
+            FormulaAtom::Add(fa1, fa2) => ("add", fa1, fa2, Predicate::PrimAdd),
+            FormulaAtom::Sub(fa1, fa2) => ("sub", fa1, fa2, Predicate::PrimSub),
+        };
+        let left = self.formula_atom(fs, fa1, arithmetic);
+        let right = self.formula_atom(fs, fa2, arithmetic);
+        let result = datalog::Value::Var {
+            name: format!(
+                "&{}[{},{}]",
+                op,
+                Self::arithmetic_name(&left),
+                Self::arithmetic_name(&right)
+            ),
+            typ: self.value_type(&vt),
+        };
+        arithmetic.push(prim(left, right, result.clone()));
+        result
+    }
+
+    fn arithmetic_name(v: &datalog::Value) -> String {
+        match v {
+            datalog::Value::Int(x) => x.to_string(),
+            datalog::Value::Var { name, .. } => name.clone(),
+            _ => panic!("non-integer value in arithmetic: {:?}", v),
+        }
+    }
+
+    fn comparison(
+        &self,
+        fs: &FunctionSignature,
+        left: &FormulaAtom,
+        right: &FormulaAtom,
+        prim: fn(datalog::Value, datalog::Value) -> Predicate,
+    ) -> Vec<Vec<Predicate>> {
+        let mut ret = vec![];
+        let left = self.formula_atom(fs, left, &mut ret);
+        let right = self.formula_atom(fs, right, &mut ret);
+        ret.push(prim(left, right));
+        vec![ret]
+    }
+
+    /// Compiles a formula to disjunctive normal form: a list of alternative
+    /// conjunctions of predicates (so [`Formula::True`] compiles to a single
+    /// empty conjunction and an unsatisfiable formula to no conjunctions).
+    fn formula(
+        &self,
+        fs: &FunctionSignature,
+        f: &Formula,
+    ) -> Vec<Vec<Predicate>> {
+        match f {
+            Formula::True => vec![vec![]],
+            Formula::Eq(left, right) => {
+                self.comparison(fs, left, right, Predicate::PrimEq)
+            }
+            Formula::Neq(left, right) => {
+                self.comparison(fs, left, right, Predicate::PrimNeq)
+            }
+            Formula::Lt(left, right) => {
+                self.comparison(fs, left, right, Predicate::PrimLt)
+            }
+            Formula::Le(left, right) => {
+                self.comparison(fs, left, right, Predicate::PrimLe)
+            }
+            Formula::Gt(left, right) => {
+                self.comparison(fs, left, right, Predicate::PrimGt)
+            }
+            Formula::Ge(left, right) => {
+                self.comparison(fs, left, right, Predicate::PrimGe)
+            }
+            Formula::Ap(ap) => vec![self.atomic_proposition(fs, ap)],
+            Formula::And(f1, f2) => {
+                Self::conjoin(self.formula(fs, f1), self.formula(fs, f2))
+            }
+            Formula::Or(f1, f2) => {
+                let mut ret = self.formula(fs, f1);
+                ret.extend(self.formula(fs, f2));
+                ret
+            }
+            Formula::Not(f) => self.negated_formula(fs, f),
         }
     }
 
-    fn formula(&self, fs: &FunctionSignature, f: &Formula) -> Vec<Predicate> {
+    /// Compiles the negation of a formula to disjunctive normal form.
+    ///
+    /// Negations are pushed inward until they reach atomic propositions, which
+    /// are compiled to their complement relation (see
+    /// [`CompileContext::negated_relation`]). Since atomic propositions only
+    /// ever refer to props (which are never derived), the resulting negation
+    /// is always stratified.
+    fn negated_formula(
+        &self,
+        fs: &FunctionSignature,
+        f: &Formula,
+    ) -> Vec<Vec<Predicate>> {
         match f {
             Formula::True => vec![],
             Formula::Eq(left, right) => {
-                vec![Predicate::PrimEq(
-                    self.formula_atom(fs, left),
-                    self.formula_atom(fs, right),
-                )]
+                self.formula(fs, &Formula::Neq(left.clone(), right.clone()))
             }
             Formula::Neq(left, right) => {
-                vec![Predicate::PrimNeq(
-                    self.formula_atom(fs, left),
-                    self.formula_atom(fs, right),
-                )]
+                self.formula(fs, &Formula::Eq(left.clone(), right.clone()))
             }
             Formula::Lt(left, right) => {
-                vec![Predicate::PrimLt(
-                    self.formula_atom(fs, left),
-                    self.formula_atom(fs, right),
-                )]
+                self.formula(fs, &Formula::Ge(left.clone(), right.clone()))
             }
-            Formula::Ap(ap) => {
-                vec![self.atomic_proposition(fs, ap)]
+            Formula::Le(left, right) => {
+                self.formula(fs, &Formula::Gt(left.clone(), right.clone()))
             }
-            Formula::And(f1, f2) => self
-                .formula(fs, f1)
-                .into_iter()
-                .chain(self.formula(fs, f2))
+            Formula::Gt(left, right) => {
+                self.formula(fs, &Formula::Le(left.clone(), right.clone()))
+            }
+            Formula::Ge(left, right) => {
+                self.formula(fs, &Formula::Lt(left.clone(), right.clone()))
+            }
+            Formula::Ap(ap) => vec![self.negated_atomic_proposition(fs, ap)],
+            Formula::And(f1, f2) => {
+                let mut ret = self.negated_formula(fs, f1);
+                ret.extend(self.negated_formula(fs, f2));
+                ret
+            }
+            Formula::Or(f1, f2) => Self::conjoin(
+                self.negated_formula(fs, f1),
+                self.negated_formula(fs, f2),
+            ),
+            Formula::Not(f) => self.formula(fs, f),
+        }
+    }
+
+    /// Conjoins two formulas in disjunctive normal form.
+    fn conjoin(
+        left: Vec<Vec<Predicate>>,
+        right: Vec<Vec<Predicate>>,
+    ) -> Vec<Vec<Predicate>> {
+        let mut ret = vec![];
+        for l in &left {
+            for r in &right {
+                ret.push(l.iter().chain(r).cloned().collect())
+            }
+        }
+        ret
+    }
+
+    /// The complement relation for an atomic proposition; it holds for
+    /// exactly the values of the non-omitted arguments for which no prop
+    /// exists.
+    fn negated_relation(&self, ap: &AtomicProposition) -> Relation {
+        let sig = self.0 .0.props.get(&ap.name).unwrap();
+        Relation(format!(
+            "&Not_{}_{}",
+            ap.name.0,
+            sig.params
+                .keys()
+                .map(|mp| match ap.args.get(mp).unwrap() {
+                    Some(_) => "1",
+                    None => "0",
+                })
+                .collect::<String>()
+        ))
+    }
+
+    fn negated_atomic_proposition(
+        &self,
+        fs: &FunctionSignature,
+        ap: &AtomicProposition,
+    ) -> Vec<Predicate> {
+        let sig = self.0 .0.props.get(&ap.name).unwrap();
+        let mut ret = vec![];
+        let fact = Predicate::Fact(Fact {
+            relation: self.negated_relation(ap),
+            args: sig
+                .params
+                .keys()
+                .filter_map(|mp| {
+                    ap.args
+                        .get(mp)
+                        .unwrap()
+                        .as_ref()
+                        .map(|fa| Some(self.formula_atom(fs, fa, &mut ret)))
+                })
                 .collect(),
+        });
+        ret.push(fact);
+        ret
+    }
+
+    /// The complement relations needed by the library, along with the prop
+    /// and parameters they are the complement of.
+    fn negated_relations(
+        &self,
+    ) -> IndexMap<Relation, (MetName, Vec<MetParam>)> {
+        let mut ret = IndexMap::new();
+        for fs in self.0 .0.functions.values() {
+            for ap in fs.condition.negated_propositions() {
+                let sig = self.0 .0.props.get(&ap.name).unwrap();
+                ret.insert(
+                    self.negated_relation(ap),
+                    (
+                        ap.name.clone(),
+                        sig.params
+                            .keys()
+                            .filter(|mp| ap.args.get(*mp).unwrap().is_some())
+                            .cloned()
+                            .collect(),
+                    ),
+                );
+            }
+        }
+        ret
+    }
+
+    /// The ground facts for the complement relations (relative to a domain).
+    pub fn negated_facts(
+        &self,
+        props: &[Met<core::Value>],
+        dom: &IndexSet<core::Value>,
+    ) -> Vec<Fact> {
+        let mut facts = vec![];
+        for (rel, (name, mps)) in self.negated_relations() {
+            let sig = self.0 .0.props.get(&name).unwrap();
+
+            let present: IndexSet<Vec<core::Value>> = props
+                .iter()
+                .filter(|p| p.name == name)
+                .map(|p| {
+                    mps.iter()
+                        .map(|mp| p.args.get(mp).unwrap().clone())
+                        .collect()
+                })
+                .collect();
+
+            let mut tuples = vec![vec![]];
+            for mp in &mps {
+                let vt = sig.params.get(mp).unwrap();
+                let support: Vec<core::Value> = match vt {
+                    core::ValueType::Bool => {
+                        vec![core::Value::Bool(true), core::Value::Bool(false)]
+                    }
+                    core::ValueType::Enum(_) => vt.vals().into_iter().collect(),
+                    _ => dom
+                        .iter()
+                        .filter(|v| self.0.infer_value(v) == *vt)
+                        .cloned()
+                        .collect(),
+                };
+                tuples = tuples
+                    .into_iter()
+                    .flat_map(|t: Vec<core::Value>| {
+                        support.iter().map(move |v| {
+                            let mut t = t.clone();
+                            t.push(v.clone());
+                            t
+                        })
+                    })
+                    .collect();
+            }
+
+            for t in tuples {
+                if present.contains(&t) {
+                    continue;
+                }
+                facts.push(Fact {
+                    relation: rel.clone(),
+                    args: t.iter().map(|v| Some(self.value(v))).collect(),
+                });
+            }
         }
+        facts
     }
 
     pub fn signatures(&self) -> RelationLibrary {
@@ -159,6 +412,20 @@ impl CompileContext<'_> {
             );
         }
 
+        for (rel, (name, mps)) in self.negated_relations() {
+            let sig = self.0 .0.props.get(&name).unwrap();
+            lib.insert(
+                rel,
+                RelationSignature {
+                    params: mps
+                        .iter()
+                        .map(|mp| self.value_type(sig.params.get(mp).unwrap()))
+                        .collect(),
+                    kind: RelationKind::EDB,
+                },
+            );
+        }
+
         for (name, sig) in &self.0 .0.types {
             lib.insert(
                 Relation(name.0.clone()),
@@ -176,35 +443,53 @@ impl CompileContext<'_> {
         lib
     }
 
-    pub fn header(&self) -> Vec<Rule> {
-        self.0
-             .0
-            .functions
-            .iter()
-            .map(|(f, sig)| Rule {
-                name: f.0.clone(),
-                head: self.free_fact(&Self::ret(), &sig.ret),
-                body: sig
-                    .params
-                    .iter()
-                    .map(|(fp, mn)| Predicate::Fact(self.free_fact(fp, mn)))
-                    .chain(self.formula(sig, &sig.condition))
-                    .collect(),
-            })
-            .collect()
+    /// The header rules, one for each disjunct of each function condition
+    /// (along with the function that the rule comes from).
+    pub fn header(&self) -> Vec<(BaseFunction, Rule)> {
+        let mut rules = vec![];
+        for (f, sig) in &self.0 .0.functions {
+            let clauses = self.formula(sig, &sig.condition);
+            let multiple = clauses.len() > 1;
+            for (i, clause) in clauses.into_iter().enumerate() {
+                rules.push((
+                    f.clone(),
+                    Rule {
+                        name: if multiple {
+                            format!("{}#{}", f.0, i)
+                        } else {
+                            f.0.clone()
+                        },
+                        head: self.free_fact(&Self::ret(), &sig.ret),
+                        body: sig
+                            .params
+                            .iter()
+                            .map(|(fp, mn)| {
+                                Predicate::Fact(self.free_fact(fp, mn))
+                            })
+                            .chain(clause)
+                            .collect(),
+                    },
+                ))
+            }
+        }
+        rules
     }
 
+    /// Returns the alternative sets of premises of an expression (one for each
+    /// combination of condition disjuncts), or `None` if the expression does
+    /// not have the given type
+    #[allow(clippy::type_complexity)]
     pub fn premises(
         &self,
         e: &Exp,
         typ: &MetName,
         path: &FunParam,
-    ) -> Option<(Vec<Predicate>, Vec<(HoleName, usize, MetName)>)> {
+    ) -> Option<Vec<(Vec<Predicate>, Vec<(HoleName, usize, MetName)>)>> {
         match e {
-            Sketch::Hole(h) => Some((
+            Sketch::Hole(h) => Some(vec![(
                 vec![Predicate::Fact(self.free_fact(path, typ))],
                 vec![(*h, 0, typ.clone())],
-            )),
+            )]),
             Sketch::App(f, args) => {
                 let fs = self.0 .0.functions.get(&f.name).unwrap();
 
@@ -212,7 +497,7 @@ impl CompileContext<'_> {
                     return None;
                 }
 
-                let mut predicates = f
+                let metadata = f
                     .metadata
                     .iter()
                     .map(|(mp, v)| {
@@ -223,28 +508,50 @@ impl CompileContext<'_> {
                     })
                     .collect::<Vec<_>>();
 
-                predicates.extend(self.formula(fs, &fs.condition));
-
-                let mut options = vec![];
+                let mut alternatives = Self::conjoin(
+                    vec![metadata],
+                    self.formula(fs, &fs.condition),
+                )
+                .into_iter()
+                .map(|predicates| (predicates, vec![]))
+                .collect::<Vec<_>>();
 
                 for (fp, arg) in args {
-                    let offset = predicates.len();
                     let mn = fs.params.get(fp).unwrap();
-
                     let rec = self.premises(arg, mn, fp)?;
-                    predicates.extend(rec.0);
-                    options.extend(
-                        rec.1.into_iter().map(|(h, k, mn)| (h, k + offset, mn)),
-                    )
+
+                    let mut new_alternatives = vec![];
+                    for (predicates, options) in &alternatives {
+                        let offset = predicates.len();
+                        for (rec_predicates, rec_options) in &rec {
+                            let mut predicates = predicates.clone();
+                            predicates.extend(rec_predicates.iter().cloned());
+                            let mut options = options.clone();
+                            options.extend(rec_options.iter().map(
+                                |(h, k, mn)| (*h, k + offset, mn.clone()),
+                            ));
+                            new_alternatives.push((predicates, options));
+                        }
+                    }
+                    alternatives = new_alternatives;
                 }
 
-                Some((
-                    predicates
+                Some(
+                    alternatives
                         .into_iter()
-                        .map(|p| p.prefix_vars(&format!("{}*", path.0)))
+                        .map(|(predicates, options)| {
+                            (
+                                predicates
+                                    .into_iter()
+                                    .map(|p| {
+                                        p.prefix_vars(&format!("{}*", path.0))
+                                    })
+                                    .collect(),
+                                options,
+                            )
+                        })
                         .collect(),
-                    options,
-                ))
+                )
             }
         }
     }
@@ -255,44 +562,51 @@ impl CompileContext<'_> {
         e: &Exp,
         typ: &MetName,
     ) -> Vec<(Rule, RelationSignature, HoleName, usize)> {
-        let (body, options) =
+        let alternatives =
             match self.premises(e, typ, &FunParam("&root".to_owned())) {
                 Some(x) => x,
                 None => return vec![],
             };
 
-        options
-            .into_iter()
-            .map(|(h, k, mn)| {
+        let mut ret = vec![];
+
+        for (i, (body, options)) in alternatives.into_iter().enumerate() {
+            for (h, k, mn) in options {
                 let mut head = match &body[k] {
                     Predicate::Fact(fact) => fact.clone(),
                     _ => panic!(),
                 };
                 head.relation = Relation(format!("&Query_{}_{}", h, k));
-                (
+                ret.push((
                     Rule {
-                        name: format!("&query_{}_{}", h, k),
+                        name: format!("&query_{}_{}#{}", h, k, i),
                         head,
                         body: body.clone(),
                     },
-                    RelationSignature {
-                        params: self
-                            .0
-                             .0
-                            .types
-                            .get(&mn)
-                            .unwrap()
-                            .params
-                            .values()
-                            .map(|vt| self.value_type(vt))
-                            .collect(),
-                        kind: RelationKind::IDB,
-                    },
+                    self.query_signature(&mn),
                     h,
                     k,
-                )
-            })
-            .collect()
+                ))
+            }
+        }
+
+        ret
+    }
+
+    pub fn query_signature(&self, mn: &MetName) -> RelationSignature {
+        RelationSignature {
+            params: self
+                .0
+                 .0
+                .types
+                .get(mn)
+                .unwrap()
+                .params
+                .values()
+                .map(|vt| self.value_type(vt))
+                .collect(),
+            kind: RelationKind::IDB,
+        }
     }
 
     /// Assumes that all existing function applications do not need checking
@@ -334,43 +648,66 @@ impl CompileContext<'_> {
             )
         }));
 
-        prims.extend(self.formula(fs, &fs.condition));
+        let clauses =
+            Self::conjoin(vec![prims], self.formula(fs, &fs.condition));
 
-        heads
-            .into_iter()
-            .map(|(fp, mn, h, k)| {
+        let mut ret = vec![];
+
+        for (fp, mn, h, k) in heads {
+            for (i, clause) in clauses.iter().enumerate() {
                 let mut head = self.free_fact(&fp, &mn);
                 head.relation = Relation(format!("&Query_{}_{}", h, k));
-                (
+                ret.push((
                     Rule {
-                        name: format!("&query_{}_{}", h, k),
+                        name: format!("&query_{}_{}#{}", h, k, i),
                         head,
                         body: facts
-                            .clone()
-                            .into_iter()
+                            .iter()
+                            .cloned()
                             .map(Predicate::Fact)
-                            .chain(prims.clone())
-                            .collect(),
-                    },
-                    RelationSignature {
-                        params: self
-                            .0
-                             .0
-                            .types
-                            .get(&mn)
-                            .unwrap()
-                            .params
-                            .values()
-                            .map(|vt| self.value_type(vt))
+                            .chain(clause.iter().cloned())
                             .collect(),
-                        kind: RelationKind::IDB,
                     },
+                    self.query_signature(&mn),
                     h,
                     k,
-                )
-            })
-            .chain(rec_calls)
-            .collect()
+                ))
+            }
+        }
+
+        ret.extend(rec_calls);
+        ret
+    }
+
+    /// The forward query of a header rule: the rule with the fact for its
+    /// parameter `fp` replaced by the metadata of an application that is
+    /// passed as that argument (the head is the return metadata).
+    pub fn forward_query(
+        &self,
+        rule: &Rule,
+        fp: &FunParam,
+        mn: &MetName,
+        arg: &ParameterizedFunction,
+    ) -> Rule {
+        let arg_fact = Predicate::Fact(self.free_fact(fp, mn));
+        let mut head = rule.head.clone();
+        head.relation = Relation("&Forward".to_owned());
+        Rule {
+            name: format!("&forward_{}_{}", rule.name, fp.0),
+            head,
+            body: rule
+                .body
+                .iter()
+                .filter(|p| **p != arg_fact)
+                .cloned()
+                .chain(arg.metadata.iter().map(|(mp, v)| {
+                    Predicate::PrimEq(
+                        self.var(fp, mp, &self.0.infer_value(v)),
+                        self.value(v),
+                    )
+                }))
+                .collect(),
+        }
     }
 
     pub fn solvability_query(
@@ -398,6 +735,91 @@ impl CompileContext<'_> {
 
         (rule, relation_signature)
     }
+
+    /// The query for all groundings of a header rule of `f`: its head lists
+    /// the metadata of the return fact followed by those of each parameter
+    /// fact (in parameter order).
+    pub fn grounding_query(
+        &self,
+        f: &BaseFunction,
+        rule: &Rule,
+    ) -> (Rule, RelationSignature) {
+        let fs = self.0 .0.functions.get(f).unwrap();
+
+        let mut head = rule.head.clone();
+        head.relation = Relation("&Grounding".to_owned());
+        let mut params = self.query_signature(&fs.ret).params;
+
+        for (fp, mn) in &fs.params {
+            head.args.extend(self.free_fact(fp, mn).args);
+            params.extend(self.query_signature(mn).params);
+        }
+
+        (
+            Rule {
+                name: "&grounding".to_owned(),
+                head,
+                body: rule.body.clone(),
+            },
+            RelationSignature {
+                params,
+                kind: RelationKind::IDB,
+            },
+        )
+    }
+}
+
+/// Compiles a (well-typed) problem to the Datalog program that the oracle
+/// loads, also returning the header rules of the program.
+pub fn compile(
+    problem: &Problem,
+) -> Result<(Vec<(BaseFunction, Rule)>, datalog::Program), datalog::Error> {
+    let compile = CompileContext(typecheck::Context(&problem.library));
+
+    let header = compile.header();
+    let vals = problem.vals();
+
+    let datalog_program = datalog::Program::new(
+        compile.signatures(),
+        vals.iter().map(|v| compile.value(v)).collect(),
+        header.iter().map(|(_, rule)| rule.clone()).collect(),
+        problem
+            .program
+            .props
+            .iter()
+            .map(|f| compile.fact(f))
+            .chain(compile.negated_facts(&problem.program.props, &vals))
+            .collect(),
+    )?;
+
+    Ok((header, datalog_program))
+}
+
+/// The queries that the oracle starts with for a (well-typed) problem: the
+/// solvability queries for the goal types, followed by the queries for the
+/// holes of the start sketch.
+pub fn initial_queries(problem: &Problem) -> Vec<(Rule, RelationSignature)> {
+    let mut library = problem.library.clone();
+    let goal = Goal::new(&problem.program.goals);
+    goal.add_to_library(&mut library.functions);
+
+    let compile = CompileContext(typecheck::Context(&library));
+    let mut queries: Vec<_> = problem
+        .program
+        .goals
+        .iter()
+        .map(|g| compile.solvability_query(&g.name))
+        .collect();
+
+    let (goal_pf, goal_args) = goal.app(&problem.start());
+    queries.extend(
+        compile
+            .fast_queries(&goal_pf, &goal_args)
+            .into_iter()
+            .map(|(rule, sig, _, _)| (rule, sig)),
+    );
+
+    queries
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -411,21 +833,305 @@ mod decompile {
         match v {
             datalog::Value::Bool(b) => core::Value::Bool(*b),
             datalog::Value::Int(x) => core::Value::Int(*x),
+            datalog::Value::Float(x) => core::Value::Float(*x),
             datalog::Value::Str(s) => core::Value::Str(s.clone()),
             datalog::Value::Var { .. } => panic!(),
         }
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// Explanations
+
+/// How many levels of parameters to explain the failing derivations of.
+const EXPLANATION_DEPTH: usize = 3;
+
+/// The reasons that a derivation can be blocked.
+#[derive(Debug, Clone, Serialize)]
+pub enum Blocker {
+    /// The constraints imposed by the rest of the sketch are unsatisfiable
+    Context,
+    /// No value of the type of a parameter can be derived (subject to the
+    /// constraints satisfied so far), along with the nearest failing
+    /// derivations of that type
+    Param {
+        param: FunParam,
+        typ: MetName,
+        derivations: Vec<Derivation>,
+    },
+    /// A condition of the function (indexed by its position in the list of
+    /// conditions; see [`Formula::conditions`]) cannot be satisfied
+    Condition { index: usize, condition: String },
+}
+
+/// A failing derivation of a type by a function, along with what blocks it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Derivation {
+    pub function: BaseFunction,
+    pub blockers: Vec<Blocker>,
+}
+
+/// An explanation for why a hole has no expansions, in terms of its nearest
+/// failing derivations (those with the fewest blockers).
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub hole: HoleName,
+    pub typ: MetName,
+    pub derivations: Vec<Derivation>,
+}
+
+fn satisfiable<Eng: Engine>(
+    engine: &mut Eng,
+    head: &Fact,
+    sig: &RelationSignature,
+    alternatives: &[Vec<Predicate>],
+) -> Result<bool, datalog::Error> {
+    for body in alternatives {
+        let rule = Rule {
+            name: "&explain".to_owned(),
+            head: head.clone(),
+            body: body.clone(),
+        };
+        if !engine.query(sig, &rule)?.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Keeps only the nearest failing derivations (or none at all if some
+/// derivation is not actually blocked).
+fn nearest(derivations: Vec<Derivation>) -> Vec<Derivation> {
+    let min = match derivations.iter().map(|d| d.blockers.len()).min() {
+        Some(0) | None => return vec![],
+        Some(min) => min,
+    };
+    derivations
+        .into_iter()
+        .filter(|d| d.blockers.len() == min)
+        .collect()
+}
+
+impl CompileContext<'_> {
+    /// Finds the failing derivations of the fact at position `k` of a query
+    /// body.
+    ///
+    /// Each function that returns the type of the fact is tried in turn by
+    /// greedily adding its conditions and then its parameters to the query;
+    /// whatever cannot be added without making the query unsatisfiable is a
+    /// blocker of the derivation. Conditions come first so that a missing
+    /// parameter is blamed (and explained) rather than the conditions that
+    /// constrain it.
+    fn explain<Eng: Engine>(
+        &self,
+        engine: &mut Eng,
+        query: &Rule,
+        k: usize,
+        depth: usize,
+    ) -> Result<Vec<Derivation>, datalog::Error> {
+        let typ = match &query.body[k] {
+            Predicate::Fact(f) => MetName(f.relation.0.clone()),
+            _ => panic!("explained predicate must be a fact"),
+        };
+        let sig = self.query_signature(&typ);
+        let prefix = |p: Predicate| p.prefix_vars(Rule::X_PREFIX);
+
+        let mut derivations = vec![];
+
+        for (f, fs) in &self.0 .0.functions {
+            if fs.ret != typ {
+                continue;
+            }
+
+            let base = query
+                .cut(
+                    &Rule {
+                        name: format!("&explain_{}", f.0),
+                        head: self.free_fact(&Self::ret(), &typ),
+                        body: vec![],
+                    },
+                    k,
+                )
+                .unwrap();
+
+            let mut alternatives = vec![base.body];
+            let mut blockers = vec![];
+
+            if !satisfiable(engine, &base.head, &sig, &alternatives)? {
+                derivations.push(Derivation {
+                    function: f.clone(),
+                    blockers: vec![Blocker::Context],
+                });
+                continue;
+            }
+
+            for (index, phi) in
+                fs.condition.conditions().into_iter().enumerate()
+            {
+                let clauses = self
+                    .formula(fs, phi)
+                    .into_iter()
+                    .map(|clause| clause.into_iter().map(prefix).collect())
+                    .collect();
+                let candidate = Self::conjoin(alternatives.clone(), clauses);
+
+                if satisfiable(engine, &base.head, &sig, &candidate)? {
+                    alternatives = candidate;
+                } else {
+                    blockers.push(Blocker::Condition {
+                        index,
+                        condition: unparse::formula(phi),
+                    });
+                }
+            }
+
+            for (fp, mn) in &fs.params {
+                let fact = prefix(Predicate::Fact(self.free_fact(fp, mn)));
+                let candidate = Self::conjoin(
+                    alternatives.clone(),
+                    vec![vec![fact.clone()]],
+                );
+
+                if satisfiable(engine, &base.head, &sig, &candidate)? {
+                    alternatives = candidate;
+                    continue;
+                }
+
+                let derivations = if depth == 0 {
+                    vec![]
+                } else {
+                    let mut body = alternatives[0].clone();
+                    body.push(fact.clone());
+                    let mut head = match fact {
+                        Predicate::Fact(fact) => fact,
+                        _ => unreachable!(),
+                    };
+                    head.relation = Relation("&Explain".to_owned());
+                    let k = body.len() - 1;
+                    self.explain(
+                        engine,
+                        &Rule {
+                            name: "&explain".to_owned(),
+                            head,
+                            body,
+                        },
+                        k,
+                        depth - 1,
+                    )?
+                };
+
+                blockers.push(Blocker::Param {
+                    param: fp.clone(),
+                    typ: mn.clone(),
+                    derivations,
+                });
+            }
+
+            derivations.push(Derivation {
+                function: f.clone(),
+                blockers,
+            });
+        }
+
+        Ok(nearest(derivations))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Witnesses
+
+/// Converts the derivation tree of a type fact to the (complete) expression
+/// that it corresponds to; the arguments of a function application are the
+/// derivation trees of the parameter facts that begin its header rule.
+fn witness_exp(
+    library: &Library,
+    header: &[(BaseFunction, Rule)],
+    f: ParameterizedFunction,
+    children: &[DerivationTree],
+) -> Exp {
+    let fs = library.functions.get(&f.name).unwrap();
+    let args =
+        fs.params
+            .keys()
+            .cloned()
+            .zip(children.iter().map(|child| {
+                let (g, _) = header
+                    .iter()
+                    .find(|(_, rule)| Some(&rule.name) == child.rule.as_ref())
+                    .unwrap();
+                let gs = library.functions.get(g).unwrap();
+                let g_ret_sig = library.types.get(&gs.ret).unwrap();
+                let pf =
+                    ParameterizedFunction::from_sig(
+                        gs,
+                        g.clone(),
+                        g_ret_sig
+                            .params
+                            .keys()
+                            .cloned()
+                            .zip(child.fact.args.iter().map(|ov| {
+                                decompile::value(ov.as_ref().unwrap())
+                            }))
+                            .collect(),
+                    );
+                witness_exp(library, header, pf, &child.children)
+            }))
+            .collect();
+    Sketch::App(f, args)
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Oracle
 
+/// The cached query results for the holes of the last sketch whose expansions
+/// were computed, keyed by hole and then by the body of each query for it.
+///
+/// The body of a query compiles exactly the part of a sketch that the
+/// expansions of a hole depend on (the function application directly above the
+/// hole along with the metadata of its siblings), so a navigation step only
+/// changes the queries of the holes that it affects: the holes it introduces
+/// and the siblings of the hole it fills. Only the results for the queries of
+/// the latest sketch are kept, so the results for the holes that a step
+/// affects are invalidated by that step.
+type Cache =
+    IndexMap<HoleName, IndexMap<Vec<Predicate>, Vec<ParameterizedFunction>>>;
+
+/// A fact that some function can derive: its type along with its metadata (in
+/// the order of the arguments of its query).
+type GroundFact = (MetName, Vec<datalog::Value>);
+
+/// The groundings of the header rules, indexed by the facts they derive: the
+/// function that each one comes from along with the facts of its parameters.
+///
+/// Distinct groundings of a fact correspond to disjoint sets of complete
+/// expressions (rooted at different functions or with differently-typed
+/// arguments), which is what makes them suitable for counting.
+type Groundings =
+    IndexMap<GroundFact, IndexSet<(BaseFunction, Vec<GroundFact>)>>;
+
+/// The minimal total costs of complete expressions, both for each derivable
+/// type fact and for each function along with the metadata it returns.
+///
+/// The cost of an expression is the sum of the costs of the functions it
+/// applies, so these are computed by relaxing the groundings of the header
+/// rules until a fixpoint (as in Bellman-Ford, this terminates because costs
+/// are non-negative).
+#[derive(Default)]
+struct Costs {
+    facts: IndexMap<GroundFact, f64>,
+    functions: IndexMap<(BaseFunction, Vec<datalog::Value>), f64>,
+}
+
 /// A datalog-backed inhabitation oracle
 pub struct Oracle<Eng: Engine> {
     engine: Eng,
     problem: Problem,
-    header: Vec<Rule>,
+    header: Vec<(BaseFunction, Rule)>,
     goal: Goal,
+    cache: Option<Cache>,
+    groundings: Option<Groundings>,
+    costs: Option<Costs>,
+    counts: IndexMap<GroundFact, util::Count>,
 }
 
 impl<Eng: Engine> Oracle<Eng> {
@@ -434,25 +1140,11 @@ impl<Eng: Engine> Oracle<Eng> {
         mut engine: Eng,
         mut problem: Problem,
     ) -> Result<Self, datalog::Error> {
-        let compile = CompileContext(typecheck::Context(&problem.library));
-
-        let header = compile.header();
-
-        let datalog_program = datalog::Program::new(
-            compile.signatures(),
-            problem.vals().iter().map(|v| compile.value(v)).collect(),
-            header.clone(),
-            problem
-                .program
-                .props
-                .iter()
-                .map(|f| compile.fact(f))
-                .collect(),
-        )?;
+        let (header, datalog_program) = compile(&problem)?;
 
-        engine.load(datalog_program);
+        engine.load(datalog_program)?;
 
-        let goal = Goal::new(&problem.program.goal);
+        let goal = Goal::new(&problem.program.goals);
         goal.add_to_library(&mut problem.library.functions);
 
         Ok(Self {
@@ -460,20 +1152,57 @@ impl<Eng: Engine> Oracle<Eng> {
             problem,
             header,
             goal,
+            cache: None,
+            groundings: None,
+            costs: None,
+            counts: IndexMap::new(),
         })
     }
 
+    /// Switch the oracle to incremental mode, in which query results are
+    /// cached from one call to [`InhabitationOracle::expansions`] to the next
+    /// so that only the holes affected by the latest navigation step are
+    /// re-queried (see [`Cache`]).
+    pub fn incremental(mut self) -> Self {
+        self.cache = Some(Cache::new());
+        self
+    }
+
+    /// Binds the fixed metadata of a goal, in the order of the arguments of
+    /// its solvability query.
+    fn bind_goal(&self, goal: &PartialType) -> Vec<Option<datalog::Value>> {
+        let goal_sig = self.problem.library.types.get(&goal.name).unwrap();
+        let compile = CompileContext(typecheck::Context(&self.problem.library));
+
+        goal_sig
+            .params
+            .keys()
+            .map(|mp| {
+                goal.args
+                    .get(mp)
+                    .cloned()
+                    .flatten()
+                    .map(|v| compile.value(&v))
+            })
+            .collect()
+    }
+
+    /// Returns the metadata for which a goal is solvable, restricted to those
+    /// that agree with the fixed metadata of the goal.
     pub fn valid_goal_metadata(
         &mut self,
-    ) -> Vec<IndexMap<MetParam, core::Value>> {
-        let goal = &self.problem.program.goal;
+        goal: &PartialType,
+    ) -> Result<Vec<IndexMap<MetParam, core::Value>>, datalog::Error> {
         let goal_sig = self.problem.library.types.get(&goal.name).unwrap();
 
         let compile = CompileContext(typecheck::Context(&self.problem.library));
         let (query, query_sig) = compile.solvability_query(&goal.name);
 
-        self.engine
-            .query(&query_sig, &query)
+        let bound = self.bind_goal(goal);
+
+        Ok(self
+            .engine
+            .query_bound(&query_sig, &query, &bound)?
             .into_iter()
             .map(|vals| {
                 goal_sig
@@ -483,12 +1212,355 @@ impl<Eng: Engine> Oracle<Eng> {
                     .zip(vals.iter().map(decompile::value))
                     .collect()
             })
-            .collect()
+            .collect())
     }
-}
-
-impl<Eng: Engine> InhabitationOracle for Oracle<Eng> {
-    type F = ParameterizedFunction;
+
+    /// Returns whether a goal is solvable for some metadata that agree with
+    /// the fixed metadata of the goal.
+    pub fn goal_solvable(
+        &mut self,
+        goal: &PartialType,
+    ) -> Result<bool, datalog::Error> {
+        let compile = CompileContext(typecheck::Context(&self.problem.library));
+        let (query, query_sig) = compile.solvability_query(&goal.name);
+
+        let bound = self.bind_goal(goal);
+
+        self.engine.exists(&query_sig, &query, &bound)
+    }
+
+    /// Returns a witness for a parameterized function: a complete expression
+    /// rooted at the function that shows how the props make it valid (or
+    /// `None` if there is no such expression).
+    pub fn witness(
+        &mut self,
+        f: &ParameterizedFunction,
+    ) -> Result<Option<Exp>, datalog::Error> {
+        let compile = CompileContext(typecheck::Context(&self.problem.library));
+        let fs = match self.problem.library.functions.get(&f.name) {
+            Some(fs) => fs,
+            None => return Ok(None),
+        };
+
+        for (g, rule) in &self.header {
+            if *g != f.name {
+                continue;
+            }
+
+            let mut query = rule.clone();
+            query.name = "&provenance".to_owned();
+            query.head.relation = Relation("&Provenance".to_owned());
+            query.body.extend(f.metadata.iter().map(|(mp, v)| {
+                Predicate::PrimEq(
+                    compile.var(
+                        &CompileContext::ret(),
+                        mp,
+                        &compile.0.infer_value(v),
+                    ),
+                    compile.value(v),
+                )
+            }));
+
+            let query_sig = compile.query_signature(&fs.ret);
+
+            if let Some((_, tree)) = self
+                .engine
+                .query_with_provenance(&query_sig, &query)?
+                .into_iter()
+                .next()
+            {
+                return Ok(Some(witness_exp(
+                    &self.problem.library,
+                    &self.header,
+                    f.clone(),
+                    &tree.children[..fs.params.len()],
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Explain why the holes of a sketch that have no expansions cannot be
+    /// filled (explaining the goal itself if the sketch is blank).
+    pub fn explain(
+        &mut self,
+        e: &Sketch<ParameterizedFunction>,
+    ) -> Result<Vec<Explanation>, datalog::Error> {
+        // Without a time limit, computing expansions can only fail in the
+        // engine itself
+        let filled: IndexSet<HoleName> = self
+            .expansions(&util::Timer::infinite(), e)
+            .map_err(|e| match e {
+                util::EarlyCutoff::Engine(e) => e,
+                e => datalog::Error::Backend(e.to_string()),
+            })?
+            .into_iter()
+            .map(|(h, _)| h)
+            .collect();
+
+        let compile = CompileContext(typecheck::Context(&self.problem.library));
+        let (goal_pf, goal_args) = self.goal.app(e);
+
+        let mut explanations: IndexMap<HoleName, Explanation> = IndexMap::new();
+
+        for (query, _, h, k) in compile.fast_queries(&goal_pf, &goal_args) {
+            if filled.contains(&h) {
+                continue;
+            }
+
+            let derivations = compile.explain(
+                &mut self.engine,
+                &query,
+                k,
+                EXPLANATION_DEPTH,
+            )?;
+
+            let explanation =
+                explanations.entry(h).or_insert_with(|| Explanation {
+                    hole: h,
+                    typ: match &query.body[k] {
+                        Predicate::Fact(f) => MetName(f.relation.0.clone()),
+                        _ => unreachable!(),
+                    },
+                    derivations: vec![],
+                });
+
+            explanation.derivations.extend(derivations);
+        }
+
+        Ok(explanations
+            .into_values()
+            .map(|mut explanation| {
+                explanation.derivations = nearest(explanation.derivations);
+                explanation
+            })
+            .collect())
+    }
+}
+
+impl<Eng: Engine> Oracle<Eng> {
+    /// Computes the groundings of the header rules (only once, since they do
+    /// not depend on the working expression).
+    fn groundings(&mut self) -> Result<&Groundings, datalog::Error> {
+        if self.groundings.is_none() {
+            let compile =
+                CompileContext(typecheck::Context(&self.problem.library));
+
+            let mut groundings = Groundings::new();
+            for (f, rule) in &self.header {
+                let fs = self.problem.library.functions.get(f).unwrap();
+                let ret_arity = compile.query_signature(&fs.ret).params.len();
+                let (query, query_sig) = compile.grounding_query(f, rule);
+
+                for vals in self.engine.query(&query_sig, &query)? {
+                    let mut rest = vals[ret_arity..].iter();
+                    let params: Vec<_> = fs
+                        .params
+                        .values()
+                        .map(|mn| {
+                            let arity =
+                                compile.query_signature(mn).params.len();
+                            (
+                                mn.clone(),
+                                rest.by_ref().take(arity).cloned().collect(),
+                            )
+                        })
+                        .collect();
+                    groundings
+                        .entry((fs.ret.clone(), vals[..ret_arity].to_vec()))
+                        .or_default()
+                        .insert((f.clone(), params));
+                }
+            }
+
+            self.groundings = Some(groundings);
+        }
+
+        Ok(self.groundings.as_ref().unwrap())
+    }
+
+    /// Computes the minimal total costs of complete expressions (only once,
+    /// since they do not depend on the working expression).
+    fn costs(&mut self) -> Result<&Costs, datalog::Error> {
+        if self.costs.is_none() {
+            self.groundings()?;
+            let groundings = self.groundings.as_ref().unwrap();
+            let library = &self.problem.library;
+
+            let mut costs = Costs::default();
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for (ret, gs) in groundings {
+                    for (f, params) in gs {
+                        let cost = library.functions.get(f).unwrap().cost();
+                        let total =
+                            match params.iter().try_fold(cost, |acc, p| {
+                                costs.facts.get(p).map(|c| acc + c)
+                            }) {
+                                Some(total) => total,
+                                None => continue,
+                            };
+                        if costs.facts.get(ret).is_none_or(|c| total < *c) {
+                            costs.facts.insert(ret.clone(), total);
+                            changed = true;
+                        }
+                        let key = (f.clone(), ret.1.clone());
+                        if costs.functions.get(&key).is_none_or(|c| total < *c)
+                        {
+                            costs.functions.insert(key, total);
+                        }
+                    }
+                }
+            }
+
+            self.costs = Some(costs);
+        }
+
+        Ok(self.costs.as_ref().unwrap())
+    }
+
+    /// Returns the number of complete expressions that derive a fact (the
+    /// count is infinite if the fact is on a cycle of derivations, or depends
+    /// on one).
+    ///
+    /// Counts are computed by depth-first search over the groundings: a fact
+    /// that is reached again while it is still being counted is on a cycle.
+    fn count(
+        groundings: &Groundings,
+        counts: &mut IndexMap<GroundFact, util::Count>,
+        visiting: &mut IndexSet<GroundFact>,
+        fact: &GroundFact,
+    ) -> util::Count {
+        if let Some(c) = counts.get(fact) {
+            return *c;
+        }
+
+        if !visiting.insert(fact.clone()) {
+            return util::Count::Infinite;
+        }
+
+        let mut total = util::Count::Finite(0);
+        for (_, params) in groundings.get(fact).into_iter().flatten() {
+            total = total
+                + params.iter().fold(util::Count::Finite(1), |acc, p| {
+                    acc * Self::count(groundings, counts, visiting, p)
+                });
+        }
+
+        visiting.pop();
+        counts.insert(fact.clone(), total);
+        total
+    }
+
+    /// Returns the ground fact that a parameterized function derives (or
+    /// `None` if it is not a library function).
+    fn ground_fact(
+        &self,
+        f: &ParameterizedFunction,
+    ) -> Option<(BaseFunction, GroundFact)> {
+        let compile = CompileContext(typecheck::Context(&self.problem.library));
+        let fs = self.problem.library.functions.get(&f.name)?;
+        let ret_sig = self.problem.library.types.get(&fs.ret).unwrap();
+
+        let vals = ret_sig
+            .params
+            .keys()
+            .map(|mp| f.metadata.get(mp).map(|v| compile.value(v)))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some((f.name.clone(), (fs.ret.clone(), vals)))
+    }
+
+    /// Returns the minimal total cost of a complete expression rooted at a
+    /// parameterized function (or `None` if there is no such expression).
+    pub fn completion_cost(
+        &mut self,
+        f: &ParameterizedFunction,
+    ) -> Result<Option<f64>, datalog::Error> {
+        let (name, (_, vals)) = match self.ground_fact(f) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        Ok(self.costs()?.functions.get(&(name, vals)).cloned())
+    }
+
+    /// Returns the number of complete expressions rooted at a parameterized
+    /// function (or `None` if it is not a library function).
+    pub fn completion_count(
+        &mut self,
+        f: &ParameterizedFunction,
+    ) -> Result<Option<util::Count>, datalog::Error> {
+        let (name, fact) = match self.ground_fact(f) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        self.groundings()?;
+        let groundings = self.groundings.as_ref().unwrap();
+
+        let mut total = util::Count::Finite(0);
+        let mut visiting = IndexSet::from([fact.clone()]);
+        for (g, params) in groundings.get(&fact).into_iter().flatten() {
+            if *g != name {
+                continue;
+            }
+            total = total
+                + params.iter().fold(util::Count::Finite(1), |acc, p| {
+                    acc * Self::count(
+                        groundings,
+                        &mut self.counts,
+                        &mut visiting,
+                        p,
+                    )
+                });
+        }
+
+        Ok(Some(total))
+    }
+
+    /// Returns the minimal total cost of completing the hole that each step
+    /// fills (reusing an expression costs nothing); steps that do not fill a
+    /// hole have no cost.
+    pub fn step_costs(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<f64>>, datalog::Error> {
+        steps
+            .iter()
+            .map(|step| match step {
+                TopDownStep::Extend(_, f, _) => self.completion_cost(f),
+                TopDownStep::Reuse(..) => Ok(Some(0.0)),
+                TopDownStep::Retract(..)
+                | TopDownStep::Wrap(..)
+                | TopDownStep::Seq(..) => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Returns the number of complete expressions that can fill the hole that
+    /// each step fills (a reused expression is the only one); steps that do
+    /// not fill a hole have no count.
+    pub fn step_counts(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<util::Count>>, datalog::Error> {
+        steps
+            .iter()
+            .map(|step| match step {
+                TopDownStep::Extend(_, f, _) => self.completion_count(f),
+                TopDownStep::Reuse(..) => Ok(Some(util::Count::Finite(1))),
+                TopDownStep::Retract(..)
+                | TopDownStep::Wrap(..)
+                | TopDownStep::Seq(..) => Ok(None),
+            })
+            .collect()
+    }
+}
+
+impl<Eng: Engine> InhabitationOracle for Oracle<Eng> {
+    type F = ParameterizedFunction;
 
     fn expansions(
         &mut self,
@@ -518,45 +1590,1114 @@ impl<Eng: Engine> InhabitationOracle for Oracle<Eng> {
                 .join(", ")
         );
 
+        let mut cache = Cache::new();
+
         for (query, query_sig, h, k) in queries {
+            if let Some(fs) = self
+                .cache
+                .as_ref()
+                .and_then(|c| c.get(&h))
+                .and_then(|c| c.get(&query.body))
+            {
+                log::debug!("Using cached query with (h={h:}, k={k:})");
+                for f in fs {
+                    let expansion = (h, f.clone());
+                    if !ret.contains(&expansion) {
+                        ret.push(expansion);
+                    }
+                }
+                cache.entry(h).or_default().insert(query.body, fs.clone());
+                continue;
+            }
+
+            let mut fs = vec![];
+
             log::debug!("Trying query with (h={h:}, k={k:}):\n{query:#?}");
-            for rule in &self.header {
+            for (f, rule) in &self.header {
                 log::debug!("Trying header rule '{}'", rule.name);
                 timer.tick()?;
                 if let Some(cut_rule) = query.cut(rule, k) {
                     log::debug!("Header rule '{}' matches", rule.name);
-                    let f = BaseFunction(rule.name.clone());
-                    let f_sig = self.problem.library.functions.get(&f).unwrap();
+                    let f_sig = self.problem.library.functions.get(f).unwrap();
                     let f_ret_sig =
                         self.problem.library.types.get(&f_sig.ret).unwrap();
 
-                    ret.extend(
-                        self.engine
-                            .query(&query_sig, &cut_rule)
-                            .into_iter()
-                            .map(|vals| {
-                                (
-                                    h,
-                                    ParameterizedFunction::from_sig(
-                                        f_sig,
-                                        f.clone(),
-                                        f_ret_sig
-                                            .params
-                                            .keys()
-                                            .cloned()
-                                            .zip(
-                                                vals.iter()
-                                                    .map(decompile::value),
-                                            )
-                                            .collect(),
-                                    ),
-                                )
-                            }),
-                    )
+                    for vals in self.engine.query(&query_sig, &cut_rule)? {
+                        let pf = ParameterizedFunction::from_sig(
+                            f_sig,
+                            f.clone(),
+                            f_ret_sig
+                                .params
+                                .keys()
+                                .cloned()
+                                .zip(vals.iter().map(decompile::value))
+                                .collect(),
+                        );
+
+                        if !fs.contains(&pf) {
+                            fs.push(pf);
+                        }
+                    }
+                }
+            }
+
+            // Multiple disjuncts (header rules or query clauses) may yield the
+            // same expansion
+            for f in &fs {
+                let expansion = (h, f.clone());
+                if !ret.contains(&expansion) {
+                    ret.push(expansion);
                 }
             }
+
+            cache.entry(h).or_default().insert(query.body, fs);
+        }
+
+        if let Some(c) = &mut self.cache {
+            *c = cache;
         }
 
         Ok(ret)
     }
 }
+
+impl<Eng: Engine> Oracle<Eng> {
+    /// Returns whether an application of `f` can take an application of `arg`
+    /// as its `fp` argument (according to the forward queries of the header
+    /// rules of `f`).
+    fn forward(
+        &mut self,
+        f: &ParameterizedFunction,
+        fp: &FunParam,
+        arg: &ParameterizedFunction,
+    ) -> Result<bool, datalog::Error> {
+        let compile = CompileContext(typecheck::Context(&self.problem.library));
+        let fs = self.problem.library.functions.get(&f.name).unwrap();
+        let mn = fs.params.get(fp).unwrap();
+        let ret_sig = self.problem.library.types.get(&fs.ret).unwrap();
+
+        let query_sig = compile.query_signature(&fs.ret);
+        let bound: Vec<_> = ret_sig
+            .params
+            .keys()
+            .map(|mp| f.metadata.get(mp).map(|v| compile.value(v)))
+            .collect();
+
+        for (g, rule) in &self.header {
+            if *g != f.name {
+                continue;
+            }
+            let query = compile.forward_query(rule, fp, mn, arg);
+            if self.engine.exists(&query_sig, &query, &bound)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl<Eng: Engine> CostOracle for Oracle<Eng> {
+    fn application_cost(&self, f: &Self::F) -> f64 {
+        self.problem
+            .library
+            .functions
+            .get(&f.name)
+            .map_or(0.0, |fs| fs.cost())
+    }
+
+    fn minimal_cost(
+        &mut self,
+        _timer: &util::Timer,
+        f: &Self::F,
+    ) -> Result<Option<f64>, util::EarlyCutoff> {
+        Ok(self.completion_cost(f)?)
+    }
+}
+
+impl<Eng: Engine> Oracle<Eng> {
+    /// Returns the inputs of a problem: the applications of nullary functions
+    /// that are valid according to the props.
+    fn inputs(&mut self) -> Result<Vec<Exp>, datalog::Error> {
+        self.groundings()?;
+        let library = &self.problem.library;
+
+        let mut ret = vec![];
+        for ((mn, vals), gs) in self.groundings.as_ref().unwrap() {
+            let ret_sig = library.types.get(mn).unwrap();
+            for (f, params) in gs {
+                if !params.is_empty() {
+                    continue;
+                }
+                let fs = library.functions.get(f).unwrap();
+                ret.push(Sketch::App(
+                    ParameterizedFunction::from_sig(
+                        fs,
+                        f.clone(),
+                        ret_sig
+                            .params
+                            .keys()
+                            .cloned()
+                            .zip(vals.iter().map(decompile::value))
+                            .collect(),
+                    ),
+                    IndexMap::new(),
+                ));
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+impl<Eng: Engine> ForwardOracle for Oracle<Eng> {
+    type F = ParameterizedFunction;
+
+    /// The expressions that can be attached below a new application are the
+    /// ground sub-expressions of the working expression and the inputs of the
+    /// problem; any valid expansion of a hole that takes the type of such an
+    /// expression can be attached above it (filling the hole) if its forward
+    /// query succeeds for the expression.
+    ///
+    /// The expansions are those of the working expression itself, so they
+    /// come from the same cache as the ones that top-down synthesis provides.
+    fn attachments(
+        &mut self,
+        timer: &util::Timer,
+        e: &Sketch<Self::F>,
+    ) -> Result<Vec<Attachment<Self::F>>, util::EarlyCutoff> {
+        let mut subs: Vec<Exp> = e
+            .ground_subterms()
+            .into_iter()
+            .filter(|sub| match sub {
+                Sketch::App(g, _) => g.name != self.goal.function,
+                Sketch::Hole(_) => false,
+            })
+            .cloned()
+            .collect();
+        for input in self.inputs()? {
+            if !subs.contains(&input) {
+                subs.push(input);
+            }
+        }
+
+        let mut ret = vec![];
+
+        for (h, f) in self.expansions(timer, e)? {
+            let fs = self.problem.library.functions.get(&f.name).unwrap();
+            let params: Vec<_> = fs
+                .params
+                .iter()
+                .map(|(fp, mn)| (fp.clone(), mn.clone()))
+                .collect();
+            for (fp, mn) in params {
+                for sub in &subs {
+                    timer.tick()?;
+                    let arg = match sub {
+                        Sketch::App(g, _) => g,
+                        Sketch::Hole(_) => unreachable!(),
+                    };
+                    let typ = &self
+                        .problem
+                        .library
+                        .functions
+                        .get(&arg.name)
+                        .unwrap()
+                        .ret;
+                    if *typ == mn && self.forward(&f, &fp, arg)? {
+                        ret.push((h, f.clone(), fp.clone(), sub.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+/// The questions about a problem (other than its expansions) that frontends
+/// ask of a Datalog-backed oracle, independent of its Datalog engine.
+pub trait Queries {
+    /// See [`Oracle::explain`]
+    fn explain(
+        &mut self,
+        e: &Sketch<ParameterizedFunction>,
+    ) -> Result<Vec<Explanation>, datalog::Error>;
+
+    /// See [`Oracle::witness`]
+    fn witness(
+        &mut self,
+        f: &ParameterizedFunction,
+    ) -> Result<Option<Exp>, datalog::Error>;
+
+    /// See [`Oracle::step_costs`]
+    fn step_costs(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<f64>>, datalog::Error>;
+
+    /// See [`Oracle::step_counts`]
+    fn step_counts(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<util::Count>>, datalog::Error>;
+}
+
+impl<Eng: Engine> Queries for Oracle<Eng> {
+    fn explain(
+        &mut self,
+        e: &Sketch<ParameterizedFunction>,
+    ) -> Result<Vec<Explanation>, datalog::Error> {
+        Oracle::explain(self, e)
+    }
+
+    fn witness(
+        &mut self,
+        f: &ParameterizedFunction,
+    ) -> Result<Option<Exp>, datalog::Error> {
+        Oracle::witness(self, f)
+    }
+
+    fn step_costs(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<f64>>, datalog::Error> {
+        Oracle::step_costs(self, steps)
+    }
+
+    fn step_counts(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<util::Count>>, datalog::Error> {
+        Oracle::step_counts(self, steps)
+    }
+}
+
+/// An oracle that is shared between a controller (which navigates with it)
+/// and a frontend (which asks it [`Queries`]), so that both use the same
+/// engine and caches.
+pub struct Shared<O>(Rc<RefCell<O>>);
+
+impl<O> Shared<O> {
+    pub fn new(oracle: O) -> Self {
+        Self(Rc::new(RefCell::new(oracle)))
+    }
+}
+
+impl<O> Clone for Shared<O> {
+    fn clone(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+}
+
+impl<O: InhabitationOracle> InhabitationOracle for Shared<O> {
+    type F = O::F;
+
+    fn expansions(
+        &mut self,
+        timer: &util::Timer,
+        e: &Sketch<Self::F>,
+    ) -> Result<Vec<Expansion<Self::F>>, util::EarlyCutoff> {
+        self.0.borrow_mut().expansions(timer, e)
+    }
+}
+
+impl<O: ForwardOracle> ForwardOracle for Shared<O> {
+    type F = O::F;
+
+    fn attachments(
+        &mut self,
+        timer: &util::Timer,
+        e: &Sketch<Self::F>,
+    ) -> Result<Vec<Attachment<Self::F>>, util::EarlyCutoff> {
+        self.0.borrow_mut().attachments(timer, e)
+    }
+}
+
+impl<O: Queries> Queries for Shared<O> {
+    fn explain(
+        &mut self,
+        e: &Sketch<ParameterizedFunction>,
+    ) -> Result<Vec<Explanation>, datalog::Error> {
+        self.0.borrow_mut().explain(e)
+    }
+
+    fn witness(
+        &mut self,
+        f: &ParameterizedFunction,
+    ) -> Result<Option<Exp>, datalog::Error> {
+        self.0.borrow_mut().witness(f)
+    }
+
+    fn step_costs(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<f64>>, datalog::Error> {
+        self.0.borrow_mut().step_costs(steps)
+    }
+
+    fn step_counts(
+        &mut self,
+        steps: &[TopDownStep<ParameterizedFunction>],
+    ) -> Result<Vec<Option<util::Count>>, datalog::Error> {
+        self.0.borrow_mut().step_counts(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::egglog::Egglog;
+    use crate::eval;
+    use crate::parse;
+
+    use std::path::PathBuf;
+
+    fn problem(entry: &str) -> Problem {
+        let suite = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../pldi25/benchmark/suites/fin");
+        let (library, _) =
+            parse::library_file(&suite.join("_suite.hblib.toml")).unwrap();
+        let (program, _) =
+            parse::program_file(&suite.join(format!("{}.hb.toml", entry)))
+                .unwrap();
+        Problem { library, program }
+    }
+
+    #[test]
+    fn incremental_expansions_agree_across_navigation() {
+        for entry in ["bio_rna_seq", "gen_branches1", "gen_limited_cycle1"] {
+            let problem = problem(entry);
+            let start = problem.start();
+            let timer = util::Timer::infinite();
+
+            let mut incremental =
+                Oracle::new(Egglog::new(true), problem.clone())
+                    .unwrap()
+                    .incremental();
+            let mut plain = Oracle::new(Egglog::new(true), problem).unwrap();
+
+            // Take a different expansion at each step, going back to an
+            // earlier sketch (as undoing would) every few steps
+            let mut history = vec![start];
+            for step in 0..24 {
+                let e = history.last().unwrap().clone();
+                let expansions = incremental.expansions(&timer, &e).unwrap();
+                assert_eq!(
+                    expansions,
+                    plain.expansions(&timer, &e).unwrap(),
+                    "{} (step {})",
+                    entry,
+                    step
+                );
+
+                if step % 5 == 4 || expansions.is_empty() {
+                    history.truncate(history.len().div_ceil(2));
+                    continue;
+                }
+
+                let (h, f) = &expansions[step % expansions.len()];
+                history.push(e.substitute(*h, &Sketch::free(&e, f)));
+            }
+        }
+    }
+
+    /// The values of `x` for which `Sample { x }` is derivable from a function
+    /// with the given condition, along with those for which the condition
+    /// holds according to the evaluator (with props `In` for 1, 2, and 3 and
+    /// `Flagged` for 2)
+    fn negation_problem(condition: &str) -> (Vec<i64>, Vec<i64>) {
+        let library = parse::library(&format!(
+            r#"
+            [Prop.In]
+            params.x = "Int"
+
+            [Prop.Flagged]
+            params.x = "Int"
+
+            [Type.Sample]
+            params.x = "Int"
+
+            [Function.sample]
+            params = {{}}
+            ret = "Sample"
+            condition = ["In {{ x = ret.x }}", "{}"]
+            "#,
+            condition
+        ))
+        .unwrap();
+        let program = parse::program(
+            r#"
+            [[Prop]]
+            name = "In"
+            args.x = 1
+
+            [[Prop]]
+            name = "In"
+            args.x = 2
+
+            [[Prop]]
+            name = "In"
+            args.x = 3
+
+            [[Prop]]
+            name = "Flagged"
+            args.x = 2
+
+            [Goal]
+            name = "Sample"
+            "#,
+        )
+        .unwrap();
+
+        let fs = &library.functions[0];
+        let x = MetParam("x".to_owned());
+        let evaluated = (1..=3)
+            .filter(|i| {
+                eval::Context {
+                    props: &program.props,
+                    args: &IndexMap::new(),
+                    ret: &IndexMap::from([(x.clone(), core::Value::Int(*i))]),
+                }
+                .sat(&fs.condition)
+            })
+            .collect();
+
+        let goal = program.goals[0].clone();
+        let problem = Problem { library, program };
+        let mut derived: Vec<_> = Oracle::new(Egglog::new(true), problem)
+            .unwrap()
+            .valid_goal_metadata(&goal)
+            .unwrap()
+            .into_iter()
+            .map(|metadata| match metadata[&x] {
+                core::Value::Int(i) => i,
+                ref v => panic!("expected int, got {:?}", v),
+            })
+            .collect();
+        derived.sort();
+
+        (derived, evaluated)
+    }
+
+    #[test]
+    fn disjunctions_and_negations_agree_with_evaluation() {
+        for (condition, expected) in [
+            ("ret.x = 1 or ret.x = 3", vec![1, 3]),
+            ("not (ret.x = 1 or ret.x = 3)", vec![2]),
+            ("not (ret.x < 3 and ret.x != 1)", vec![1, 3]),
+            ("not not ret.x = 2", vec![2]),
+            ("ret.x = 1 or (ret.x > 1 and not ret.x = 3)", vec![1, 2]),
+        ] {
+            let (derived, evaluated) = negation_problem(condition);
+            assert_eq!(derived, expected, "{}", condition);
+            assert_eq!(evaluated, expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn negated_props_are_stratified() {
+        for (condition, expected) in [
+            ("not Flagged { x = ret.x }", vec![1, 3]),
+            ("not Flagged { x = _ }", vec![]),
+            ("not In { x = ret.x } or Flagged { x = ret.x }", vec![2]),
+        ] {
+            let (derived, evaluated) = negation_problem(condition);
+            assert_eq!(derived, expected, "{}", condition);
+            assert_eq!(evaluated, expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn disjunctions_compile_to_one_rule_per_clause() {
+        let library = parse::library(
+            r#"
+            [Type.Sample]
+            params.x = "Int"
+
+            [Function.sample]
+            params = {}
+            ret = "Sample"
+            condition = ["ret.x = 1 or ret.x = 2 or ret.x = 3"]
+            "#,
+        )
+        .unwrap();
+        let names: Vec<_> = CompileContext(typecheck::Context(&library))
+            .header()
+            .into_iter()
+            .map(|(_, rule)| rule.name)
+            .collect();
+        assert_eq!(names, vec!["sample#0", "sample#1", "sample#2"]);
+    }
+
+    fn explain(library: &str, goal: &str) -> Vec<Explanation> {
+        let problem = Problem {
+            library: parse::library(library).unwrap(),
+            program: parse::program(&format!(
+                r#"
+                [[Prop]]
+                name = "P_Reads"
+                args.sample = "s1"
+
+                [Goal]
+                name = "{}"
+                "#,
+                goal
+            ))
+            .unwrap(),
+        };
+        let start = problem.start();
+        Oracle::new(Egglog::new(true), problem)
+            .unwrap()
+            .explain(&start)
+            .unwrap()
+    }
+
+    /// The only blocker of a derivation
+    fn blocker<'a>(derivation: &'a Derivation, function: &str) -> &'a Blocker {
+        assert_eq!(derivation.function.0, function);
+        match &derivation.blockers[..] {
+            [blocker] => blocker,
+            blockers => panic!("unexpected blockers {:?}", blockers),
+        }
+    }
+
+    /// The nearest failing derivations of the only parameter blocking a
+    /// derivation
+    fn param<'a>(
+        derivation: &'a Derivation,
+        function: &str,
+        expected_param: &str,
+    ) -> &'a [Derivation] {
+        match blocker(derivation, function) {
+            Blocker::Param {
+                param, derivations, ..
+            } => {
+                assert_eq!(param.0, expected_param);
+                derivations
+            }
+            b => panic!("unexpected blocker {:?}", b),
+        }
+    }
+
+    /// Checks that a blocker is a condition (which must be unparsed to
+    /// surface syntax)
+    fn condition(blocker: &Blocker, expected_index: usize, expected: &str) {
+        match blocker {
+            Blocker::Condition { index, condition } => {
+                assert_eq!(*index, expected_index);
+                assert_eq!(
+                    Formula::try_from(vec![condition.clone()]).unwrap(),
+                    Formula::try_from(vec![expected.to_owned()]).unwrap(),
+                );
+            }
+            b => panic!("unexpected blocker {:?}", b),
+        }
+    }
+
+    #[test]
+    fn explanations_blame_conditions_and_params() {
+        let explanations = explain(
+            r#"
+            [Prop.P_Reads]
+            params.sample = "Str"
+
+            [Type.Reads]
+            params.sample = "Str"
+
+            [Type.Alignment]
+            params.sample = "Str"
+
+            [Type.Report]
+            params = {}
+
+            [Function.F_Reads]
+            params = {}
+            ret = "Reads"
+            condition = ["P_Reads { sample = ret.sample }"]
+
+            [Function.align]
+            params.reads = "Reads"
+            ret = "Alignment"
+            condition = [
+                "ret.sample = reads.sample",
+                'reads.sample = "s2" or reads.sample = "s3"',
+            ]
+
+            [Function.report]
+            params.alignment = "Alignment"
+            ret = "Report"
+            condition = []
+
+            [Function.summarize]
+            params = {}
+            ret = "Report"
+            condition = ["1 = 2 or true = false"]
+            "#,
+            "Report",
+        );
+
+        assert_eq!(explanations.len(), 1);
+        let derivations = &explanations[0].derivations;
+        assert_eq!(derivations.len(), 2);
+
+        // The conditions of `align` are satisfiable on their own, so the
+        // missing `Reads` (rather than the conditions) is blamed
+        let derivations = param(&derivations[0], "report", "alignment");
+        let derivations = param(&derivations[0], "align", "reads");
+        condition(
+            blocker(&derivations[0], "F_Reads"),
+            0,
+            "P_Reads { sample = ret.sample }",
+        );
+
+        condition(
+            blocker(&explanations[0].derivations[1], "summarize"),
+            0,
+            "1 = 2 or true = false",
+        );
+    }
+
+    #[test]
+    fn explanations_are_limited_in_depth() {
+        // T0 <- T1 <- ... <- T5, where T5 cannot be derived
+        let mut library = String::new();
+        for i in 0..=5 {
+            library += &format!("[Type.T{}]\nparams = {{}}\n\n", i);
+        }
+        for i in 0..5 {
+            library += &format!(
+                "[Function.f{}]\nparams.x = \"T{}\"\nret = \"T{}\"\n\
+                 condition = []\n\n",
+                i,
+                i + 1,
+                i
+            );
+        }
+        library += "[Prop.P_Reads]\nparams.sample = \"Str\"\n";
+        let explanations = explain(&library, "T0");
+
+        // Each level blames the parameter of the single function, until the
+        // depth runs out
+        let mut derivations = &explanations[0].derivations;
+        let mut levels = 0;
+        while let [derivation] = &derivations[..] {
+            assert_eq!(derivation.function.0, format!("f{}", levels));
+            derivations = match &derivation.blockers[..] {
+                [Blocker::Param { derivations, .. }] => derivations,
+                blockers => panic!("unexpected blockers {:?}", blockers),
+            };
+            levels += 1;
+        }
+        assert!(derivations.is_empty());
+        assert_eq!(levels, EXPLANATION_DEPTH + 1);
+    }
+
+    const ALIGNMENT: &str = r#"
+        [Prop.P_Reads]
+        params.sample = "Str"
+
+        [Type.Reads]
+        params.sample = "Str"
+
+        [Type.Alignment]
+        params.sample = "Str"
+
+        [Type.Report]
+        params = {}
+
+        [Function.F_Reads]
+        params = {}
+        ret = "Reads"
+        condition = ["P_Reads { sample = ret.sample }"]
+
+        [Function.align]
+        params.reads = "Reads"
+        ret = "Alignment"
+        condition = ["ret.sample = reads.sample"]
+
+        [Function.merge]
+        params.a = "Alignment"
+        params.b = "Alignment"
+        ret = "Alignment"
+        condition = ["ret.sample = a.sample", "a.sample = b.sample"]
+
+        [Function.report]
+        params.alignment = "Alignment"
+        ret = "Report"
+        condition = []
+    "#;
+
+    fn alignment_problem(goal: &str) -> Problem {
+        Problem {
+            library: parse::library(ALIGNMENT).unwrap(),
+            program: parse::program(&format!(
+                r#"
+                [[Prop]]
+                name = "P_Reads"
+                args.sample = "s1"
+
+                [[Prop]]
+                name = "P_Reads"
+                args.sample = "s2"
+
+                [Goal]
+                name = "{}"
+                "#,
+                goal
+            ))
+            .unwrap(),
+        }
+    }
+
+    /// A compact rendering of an expression, with the first metadata value of
+    /// each application (if it is a string)
+    fn show(e: &Exp) -> String {
+        match e {
+            Sketch::Hole(h) => format!("?{}", h),
+            Sketch::App(f, args) => format!(
+                "{}{}({})",
+                f.name.0,
+                match f.metadata.values().next() {
+                    Some(core::Value::Str(s)) => format!("<{}>", s),
+                    _ => String::new(),
+                },
+                args.iter()
+                    .map(|(fp, arg)| format!("{}={}", fp.0, show(arg)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// The attachments of an expression, as (hole, function, parameter,
+    /// attached expression) tuples
+    fn attachments(
+        oracle: &mut impl ForwardOracle<F = ParameterizedFunction>,
+        e: &Exp,
+    ) -> Vec<(HoleName, String, String, String)> {
+        let mut ret: Vec<_> = oracle
+            .attachments(&util::Timer::infinite(), e)
+            .unwrap()
+            .into_iter()
+            .map(|(h, f, fp, sub)| {
+                (h, show(&Sketch::App(f, IndexMap::new())), fp.0, show(&sub))
+            })
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    fn attachment(
+        h: HoleName,
+        f: &str,
+        fp: &str,
+        sub: &str,
+    ) -> (HoleName, String, String, String) {
+        (h, f.to_owned(), fp.to_owned(), sub.to_owned())
+    }
+
+    #[test]
+    fn attachments_start_from_inputs() {
+        let mut oracle =
+            Oracle::new(Egglog::new(true), alignment_problem("Alignment"))
+                .unwrap()
+                .incremental();
+
+        // The reads of a sample can only be aligned for that sample
+        assert_eq!(
+            attachments(&mut oracle, &Sketch::blank()),
+            vec![
+                attachment(0, "align<s1>()", "reads", "F_Reads<s1>()"),
+                attachment(0, "align<s2>()", "reads", "F_Reads<s2>()"),
+            ]
+        );
+    }
+
+    /// Takes the step to an expression (as rendered by [`show`])
+    fn navigate(controller: &mut crate::menu::Controller, to: &str) {
+        let e = controller.working_expression().clone();
+        let step = controller
+            .provide()
+            .unwrap()
+            .into_iter()
+            .find(|step| {
+                pbn::Step::apply(step, &e).is_some_and(|e2| show(&e2) == to)
+            })
+            .unwrap_or_else(|| panic!("no step from {} to {}", show(&e), to));
+        controller.decide(step);
+    }
+
+    #[test]
+    fn attachments_take_subexpressions_of_any_type() {
+        let problem = alignment_problem("Report");
+        let mut controller = crate::menu::Algorithm::PBNHoneybeeBottomUp
+            .controller(util::Timer::infinite(), problem.clone(), false)
+            .unwrap();
+
+        // Nothing takes reads directly to a report
+        assert!(controller
+            .provide()
+            .unwrap()
+            .iter()
+            .all(|step| !matches!(step, TopDownStep::Wrap(..))));
+
+        navigate(&mut controller, "report(alignment=?1)");
+        navigate(&mut controller, "report(alignment=merge<s1>(a=?2, b=?3))");
+        navigate(
+            &mut controller,
+            "report(alignment=merge<s1>(a=align<s1>(reads=F_Reads<s1>()), b=?3))",
+        );
+
+        let mut oracle = Oracle::new(Egglog::new(true), problem)
+            .unwrap()
+            .incremental();
+        let aligned = "align<s1>(reads=F_Reads<s1>())";
+        assert_eq!(
+            attachments(&mut oracle, controller.working_expression()),
+            vec![
+                attachment(3, "align<s1>()", "reads", "F_Reads<s1>()"),
+                attachment(3, "merge<s1>()", "a", aligned),
+                attachment(3, "merge<s1>()", "b", aligned),
+            ]
+        );
+
+        navigate(
+            &mut controller,
+            &format!(
+                "report(alignment=merge<s1>(a={}, b=merge<s1>(a=?4, b={})))",
+                aligned, aligned
+            ),
+        );
+        assert!(!controller.valid());
+    }
+
+    #[test]
+    fn completion_costs_are_minimal_totals() {
+        let problem = Problem {
+            library: parse::library(
+                r#"
+                [Type.Reads]
+                params.kind = "Str"
+
+                [Type.Report]
+                params.kind = "Str"
+
+                [Function.illumina]
+                params = {}
+                ret = "Reads"
+                condition = ['ret.kind = "short"']
+                info.cost = 1
+
+                [Function.nanopore]
+                params = {}
+                ret = "Reads"
+                condition = ['ret.kind = "long"']
+                info.cost = 5
+
+                [Function.resequence]
+                params.r = "Reads"
+                ret = "Reads"
+                condition = ['ret.kind = "short"']
+                info.cost = 0.5
+
+                [Function.summarize]
+                params.r = "Reads"
+                ret = "Report"
+                condition = ["ret.kind = r.kind"]
+                "#,
+            )
+            .unwrap(),
+            program: parse::program(
+                r#"
+                Prop = []
+
+                [Goal]
+                name = "Report"
+                "#,
+            )
+            .unwrap(),
+        };
+        let mut oracle = Oracle::new(Egglog::new(true), problem).unwrap();
+
+        let e = Sketch::blank();
+        let steps: Vec<_> = oracle
+            .expansions(&util::Timer::infinite(), &e)
+            .unwrap()
+            .into_iter()
+            .map(|(h, f)| TopDownStep::Extend(h, f, IndexMap::new()))
+            .collect();
+        let mut costs: Vec<_> = steps
+            .iter()
+            .zip(oracle.step_costs(&steps).unwrap())
+            .map(|(step, cost)| match step {
+                TopDownStep::Extend(_, f, _) => {
+                    (show(&Sketch::App(f.clone(), IndexMap::new())), cost)
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        costs.sort_by(|(f1, _), (f2, _)| f1.cmp(f2));
+
+        // Long reads can be resequenced into short ones, but that is never
+        // cheaper than sequencing short reads directly
+        assert_eq!(
+            costs,
+            vec![
+                ("summarize<long>()".to_owned(), Some(6.0)),
+                ("summarize<short>()".to_owned(), Some(2.0)),
+            ]
+        );
+
+        let summary = match &steps[0] {
+            TopDownStep::Extend(h, f, _) => (h, f.clone()),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            oracle
+                .step_costs(&[
+                    TopDownStep::Reuse(
+                        *summary.0,
+                        Sketch::App(summary.1, IndexMap::new())
+                    ),
+                    TopDownStep::Retract(vec![]),
+                ])
+                .unwrap(),
+            vec![Some(0.0), None]
+        );
+    }
+
+    /// The number of completions of each expansion of a blank sketch for the
+    /// goal `Report`, by function name
+    fn completion_counts(library: &str) -> Vec<(String, util::Count)> {
+        let problem = Problem {
+            library: parse::library(library).unwrap(),
+            program: parse::program(
+                r#"
+                Prop = []
+
+                [Goal]
+                name = "Report"
+                "#,
+            )
+            .unwrap(),
+        };
+        let mut oracle = Oracle::new(Egglog::new(true), problem).unwrap();
+
+        let mut counts: Vec<_> = oracle
+            .expansions(&util::Timer::infinite(), &Sketch::blank())
+            .unwrap()
+            .into_iter()
+            .map(|(_, f)| {
+                let count = oracle.completion_count(&f).unwrap().unwrap();
+                (f.name.0, count)
+            })
+            .collect();
+        counts.sort_by(|(f1, _), (f2, _)| f1.cmp(f2));
+        counts
+    }
+
+    const READS: &str = r#"
+        [Type.Reads]
+        params = {}
+
+        [Type.Report]
+        params = {}
+
+        [Function.illumina]
+        params = {}
+        ret = "Reads"
+        condition = []
+
+        [Function.nanopore]
+        params = {}
+        ret = "Reads"
+        condition = []
+
+        [Function.summarize]
+        params.r = "Reads"
+        ret = "Report"
+        condition = []
+
+        [Function.compare]
+        params.a = "Reads"
+        params.b = "Reads"
+        ret = "Report"
+        condition = []
+
+        [Function.empty]
+        params = {}
+        ret = "Report"
+        condition = []
+    "#;
+
+    #[test]
+    fn completions_are_counted_over_derivations() {
+        assert_eq!(
+            completion_counts(READS),
+            vec![
+                ("compare".to_owned(), util::Count::Finite(4)),
+                ("empty".to_owned(), util::Count::Finite(1)),
+                ("summarize".to_owned(), util::Count::Finite(2)),
+            ]
+        );
+
+        // Reads can be resequenced any number of times
+        let recursive = format!(
+            r#"
+            {}
+
+            [Function.resequence]
+            params.r = "Reads"
+            ret = "Reads"
+            condition = []
+            "#,
+            READS
+        );
+        assert_eq!(
+            completion_counts(&recursive),
+            vec![
+                ("compare".to_owned(), util::Count::Infinite),
+                ("empty".to_owned(), util::Count::Finite(1)),
+                ("summarize".to_owned(), util::Count::Infinite),
+            ]
+        );
+    }
+
+    #[test]
+    fn underivable_params_have_no_completions() {
+        let fact = |name: &str| (MetName(name.to_owned()), vec![]);
+        let function = |name: &str| BaseFunction(name.to_owned());
+
+        // Reads are recursive, but there is no reference to align them to
+        let groundings = Groundings::from([
+            (
+                fact("Alignment"),
+                IndexSet::from([(
+                    function("align"),
+                    vec![fact("Reads"), fact("Reference")],
+                )]),
+            ),
+            (
+                fact("Reads"),
+                IndexSet::from([
+                    (function("sequence"), vec![]),
+                    (function("resequence"), vec![fact("Reads")]),
+                ]),
+            ),
+        ]);
+
+        let mut counts = IndexMap::new();
+        let mut count = |name: &str| {
+            Oracle::<Egglog>::count(
+                &groundings,
+                &mut counts,
+                &mut IndexSet::new(),
+                &fact(name),
+            )
+        };
+        assert_eq!(count("Reads"), util::Count::Infinite);
+        assert_eq!(count("Reference"), util::Count::Finite(0));
+        assert_eq!(count("Alignment"), util::Count::Finite(0));
+    }
+}
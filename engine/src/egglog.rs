@@ -2,6 +2,30 @@ use crate::datalog::*;
 
 use chumsky::Parser;
 use egglog::EGraph;
+use indexmap::IndexSet;
+
+// Strings
+
+/// Escape the backslashes and double quotes in a string.
+///
+/// Egglog prints strings verbatim (without escaping them) in its output, so
+/// string values are escaped once before being stored in the e-graph and once
+/// more to be written as an egglog string literal. The stored form can then be
+/// read back unambiguously by the output parser.
+fn escape(s: &str) -> String {
+    let mut ret = String::new();
+    for c in s.chars() {
+        if c == '\\' || c == '"' {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+fn string_literal(s: &str) -> String {
+    format!("\"{}\"", escape(&escape(s)))
+}
 
 // Compiler
 
@@ -60,6 +84,7 @@ impl Compiler {
         match vt {
             ValueType::Bool => self.write("bool"),
             ValueType::Int => self.write("i64"),
+            ValueType::Float => self.write("f64"),
             ValueType::Str => self.write("String"),
         }
     }
@@ -78,15 +103,17 @@ impl Compiler {
         match v {
             Value::Bool(b) => self.write(&b.to_string()),
             Value::Int(x) => self.write(&x.to_string()),
-            Value::Str(s) => self.write(&format!("\"{}\"", s)),
+            Value::Float(x) => self.write(&format!("{:?}", x)),
+            Value::Str(s) => self.write(&string_literal(s)),
             Value::Var { name, typ: _ } => self.write(name),
         }
     }
 
     fn constrain(&mut self, v: &Value) {
         match v.unsafe_infer() {
-            ValueType::Bool => return,
+            ValueType::Bool => self.write("(&Bool "),
             ValueType::Int => self.write("(&Int "),
+            ValueType::Float => self.write("(&Float "),
             ValueType::Str => self.write("(&Str "),
         };
         self.value(v);
@@ -108,53 +135,108 @@ impl Compiler {
         self.write(")");
     }
 
+    fn binary_primitive(&mut self, op: &str, left: &Value, right: &Value) {
+        self.write(&format!("({} ", op));
+        self.value(left);
+        self.write(" ");
+        self.value(right);
+        self.write(")");
+    }
+
+    fn arithmetic_primitive(
+        &mut self,
+        op: &str,
+        left: &Value,
+        right: &Value,
+        result: &Value,
+    ) {
+        self.write("(= ");
+        self.value(result);
+        self.write(" ");
+        self.binary_primitive(op, left, right);
+        self.write(")");
+    }
+
     fn predicate(&mut self, p: &Predicate) {
         match p {
             Predicate::Fact(f) => self.fact(f),
             Predicate::PrimEq(left, right) => {
-                self.write("(= ");
-                self.value(left);
-                self.write(" ");
-                self.value(right);
-                self.write(")");
+                self.binary_primitive("=", left, right)
             }
             Predicate::PrimLt(left, right) => {
-                self.write("(< ");
-                self.value(left);
-                self.write(" ");
-                self.value(right);
-                self.write(")");
+                self.binary_primitive("<", left, right)
+            }
+            Predicate::PrimLe(left, right) => {
+                self.binary_primitive("<=", left, right)
+            }
+            Predicate::PrimGt(left, right) => {
+                self.binary_primitive(">", left, right)
+            }
+            Predicate::PrimGe(left, right) => {
+                self.binary_primitive(">=", left, right)
             }
             Predicate::PrimNeq(left, right) => {
-                self.write("(!= ");
-                self.value(left);
-                self.write(" ");
-                self.value(right);
-                self.write(")");
+                self.binary_primitive("!=", left, right)
+            }
+            Predicate::PrimAdd(left, right, result) => {
+                self.arithmetic_primitive("+", left, right, result)
+            }
+            Predicate::PrimSub(left, right, result) => {
+                self.arithmetic_primitive("-", left, right, result)
             }
         }
     }
 
-    fn rule(&mut self, r: &Rule) {
-        self.writeln(&format!("; {}", r.name));
-        self.write("(rule\n  ; Antecedent\n  (");
+    /// Writes the body of a rule, along with the domain constraints on its
+    /// values
+    fn antecedent(&mut self, r: &Rule) {
         for p in &r.body {
             self.predicate(p);
             self.tentative("\n   ");
         }
+        // Booleans are never restricted by the domain, so Boolean variables
+        // only need to be constrained when no fact binds them (as in the
+        // partial queries used to explain failures)
+        let fact_vals: IndexSet<Value> = r
+            .body
+            .iter()
+            .filter(|p| matches!(p, Predicate::Fact(_)))
+            .flat_map(|p| p.vals())
+            .collect();
         for v in r.vals() {
+            if v.unsafe_infer() == ValueType::Bool
+                && (!matches!(v, Value::Var { .. }) || fact_vals.contains(&v))
+            {
+                continue;
+            }
             self.constrain(&v);
             self.tentative("\n   ");
         }
         self.cancel();
+    }
+
+    fn rule(&mut self, r: &Rule) {
+        self.writeln(&format!("; {}", r.name));
+        self.write("(rule\n  ; Antecedent\n  (");
+        self.antecedent(r);
         self.write(")\n  ; Consequent\n  (");
         self.fact(&r.head);
         self.writeln(&format!(
             ")\n  :name \"{}\"\n  :ruleset {})",
-            r.name, self.ruleset
+            escape(&r.name),
+            self.ruleset
         ))
     }
 
+    /// Writes a check that the body of a rule has a match (which egglog stops
+    /// searching for at the first match)
+    fn check(&mut self, r: &Rule) {
+        self.writeln(&format!("; {}", r.name));
+        self.write("(check\n  ");
+        self.antecedent(r);
+        self.writeln(")");
+    }
+
     fn ruleset(&mut self) {
         self.writeln(&format!("(ruleset {})", self.ruleset));
     }
@@ -163,8 +245,8 @@ impl Compiler {
         self.writeln(&format!("(run-schedule (saturate {}))", self.ruleset));
     }
 
-    fn print(&mut self, rel: &Relation) {
-        self.writeln(&format!("(print-function {} {})", rel.0, 10_000_000));
+    fn print(&mut self, rel: &Relation, limit: usize) {
+        self.writeln(&format!("(print-function {} {})", rel.0, limit));
     }
 
     pub fn program(&mut self, prog: &Program) {
@@ -172,11 +254,17 @@ impl Compiler {
 
         self.newln();
 
+        self.writeln("(relation &Bool (bool))");
         self.writeln("(relation &Int (i64))");
+        self.writeln("(relation &Float (f64))");
         self.writeln("(relation &Str (String))");
 
         self.newln();
 
+        // Booleans are never restricted by the domain
+        self.writeln("(&Bool true)");
+        self.writeln("(&Bool false)");
+
         for v in &prog.dom {
             self.constrain(v);
             self.newln();
@@ -212,43 +300,79 @@ mod parse {
     use crate::datalog::*;
     use chumsky::prelude::*;
 
-    fn value() -> impl Parser<char, Value, Error = Simple<char>> {
-        choice((
-            just("true").to(Value::Bool(true)),
-            just("false").to(Value::Bool(false)),
-            text::int(10).map(|s: String| Value::Int(s.parse().unwrap())),
-            none_of("\"")
+    fn value(
+        vt: &ValueType,
+    ) -> BoxedParser<'static, char, Value, Simple<char>> {
+        match vt {
+            ValueType::Bool => choice((
+                just("true").to(Value::Bool(true)),
+                just("false").to(Value::Bool(false)),
+            ))
+            .boxed(),
+            ValueType::Int => just('-')
+                .or_not()
+                .then(text::int(10))
+                .try_map(|(sign, digits): (Option<char>, String), span| {
+                    let s = match sign {
+                        Some(_) => format!("-{}", digits),
+                        None => digits,
+                    };
+                    s.parse()
+                        .map(Value::Int)
+                        .map_err(|_| Simple::custom(span, "invalid int"))
+                })
+                .boxed(),
+            // Floats are printed without an exponent, but possibly as a
+            // special value such as NaN or inf
+            ValueType::Float => none_of(" ()")
+                .repeated()
+                .at_least(1)
+                .collect::<String>()
+                .try_map(|s, span| {
+                    s.parse()
+                        .map(Value::Float)
+                        .map_err(|_| Simple::custom(span, "invalid float"))
+                })
+                .boxed(),
+            ValueType::Str => just('\\')
+                .ignore_then(any())
+                .or(none_of("\\\""))
                 .repeated()
                 .collect()
                 .delimited_by(just('"'), just('"'))
-                .map(|s: String| Value::Str(s)),
-        ))
+                .map(|s: String| Value::Str(s))
+                .boxed(),
+        }
     }
 
     fn entry(
         rel: &Relation,
+        params: &[ValueType],
     ) -> impl Parser<char, Vec<Value>, Error = Simple<char>> {
+        let mut args = empty().to(vec![]).boxed();
+        for vt in params {
+            args = args
+                .then(just(' ').repeated().at_least(1).ignore_then(value(vt)))
+                .map(|(mut vals, v)| {
+                    vals.push(v);
+                    vals
+                })
+                .boxed();
+        }
+
         just(rel.0.clone())
-            .ignored()
-            .then(
-                just(' ')
-                    .repeated()
-                    .at_least(1)
-                    .then(value())
-                    .map(|(_, x)| x)
-                    .repeated(),
-            )
-            .then(just(' ').repeated().ignored())
+            .ignore_then(args)
+            .then_ignore(just(' ').repeated())
             .delimited_by(just('('), just(')'))
-            .map(|((_, vals), _)| vals)
     }
 
     pub fn output(
         rel: &Relation,
+        params: &[ValueType],
     ) -> impl Parser<char, Vec<Vec<Value>>, Error = Simple<char>> {
         choice((
             just('(').padded().then(just(')').padded()).to(vec![]),
-            entry(rel)
+            entry(rel, params)
                 .padded()
                 .repeated()
                 .delimited_by(just('('), just(')'))
@@ -265,6 +389,7 @@ enum State {
 
 pub struct Egglog {
     state: State,
+    program: Option<Program>,
 }
 
 impl Egglog {
@@ -272,19 +397,116 @@ impl Egglog {
         if cache {
             Self {
                 state: State::Cache { egraph: None },
+                program: None,
             }
         } else {
             Self {
                 state: State::NoCache {
                     egglog_program: None,
                 },
+                program: None,
             }
         }
     }
+
+    /// Runs a query, printing the entries of its head relation.
+    fn run_query(
+        &mut self,
+        signature: &RelationSignature,
+        rule: &Rule,
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        let mut comp = Compiler::new("query");
+        comp.writeln(";;; Query ;;;\n");
+        comp.ruleset();
+        comp.newln();
+        comp.relation_signature(&rule.head.relation, signature);
+        comp.newln();
+        comp.newln();
+        comp.rule(rule);
+        comp.newln();
+        comp.saturate();
+        comp.print(&rule.head.relation, 10_000_000);
+        let egglog_query = comp.get();
+
+        log::debug!("Egglog query constructed\n{}", egglog_query);
+
+        let messages = match &mut self.state {
+            State::NoCache {
+                egglog_program: Some(p),
+            } => {
+                let combined_program = format!("{}\n{}", p, egglog_query);
+                let mut e = EGraph::default();
+                e.parse_and_run_program(None, &combined_program)
+                    .map_err(|e| Error::Backend(e.to_string()))?
+            }
+            State::Cache { egraph: Some(e) } => {
+                // TODO: might not need to push/pop here
+                e.push();
+                let messages = e.parse_and_run_program(None, &egglog_query);
+                e.pop().map_err(|e| Error::Backend(e.to_string()))?;
+                messages.map_err(|e| Error::Backend(e.to_string()))?
+            }
+            _ => return Err(Error::NotLoaded),
+        };
+
+        let message = match <[String; 1]>::try_from(messages) {
+            Ok([message]) => message,
+            Err(messages) => {
+                return Err(Error::UnexpectedOutput(format!(
+                    "expected 1 message, got:\n\n{:?}",
+                    messages
+                )))
+            }
+        };
+
+        parse::output(&rule.head.relation, &signature.params)
+            .parse(message.as_str())
+            .map_err(|errs| {
+                Error::UnexpectedOutput(format!(
+                    "could not parse output:\n\n{}\n\n{:?}",
+                    message, errs
+                ))
+            })
+    }
+
+    /// Checks whether the body of a rule has a match, without running the
+    /// rule or printing its answers.
+    fn run_check(&mut self, rule: &Rule) -> Result<bool, Error> {
+        // Egglog cannot check an empty list of facts
+        if rule.body.is_empty() && rule.vals().is_empty() {
+            return Ok(true);
+        }
+
+        let mut comp = Compiler::new("check");
+        comp.writeln(";;; Check ;;;\n");
+        comp.check(rule);
+        let egglog_check = comp.get();
+
+        log::debug!("Egglog check constructed\n{}", egglog_check);
+
+        let result = match &mut self.state {
+            State::NoCache {
+                egglog_program: Some(p),
+            } => {
+                let combined_program = format!("{}\n{}", p, egglog_check);
+                EGraph::default().parse_and_run_program(None, &combined_program)
+            }
+            State::Cache { egraph: Some(e) } => {
+                e.parse_and_run_program(None, &egglog_check)
+            }
+            _ => return Err(Error::NotLoaded),
+        };
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(egglog::Error::CheckError(..)) => Ok(false),
+            Err(e) => Err(Error::Backend(e.to_string())),
+        }
+    }
 }
 
 impl Engine for Egglog {
-    fn load(&mut self, program: Program) {
+    fn load(&mut self, program: Program) -> Result<(), Error> {
         let mut comp = Compiler::new("program");
         comp.program(&program);
         let egglog_program = comp.get();
@@ -299,10 +521,13 @@ impl Engine for Egglog {
                 let mut egraph = EGraph::default();
                 let messages = egraph
                     .parse_and_run_program(None, &egglog_program)
-                    .unwrap();
+                    .map_err(|e| Error::Backend(e.to_string()))?;
 
                 if !messages.is_empty() {
-                    panic!("expected 0 messages, got:\n\n{:?}", messages);
+                    return Err(Error::UnexpectedOutput(format!(
+                        "expected 0 messages, got:\n\n{:?}",
+                        messages
+                    )));
                 }
 
                 State::Cache {
@@ -310,58 +535,360 @@ impl Engine for Egglog {
                 }
             }
         };
+
+        self.program = Some(program);
+        Ok(())
     }
 
     fn query(
         &mut self,
         signature: &RelationSignature,
         rule: &Rule,
-    ) -> Vec<Vec<Value>> {
-        let mut comp = Compiler::new("query");
-        comp.writeln(";;; Query ;;;\n");
-        comp.ruleset();
-        comp.newln();
-        comp.relation_signature(&rule.head.relation, signature);
-        comp.newln();
-        comp.newln();
-        comp.rule(rule);
-        comp.newln();
-        comp.saturate();
-        comp.print(&rule.head.relation);
-        let egglog_query = comp.get();
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        self.run_query(signature, rule)
+    }
 
-        log::debug!("Egglog query constructed\n{}", egglog_query);
+    /// The query is never run as a rule: its body is checked directly
+    /// against the saturated database, and egglog stops at the first match.
+    fn exists(
+        &mut self,
+        _signature: &RelationSignature,
+        rule: &Rule,
+        bound: &[Option<Value>],
+    ) -> Result<bool, Error> {
+        self.run_check(&rule.bind(bound))
+    }
 
-        let messages = match &mut self.state {
-            State::NoCache {
-                egglog_program: Some(p),
-            } => {
-                let combined_program = format!("{}\n{}", p, egglog_query);
-                let mut e = EGraph::default();
-                e.parse_and_run_program(None, &combined_program)
-                    .map_err(|e| {
-                        eprintln!("{}", combined_program);
-                        panic!("{}", e)
-                    })
-                    .unwrap()
-            }
-            State::Cache { egraph: Some(e) } => {
-                // TODO: might not need to push/pop here
-                e.push();
-                let messages =
-                    e.parse_and_run_program(None, &egglog_query).unwrap();
-                e.pop().unwrap();
-                messages
-            }
-            _ => panic!("must call Engine::load before Engine::query"),
+    fn query_with_provenance(
+        &mut self,
+        signature: &RelationSignature,
+        rule: &Rule,
+    ) -> Result<Vec<(Vec<Value>, DerivationTree)>, Error> {
+        // Egglog does not track provenance itself, so derivations are
+        // reconstructed from ordinary queries
+        let program = self.program.take().ok_or(Error::NotLoaded)?;
+        let ret = reconstruct(self, &program, signature, rule);
+        self.program = Some(program);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core;
+    use crate::dl_oracle;
+    use crate::parse as core_parse;
+    use crate::util;
+
+    const LABEL: &str = "é \"q\" \\ ☃🐝\n\t\u{1}";
+
+    fn round_trip(vt: ValueType, vs: Vec<Value>) -> Vec<Vec<Value>> {
+        let rel = Relation("R".to_owned());
+
+        let mut comp = Compiler::new("test");
+        comp.relation_signature(
+            &rel,
+            &RelationSignature {
+                params: vec![vt.clone()],
+                kind: RelationKind::EDB,
+            },
+        );
+        for v in vs {
+            comp.fact(&Fact {
+                relation: rel.clone(),
+                args: vec![Some(v)],
+            });
+        }
+        comp.print(&rel, 10);
+
+        let messages = EGraph::default()
+            .parse_and_run_program(None, &comp.get())
+            .unwrap();
+        super::parse::output(&rel, &[vt])
+            .parse(messages[0].as_str())
+            .unwrap()
+    }
+
+    #[test]
+    fn negative_int_round_trip() {
+        let ints = [-7, -1, 0, 3, i64::MIN, i64::MAX];
+        let mut got: Vec<_> = round_trip(
+            ValueType::Int,
+            ints.iter().map(|x| Value::Int(*x)).collect(),
+        )
+        .into_iter()
+        .map(|args| match args.as_slice() {
+            [Value::Int(x)] => *x,
+            _ => panic!("expected a single int, got {:?}", args),
+        })
+        .collect();
+        got.sort();
+        let mut expected = ints.to_vec();
+        expected.sort();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn string_round_trip() {
+        let v = Value::Str(LABEL.to_owned());
+        assert_eq!(round_trip(ValueType::Str, vec![v.clone()]), vec![vec![v]]);
+    }
+
+    #[test]
+    fn string_round_trip_through_oracle() {
+        let library = core_parse::library(
+            r#"
+            [Type.Label]
+            params.s = "Str"
+
+            [Function.make]
+            params = {}
+            ret = "Label"
+            condition = ['ret.s = "é \"q\" \\ ☃🐝\n\t\u0001"']
+            "#,
+        )
+        .unwrap();
+        let program = core_parse::program(
+            r#"
+            Prop = []
+
+            [Goal]
+            name = "Label"
+            "#,
+        )
+        .unwrap();
+        let goal = program.goals[0].clone();
+        let problem = core::Problem { library, program };
+
+        let mut oracle =
+            dl_oracle::Oracle::new(Egglog::new(true), problem).unwrap();
+        let vgm = oracle.valid_goal_metadata(&goal).unwrap();
+        assert_eq!(vgm.len(), 1);
+        let s = match &vgm[0][&core::MetParam("s".to_owned())] {
+            core::Value::Str(s) => s.clone(),
+            v => panic!("expected string, got {:?}", v),
         };
+        assert_eq!(s, LABEL);
+
+        let literal = util::quote(&s);
+        assert_eq!(literal, r#""é \"q\" \\ ☃🐝\n\t\u0001""#);
+
+        let phi = core::Formula::try_from(vec![format!("ret.s = {}", literal)])
+            .unwrap();
+        assert_eq!(
+            phi.conditions(),
+            vec![&core::Formula::Eq(
+                core::FormulaAtom::Ret(core::MetParam("s".to_owned())),
+                core::FormulaAtom::Lit(core::Value::Str(LABEL.to_owned())),
+            )]
+        );
+    }
 
-        if messages.len() != 1 {
-            panic!("expected 1 message, got:\n\n{:?}", messages);
+    /// An engine that counts the queries made of it
+    struct Counting {
+        engine: Egglog,
+        program: Option<Program>,
+        queries: usize,
+    }
+
+    impl Engine for Counting {
+        fn load(&mut self, program: Program) -> Result<(), Error> {
+            self.program = Some(program.clone());
+            self.engine.load(program)
         }
 
-        let message = messages.into_iter().next().unwrap();
+        fn query(
+            &mut self,
+            signature: &RelationSignature,
+            rule: &Rule,
+        ) -> Result<Vec<Vec<Value>>, Error> {
+            self.queries += 1;
+            self.engine.query(signature, rule)
+        }
 
-        parse::output(&rule.head.relation).parse(message).unwrap()
+        fn query_with_provenance(
+            &mut self,
+            signature: &RelationSignature,
+            rule: &Rule,
+        ) -> Result<Vec<(Vec<Value>, DerivationTree)>, Error> {
+            let program = self.program.take().ok_or(Error::NotLoaded)?;
+            let ret = reconstruct(self, &program, signature, rule);
+            self.program = Some(program);
+            ret
+        }
+    }
+
+    fn var(name: &str) -> Value {
+        Value::Var {
+            name: name.to_owned(),
+            typ: ValueType::Int,
+        }
+    }
+
+    fn fact(relation: &str, args: Vec<Value>) -> Fact {
+        Fact {
+            relation: Relation(relation.to_owned()),
+            args: args.into_iter().map(Some).collect(),
+        }
+    }
+
+    #[test]
+    fn exists_checks_the_saturated_database() {
+        // R(x, y) :- E(x, z), E(z, y)
+        let idb = RelationSignature {
+            params: vec![ValueType::Int, ValueType::Int],
+            kind: RelationKind::IDB,
+        };
+        let lib = indexmap::IndexMap::from([
+            (Relation("R".to_owned()), idb.clone()),
+            (
+                Relation("E".to_owned()),
+                RelationSignature {
+                    params: vec![ValueType::Int, ValueType::Int],
+                    kind: RelationKind::EDB,
+                },
+            ),
+        ]);
+        let step = Rule {
+            name: "step".to_owned(),
+            head: fact("R", vec![var("x"), var("y")]),
+            body: vec![
+                Predicate::Fact(fact("E", vec![var("x"), var("z")])),
+                Predicate::Fact(fact("E", vec![var("z"), var("y")])),
+            ],
+        };
+        let program = Program::new(
+            lib,
+            (0..4).map(Value::Int).collect(),
+            vec![step],
+            vec![
+                fact("E", vec![Value::Int(0), Value::Int(1)]),
+                fact("E", vec![Value::Int(1), Value::Int(2)]),
+                fact("E", vec![Value::Int(2), Value::Int(3)]),
+            ],
+        )
+        .unwrap();
+
+        // Q(a, b) :- R(a, b)
+        let query = Rule {
+            name: "query".to_owned(),
+            head: fact("Q", vec![var("a"), var("b")]),
+            body: vec![Predicate::Fact(fact("R", vec![var("a"), var("b")]))],
+        };
+
+        for cache in [true, false] {
+            let mut engine = Egglog::new(cache);
+            engine.load(program.clone()).unwrap();
+
+            for (bound, expected) in [
+                (vec![None, None], true),
+                (vec![Some(Value::Int(0)), None], true),
+                (vec![Some(Value::Int(0)), Some(Value::Int(2))], true),
+                (vec![Some(Value::Int(0)), Some(Value::Int(3))], false),
+                (vec![None, Some(Value::Int(1))], false),
+            ] {
+                assert_eq!(
+                    engine.exists(&idb, &query, &bound).unwrap(),
+                    expected,
+                    "{:?} (cache: {})",
+                    bound,
+                    cache
+                );
+            }
+
+            // Checks leave the database untouched
+            assert_eq!(
+                engine.query(&idb, &query).unwrap(),
+                vec![
+                    vec![Value::Int(0), Value::Int(2)],
+                    vec![Value::Int(1), Value::Int(3)]
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reconstruction_is_not_exponential() {
+        // Reachability in a complete graph, trying the recursive rule first:
+        // without memoization, backtracking explores every simple path
+        const N: i64 = 8;
+
+        let idb = RelationSignature {
+            params: vec![ValueType::Int],
+            kind: RelationKind::IDB,
+        };
+        let lib = indexmap::IndexMap::from([
+            (
+                Relation("Edge".to_owned()),
+                RelationSignature {
+                    params: vec![ValueType::Int, ValueType::Int],
+                    kind: RelationKind::EDB,
+                },
+            ),
+            (
+                Relation("Start".to_owned()),
+                RelationSignature {
+                    params: vec![ValueType::Int],
+                    kind: RelationKind::EDB,
+                },
+            ),
+            (Relation("Reach".to_owned()), idb.clone()),
+        ]);
+        let rules = vec![
+            Rule {
+                name: "step".to_owned(),
+                head: fact("Reach", vec![var("x")]),
+                body: vec![
+                    Predicate::Fact(fact("Edge", vec![var("y"), var("x")])),
+                    Predicate::Fact(fact("Reach", vec![var("y")])),
+                ],
+            },
+            Rule {
+                name: "start".to_owned(),
+                head: fact("Reach", vec![var("x")]),
+                body: vec![Predicate::Fact(fact("Start", vec![var("x")]))],
+            },
+        ];
+        let mut ground_facts = vec![fact("Start", vec![Value::Int(0)])];
+        for i in 0..N {
+            for j in 0..N {
+                if i != j {
+                    ground_facts
+                        .push(fact("Edge", vec![Value::Int(i), Value::Int(j)]));
+                }
+            }
+        }
+        let program = Program::new(
+            lib,
+            (0..N).map(Value::Int).collect(),
+            rules,
+            ground_facts,
+        )
+        .unwrap();
+
+        let mut engine = Counting {
+            engine: Egglog::new(true),
+            program: None,
+            queries: 0,
+        };
+        engine.load(program).unwrap();
+
+        let query = Rule {
+            name: "query".to_owned(),
+            head: fact("&Query", vec![var("x")]),
+            body: vec![
+                Predicate::Fact(fact("Reach", vec![var("x")])),
+                Predicate::PrimEq(var("x"), Value::Int(N - 1)),
+            ],
+        };
+        let ret = engine.query_with_provenance(&idb, &query).unwrap();
+
+        assert_eq!(ret.len(), 1);
+        assert!(
+            engine.queries <= (N * N) as usize,
+            "{} queries",
+            engine.queries
+        );
     }
 }
@@ -22,6 +22,7 @@ use std::collections::VecDeque;
 /// components, which is the set of possible values that could be filled in.
 pub struct Support {
     ints: Vec<Value>,
+    floats: Vec<Value>,
     strings: Vec<Value>,
 }
 
@@ -29,24 +30,32 @@ impl Support {
     /// Create a new support from a set of values
     fn new(values: IndexSet<Value>) -> Self {
         let mut ints = vec![];
+        let mut floats = vec![];
         let mut strings = vec![];
 
         for v in values {
             match v {
                 Value::Bool(_) => (),
                 Value::Int(_) => ints.push(v),
+                Value::Float(_) => floats.push(v),
                 Value::Str(_) => strings.push(v),
             }
         }
 
-        Self { ints, strings }
+        Self {
+            ints,
+            floats,
+            strings,
+        }
     }
 
     fn value_type(&self, vt: &ValueType) -> Vec<Value> {
         match vt {
             ValueType::Bool => vec![Value::Bool(true), Value::Bool(false)],
             ValueType::Int => self.ints.clone(),
+            ValueType::Float => self.floats.clone(),
             ValueType::Str => self.strings.clone(),
+            ValueType::Enum(_) => vt.vals().into_iter().collect(),
         }
     }
 
@@ -168,7 +177,7 @@ pub struct EnumerativeSynthesis<P: Prune> {
 impl<P: Prune> EnumerativeSynthesis<P> {
     /// Create a new enumerative synthesis instance
     pub fn new(mut problem: Problem, pruner: P) -> Self {
-        let goal = Goal::new(&problem.program.goal);
+        let goal = Goal::new(&problem.program.goals);
         goal.add_to_library(&mut problem.library.functions);
 
         Self {
@@ -261,8 +270,8 @@ impl<P: Prune> EnumerativeSynthesis<P> {
         match e {
             Sketch::Hole(_) => panic!(),
             Sketch::App(f, mut args) => {
-                if f.name == self.goal.function {
-                    args.swap_remove(&self.goal.param).unwrap()
+                if f.name == self.goal.function && !self.goal.multiple() {
+                    args.swap_remove(&self.goal.params[0]).unwrap()
                 } else {
                     Sketch::App(f, args)
                 }
@@ -17,19 +17,78 @@ pub struct Context<'a> {
 }
 
 impl Context<'_> {
-    fn formula_atom(&self, fa: &FormulaAtom) -> Value {
+    /// Evaluates a formula atom (or returns `None` if its arithmetic
+    /// overflows; see [`FormulaAtom::arithmetic`])
+    fn formula_atom(&self, fa: &FormulaAtom) -> Option<Value> {
         match fa {
             FormulaAtom::Param(fp, mp) => {
-                self.args.get(fp).unwrap().get(mp).unwrap().clone()
+                Some(self.args.get(fp).unwrap().get(mp).unwrap().clone())
+            }
+            FormulaAtom::Ret(mp) => Some(self.ret.get(mp).unwrap().clone()),
+            FormulaAtom::Lit(v) => Some(v.clone()),
+            FormulaAtom::Add(fa1, fa2) | FormulaAtom::Sub(fa1, fa2) => {
+                match (self.formula_atom(fa1)?, self.formula_atom(fa2)?) {
+                    (Value::Int(x1), Value::Int(x2)) => {
+                        fa.arithmetic(x1, x2).map(Value::Int)
+                    }
+                    (v1, v2) => panic!(
+                        "arithmetic only supported for ints, got {:?} and {:?}",
+                        v1, v2,
+                    ),
+                }
             }
-            FormulaAtom::Ret(mp) => self.ret.get(mp).unwrap().clone(),
-            FormulaAtom::Lit(v) => v.clone(),
         }
     }
 
+    /// Compares two formula atoms (a comparison involving arithmetic that
+    /// overflows never holds)
+    fn compare(
+        &self,
+        fa1: &FormulaAtom,
+        fa2: &FormulaAtom,
+        int_op: fn(&i64, &i64) -> bool,
+        float_op: fn(&f64, &f64) -> bool,
+    ) -> bool {
+        let (v1, v2) = match (self.formula_atom(fa1), self.formula_atom(fa2)) {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => return false,
+        };
+        match (v1, v2) {
+            (Value::Int(x1), Value::Int(x2)) => int_op(&x1, &x2),
+            (Value::Float(x1), Value::Float(x2)) => float_op(&x1, &x2),
+            (v1, v2) => panic!(
+                "comparisons only supported for ints and floats, got {:?} and {:?}",
+                v1, v2,
+            ),
+        }
+    }
+
+    fn equal(&self, fa1: &FormulaAtom, fa2: &FormulaAtom, eq: bool) -> bool {
+        match (self.formula_atom(fa1), self.formula_atom(fa2)) {
+            (Some(v1), Some(v2)) => (v1 == v2) == eq,
+            _ => false,
+        }
+    }
+
+    /// Evaluates the non-omitted arguments of an atomic proposition (or
+    /// returns `None` if any of their arithmetic overflows)
+    fn atomic_proposition_args(
+        &self,
+        ap: &AtomicProposition,
+    ) -> Option<IndexMap<MetParam, Value>> {
+        ap.args
+            .iter()
+            .filter_map(|(mp, ofa)| {
+                let fa = ofa.as_ref()?;
+                Some(self.formula_atom(fa).map(|v| (mp.clone(), v)))
+            })
+            .collect()
+    }
+
     fn atomic_proposition_matches(
         &self,
         ap: &AtomicProposition,
+        args: &IndexMap<MetParam, Value>,
         prop: &Met<Value>,
     ) -> bool {
         if ap.name != prop.name {
@@ -38,17 +97,14 @@ impl Context<'_> {
         if ap.args.len() != prop.args.len() {
             return false;
         }
-        for (mp, ofa) in &ap.args {
+        for mp in ap.args.keys() {
             let v = match prop.args.get(mp) {
                 Some(v) => v,
                 None => return false,
             };
-            let fa = match ofa {
-                Some(fa) => fa,
-                None => continue,
-            };
-            if self.formula_atom(fa) != *v {
-                return false;
+            match args.get(mp) {
+                Some(arg) if arg != v => return false,
+                _ => (),
             }
         }
         true
@@ -58,30 +114,54 @@ impl Context<'_> {
     pub fn sat(&self, phi: &Formula) -> bool {
         match phi {
             Formula::True => true,
-            Formula::Eq(fa1, fa2) => {
-                self.formula_atom(fa1) == self.formula_atom(fa2)
-            }
-            Formula::Neq(fa1, fa2) => {
-                self.formula_atom(fa1) != self.formula_atom(fa2)
-            }
-            Formula::Lt(fa1, fa2) => {
-                match (self.formula_atom(fa1), self.formula_atom(fa2)) {
-                    (Value::Int(x1), Value::Int(x2)) => x1 < x2,
-                    (v1, v2) => panic!(
-                        "Lt only supported for ints, got {:?} and {:?}",
-                        v1, v2,
-                    ),
-                }
+            Formula::Eq(fa1, fa2) => self.equal(fa1, fa2, true),
+            Formula::Neq(fa1, fa2) => self.equal(fa1, fa2, false),
+            Formula::Lt(fa1, fa2) => self.compare(fa1, fa2, i64::lt, f64::lt),
+            Formula::Le(fa1, fa2) => self.compare(fa1, fa2, i64::le, f64::le),
+            Formula::Gt(fa1, fa2) => self.compare(fa1, fa2, i64::gt, f64::gt),
+            Formula::Ge(fa1, fa2) => self.compare(fa1, fa2, i64::ge, f64::ge),
+            Formula::Ap(ap) => match self.atomic_proposition_args(ap) {
+                Some(args) => self.props.iter().any(|prop| {
+                    self.atomic_proposition_matches(ap, &args, prop)
+                }),
+                None => false,
+            },
+            Formula::And(phi1, phi2) => self.sat(phi1) && self.sat(phi2),
+            Formula::Or(phi1, phi2) => self.sat(phi1) || self.sat(phi2),
+            Formula::Not(phi) => self.sat_negated(phi),
+        }
+    }
+
+    /// Check whether or not the negation of a formula is satisfied in a
+    /// context.
+    ///
+    /// As in the Datalog oracle, negations are pushed inward to comparisons
+    /// and atomic propositions (so, for example, the negation of `x < y` is
+    /// `x >= y`, and neither holds if the arithmetic of `x` overflows). This
+    /// agrees with the complement of `x < y` otherwise, since the typechecker
+    /// rejects NaN.
+    fn sat_negated(&self, phi: &Formula) -> bool {
+        match phi {
+            Formula::True => false,
+            Formula::Eq(fa1, fa2) => self.equal(fa1, fa2, false),
+            Formula::Neq(fa1, fa2) => self.equal(fa1, fa2, true),
+            Formula::Lt(fa1, fa2) => self.compare(fa1, fa2, i64::ge, f64::ge),
+            Formula::Le(fa1, fa2) => self.compare(fa1, fa2, i64::gt, f64::gt),
+            Formula::Gt(fa1, fa2) => self.compare(fa1, fa2, i64::le, f64::le),
+            Formula::Ge(fa1, fa2) => self.compare(fa1, fa2, i64::lt, f64::lt),
+            Formula::Ap(ap) => match self.atomic_proposition_args(ap) {
+                Some(args) => !self.props.iter().any(|prop| {
+                    self.atomic_proposition_matches(ap, &args, prop)
+                }),
+                None => false,
+            },
+            Formula::And(phi1, phi2) => {
+                self.sat_negated(phi1) || self.sat_negated(phi2)
             }
-            Formula::Ap(ap) => {
-                for prop in self.props {
-                    if self.atomic_proposition_matches(ap, prop) {
-                        return true;
-                    }
-                }
-                false
+            Formula::Or(phi1, phi2) => {
+                self.sat_negated(phi1) && self.sat_negated(phi2)
             }
-            Formula::And(phi1, phi2) => self.sat(phi1) && self.sat(phi2),
+            Formula::Not(phi) => self.sat(phi),
         }
     }
 }
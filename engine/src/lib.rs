@@ -10,8 +10,11 @@ mod dl_oracle;
 mod egglog;
 mod enumerate;
 mod eval;
+mod lint;
 mod machine_readable;
 mod parse;
+mod seminaive;
+mod souffle;
 mod top_down;
 mod traditional_synthesis;
 mod typecheck;
@@ -23,12 +26,40 @@ use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+/// The sources of the libraries that libraries may import, keyed by their
+/// path (see [`register_library`])
+static mut IMPORTS: Option<IndexMap<String, String>> = None;
+
+/// Provide the source of a library so that other libraries can import it
+/// (the path is relative to the importing library, which is at the root).
+#[wasm_bindgen]
+#[allow(static_mut_refs)]
+pub fn register_library(path: &str, lib_src: &str) {
+    unsafe {
+        IMPORTS
+            .get_or_insert_with(IndexMap::new)
+            .insert(path.to_owned(), lib_src.to_owned());
+    }
+}
+
+#[allow(static_mut_refs)]
+fn load_library(lib_src: &str) -> Result<core::Library, String> {
+    let imports = unsafe { IMPORTS.get_or_insert_with(IndexMap::new) };
+    match parse::library_with_imports(lib_src, imports) {
+        Ok((library, _)) => Ok(library),
+        Err(parse::LibraryError::Parse(e)) => Err(e),
+        Err(parse::LibraryError::Type(e)) => {
+            Err(format!("type error: {}", e.message))
+        }
+    }
+}
+
 fn load_problem(
     lib_src: &str,
     prog_src: &str,
 ) -> Result<core::Problem, String> {
-    let library = parse::library(&lib_src)?;
-    let program = parse::program(&prog_src)?;
+    let library = load_library(lib_src)?;
+    let program = parse::program(prog_src)?;
     let problem = core::Problem { library, program };
 
     typecheck::problem(&problem)
@@ -39,17 +70,42 @@ fn load_problem(
 
 #[wasm_bindgen]
 pub fn parse_library(lib_src: &str) -> Result<JsValue, String> {
-    let library = parse::library(lib_src)?;
+    let library = load_library(lib_src)?;
     serde_wasm_bindgen::to_value(&library)
         .map_err(|_| "serde_wasm_bindgen error: to_value(library)".to_owned())
 }
 
 #[wasm_bindgen]
-pub fn autopilot(lib_src: &str, prog_src: &str) -> Result<String, String> {
+pub fn lint_library(lib_src: &str) -> Result<JsValue, String> {
+    let library = load_library(lib_src)?;
+
+    typecheck::library(&library)
+        .map_err(|e| format!("type error: {}", e.message))?;
+
+    let engine = egglog::Egglog::new(true);
+    let warnings = lint::library(engine, &library)?;
+
+    serde_wasm_bindgen::to_value(&warnings)
+        .map_err(|_| "serde_wasm_bindgen error: to_value(warnings)".to_owned())
+}
+
+/// Complete a program without interaction (with a minimum-cost expression if
+/// `optimal` is set, otherwise with the first expression found)
+#[wasm_bindgen]
+pub fn autopilot(
+    lib_src: &str,
+    prog_src: &str,
+    optimal: bool,
+) -> Result<String, String> {
     let problem = load_problem(lib_src, prog_src)?;
     let timer = util::Timer::infinite();
-    let start = top_down::Sketch::blank();
-    let mut synth = menu::Algorithm::PBNHoneybee.any_synthesizer(problem);
+    let start = problem.start();
+    let algorithm = if optimal {
+        menu::Algorithm::PBNHoneybeeMinCost
+    } else {
+        menu::Algorithm::PBNHoneybee
+    };
+    let mut synth = algorithm.any_synthesizer(problem)?;
     let hf = match synth.provide_any(&timer, &start) {
         Ok(Some(hf)) => hf,
         Ok(None) => return Err("no solution".to_owned()),
@@ -76,20 +132,30 @@ struct ValidGoalMetadataMessage {
     choices: Vec<IndexMap<String, core::Value>>,
 }
 
+/// Returns the metadata for which the goal of a program is solvable (only for
+/// programs with exactly one goal)
 #[wasm_bindgen]
 pub fn valid_goal_metadata(
     lib_src: &str,
     prog_src: &str,
 ) -> Result<JsValue, String> {
     let problem = load_problem(lib_src, prog_src)?;
-    let goal_name = problem.program.goal.name.0.clone();
+    let goal = match problem.program.goals.as_slice() {
+        [goal] => goal.clone(),
+        goals => {
+            return Err(format!(
+                "valid goal metadata requires exactly one goal (got {})",
+                goals.len()
+            ))
+        }
+    };
 
     let engine = egglog::Egglog::new(true);
     let mut oracle = dl_oracle::Oracle::new(engine, problem)?;
-    let vgm = oracle.valid_goal_metadata();
+    let vgm = oracle.valid_goal_metadata(&goal)?;
 
     let msg = ValidGoalMetadataMessage {
-        goalName: goal_name,
+        goalName: goal.name.0,
         choices: vgm
             .into_iter()
             .map(|assignment| {
@@ -103,6 +169,31 @@ pub fn valid_goal_metadata(
     })
 }
 
+/// Explain why a program cannot be completed, using the Datalog oracle of an
+/// algorithm (`PBNHoneybee` by default)
+#[wasm_bindgen]
+pub fn explain(
+    lib_src: &str,
+    prog_src: &str,
+    algorithm: Option<String>,
+) -> Result<JsValue, String> {
+    let problem = load_problem(lib_src, prog_src)?;
+    let algorithm: menu::Algorithm = match algorithm {
+        Some(a) => a
+            .parse()
+            .map_err(|_| format!("unknown algorithm '{}'", a))?,
+        None => menu::Algorithm::PBNHoneybee,
+    };
+
+    let start = problem.start();
+
+    let mut oracle = algorithm.oracle(problem)?;
+    let explanations = oracle.explain(&start)?;
+
+    serde_wasm_bindgen::to_value(&explanations)
+        .map_err(|_| "serde_wasm_bindgen error in explain".to_owned())
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // PBN Interaction
 
@@ -111,6 +202,7 @@ struct State {
         util::Timer,
         top_down::TopDownStep<core::ParameterizedFunction>,
     >,
+    oracle: Box<dyn dl_oracle::Queries>,
     library: core::Library,
 }
 
@@ -145,11 +237,16 @@ fn send_message() -> Result<JsValue, String> {
 
     let work_exp = state.controller.working_expression();
 
+    let costs = state.oracle.step_costs(&options)?;
+    let counts = state.oracle.step_counts(&options)?;
+
     let msg = PbnStatusMessage {
         cells: cellgen::fill(
             &state.library,
+            &work_exp,
             &options,
-            cellgen::exp(&state.library, &work_exp),
+            &costs,
+            &counts,
         )?,
         output: if state.controller.valid() {
             Some(codegen::jupyter_notebook(&state.library, &work_exp))
@@ -169,9 +266,14 @@ pub fn pbn_init(lib_src: &str, prog_src: &str) -> Result<JsValue, String> {
     let timer = util::Timer::infinite();
     let algorithm = menu::Algorithm::PBNHoneybee;
 
+    let library = problem.library.clone();
+    let (controller, oracle) =
+        algorithm.controller_with_oracle(timer, problem, true)?;
+
     set_state(State {
-        library: problem.library.clone(),
-        controller: algorithm.controller(timer, problem, true),
+        controller,
+        oracle,
+        library,
     });
 
     send_message()
@@ -191,7 +293,7 @@ pub fn pbn_choose(choice_index: usize) -> Result<JsValue, String> {
         for (i, option) in options.iter().enumerate() {
             match option {
                 top_down::TopDownStep::Extend(_, f, _) => {
-                    if f.name.0.starts_with("F_") {
+                    if f.name.local().starts_with("F_") {
                         state
                             .controller
                             .decide_without_history(options.swap_remove(i));
@@ -217,6 +319,47 @@ pub fn pbn_undo() -> Result<JsValue, String> {
     send_message()
 }
 
+#[wasm_bindgen]
+pub fn pbn_retract(path: Vec<String>) -> Result<JsValue, String> {
+    let state = get_state()?;
+    let step = core::Goal::retraction(
+        state.controller.working_expression(),
+        path.into_iter().map(top_down::FunParam).collect(),
+    )
+    .ok_or_else(|| "invalid path to retract".to_owned())?;
+    state.controller.decide(step);
+    send_message()
+}
+
+#[wasm_bindgen]
+pub fn pbn_explain() -> Result<JsValue, String> {
+    let state = get_state()?;
+
+    let explanations = state
+        .oracle
+        .explain(state.controller.working_expression())?;
+
+    serde_wasm_bindgen::to_value(&explanations)
+        .map_err(|_| "serde_wasm_bindgen error in pbn_explain".to_owned())
+}
+
+#[wasm_bindgen]
+pub fn pbn_witness(choice_index: usize) -> Result<JsValue, String> {
+    let state = get_state()?;
+    let options = state.controller.provide().map_err(|e| format!("{:?}", e))?;
+    let witness = match options.get(choice_index) {
+        Some(top_down::TopDownStep::Extend(_, f, _)) => {
+            state.oracle.witness(f)?
+        }
+        // A reused expression is its own witness
+        Some(top_down::TopDownStep::Reuse(_, e)) => Some(e.clone()),
+        _ => return Err(format!("invalid choice index {}", choice_index)),
+    };
+
+    serde_wasm_bindgen::to_value(&witness)
+        .map_err(|_| "serde_wasm_bindgen error in pbn_witness".to_owned())
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Python bindings
 
@@ -224,7 +367,10 @@ pub fn pbn_undo() -> Result<JsValue, String> {
 mod honeybee {
     use crate::unparse;
 
-    use super::{cellgen, core, menu, parse, top_down, typecheck, util};
+    use super::{
+        cellgen, core, datalog, dl_oracle, egglog, lint, menu, parse, top_down,
+        typecheck, util,
+    };
 
     use pyo3::exceptions::PyValueError;
     use pyo3::prelude::*;
@@ -236,48 +382,59 @@ mod honeybee {
             util::Timer,
             top_down::TopDownStep<core::ParameterizedFunction>,
         >,
+        _oracle: Box<dyn dl_oracle::Queries>,
         _library: core::Library,
     }
 
+    fn type_error(e: typecheck::Error, source_map: &parse::SourceMap) -> PyErr {
+        if let Some(report) = source_map.report(&e) {
+            return PyValueError::new_err(report);
+        }
+
+        PyValueError::new_err(format!(
+            "type error: {}\n  occurred:{}",
+            e.message,
+            e.context
+                .into_iter()
+                .map(|ctx| format!("\n    - in {}", ctx))
+                .collect::<Vec<_>>()
+                .join("")
+        ))
+    }
+
+    fn load_library(
+        library: &str,
+    ) -> pyo3::PyResult<(core::Library, parse::SourceMap)> {
+        parse::library_file(std::path::Path::new(library)).map_err(
+            |e| match e {
+                parse::LibraryError::Parse(e) => PyValueError::new_err(
+                    format!("parse error (library):\n{}", e),
+                ),
+                parse::LibraryError::Type(e) => {
+                    type_error(e, &parse::SourceMap::default())
+                }
+            },
+        )
+    }
+
     fn load_problem(
         library: &str,
         program: &str,
     ) -> pyo3::PyResult<core::Problem> {
-        let lib_string = std::fs::read_to_string(library).map_err(|e| {
-            PyValueError::new_err(format!(
-                "error while reading library file: {}",
-                e.to_string()
-            ))
-        })?;
-
-        let prog_string = std::fs::read_to_string(program).map_err(|e| {
-            PyValueError::new_err(format!(
-                "error while reading program file: {}",
-                e.to_string()
-            ))
-        })?;
+        let (library, mut source_map) = load_library(library)?;
 
-        let library = parse::library(&lib_string).map_err(|e| {
-            PyValueError::new_err(format!("parse error (library):\n{}", e))
-        })?;
-
-        let program = parse::program(&prog_string).map_err(|e| {
+        let (program, program_source_map) = parse::program_file(
+            std::path::Path::new(program),
+        )
+        .map_err(|e| {
             PyValueError::new_err(format!("parse error (program):\n{}", e))
         })?;
 
+        source_map.extend(program_source_map);
+
         let problem = core::Problem { library, program };
 
-        typecheck::problem(&problem).map_err(|e| {
-            PyValueError::new_err(format!(
-                "type error: {}\n  occurred:{}",
-                e.message,
-                e.context
-                    .into_iter()
-                    .map(|ctx| format!("\n    - in {}", ctx))
-                    .collect::<Vec<_>>()
-                    .join("")
-            ))
-        })?;
+        typecheck::problem(&problem).map_err(|e| type_error(e, &source_map))?;
 
         Ok(problem)
     }
@@ -286,6 +443,69 @@ mod honeybee {
         PyValueError::new_err("Out of time")
     }
 
+    fn engine_error(e: datalog::Error) -> PyErr {
+        PyValueError::new_err(format!("engine error: {}", e))
+    }
+
+    fn early_cutoff(e: util::EarlyCutoff) -> PyErr {
+        match e {
+            util::EarlyCutoff::Engine(e) => engine_error(e),
+            _ => out_of_time(),
+        }
+    }
+
+    /// Synthesize up to k complete expressions for a problem that differ in
+    /// the functions they use (cheapest first)
+    #[pyfunction]
+    #[pyo3(signature = (library, program, k = 3, timeout = 10))]
+    fn diverse(
+        py: Python,
+        library: &str,
+        program: &str,
+        k: usize,
+        timeout: u64,
+    ) -> PyResult<Vec<Py<PyAny>>> {
+        let problem = load_problem(library, program)?;
+        let start = problem.start();
+
+        let mut synth =
+            menu::diverse_synthesizer(problem).map_err(engine_error)?;
+        let hfs = synth
+            .provide_diverse(
+                &util::Timer::finite(instant::Duration::from_secs(timeout)),
+                &start,
+                k,
+            )
+            .map_err(early_cutoff)?;
+
+        Ok(hfs
+            .into_iter()
+            .map(|hf| {
+                let mut e = start.clone();
+                for (h, rhs) in hf {
+                    e = e.substitute(h, &rhs);
+                }
+                pythonize(py, &e).unwrap().unbind()
+            })
+            .collect())
+    }
+
+    /// Lint a library file, returning its lint warnings
+    #[pyfunction]
+    fn lint_library(py: Python, library: &str) -> PyResult<Vec<Py<PyAny>>> {
+        let (library, source_map) = load_library(library)?;
+
+        typecheck::library(&library).map_err(|e| type_error(e, &source_map))?;
+
+        let engine = egglog::Egglog::new(true);
+        let warnings = lint::library(engine, &library).map_err(engine_error)?;
+
+        Ok(warnings
+            .iter()
+            .map(|w| pythonize(py, w).unwrap().unbind())
+            .collect())
+    }
+
     #[pymethods]
     impl Controller {
         #[new]
@@ -305,9 +525,15 @@ mod honeybee {
                     ))
                 })?;
 
+            let library = problem.library.clone();
+            let (controller, oracle) = algorithm
+                .controller_with_oracle(timer, problem, false)
+                .map_err(engine_error)?;
+
             Ok(Self {
-                _library: problem.library.clone(),
-                _controller: algorithm.controller(timer, problem, false),
+                _controller: controller,
+                _oracle: oracle,
+                _library: library,
             })
         }
 
@@ -318,16 +544,19 @@ mod honeybee {
         }
 
         fn provide(&mut self, py: Python) -> PyResult<Vec<Py<PyAny>>> {
-            let options =
-                self._controller.provide().map_err(|_| out_of_time())?;
+            let options = self._controller.provide().map_err(early_cutoff)?;
+
+            let costs =
+                self._oracle.step_costs(&options).map_err(engine_error)?;
+            let counts =
+                self._oracle.step_counts(&options).map_err(engine_error)?;
 
             let function_choices: Vec<_> = cellgen::fill(
                 &self._library,
+                &self._controller.working_expression(),
                 &options,
-                cellgen::exp(
-                    &self._library,
-                    &self._controller.working_expression(),
-                ),
+                &costs,
+                &counts,
             )
             .unwrap()
             .into_iter()
@@ -347,7 +576,7 @@ mod honeybee {
 
         fn decide(&mut self, index: usize) -> PyResult<()> {
             let mut options =
-                self._controller.provide().map_err(|_| out_of_time())?;
+                self._controller.provide().map_err(early_cutoff)?;
             if index >= options.len() {
                 return Err(PyValueError::new_err(format!(
                     "Index out of bounds for options: {} (length = {})",
@@ -358,5 +587,17 @@ mod honeybee {
             self._controller.decide(options.swap_remove(index));
             Ok(())
         }
+
+        /// Turn the function application at a path (of parameter names) of
+        /// the working expression back into a hole
+        fn retract(&mut self, path: Vec<String>) -> PyResult<()> {
+            let step = core::Goal::retraction(
+                self._controller.working_expression(),
+                path.into_iter().map(top_down::FunParam).collect(),
+            )
+            .ok_or_else(|| PyValueError::new_err("Invalid path to retract"))?;
+            self._controller.decide(step);
+            Ok(())
+        }
     }
 }
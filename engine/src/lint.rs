@@ -0,0 +1,527 @@
+//! # Linting Honeybee libraries
+//!
+//! This module checks (well-typed) libraries for definitions that can never
+//! take part in a solution, such as types that no function returns or
+//! functions whose condition can never hold, or types that can only be
+//! derived without any input (`P_*`) props.
+//!
+//! The checks that depend on conditions reuse the Datalog compilation of
+//! [`crate::dl_oracle`]. Since a library is linted without a program, every
+//! atomic proposition (negated or not) is assumed to be satisfiable; the
+//! remaining constraints are checked relative to the values of the library,
+//! along with enough fresh values that variables unconstrained by the library
+//! may always take on distinct values.
+
+use crate::core::*;
+use crate::datalog::{self, Engine, Predicate, Relation, Rule};
+use crate::dl_oracle::CompileContext;
+use crate::typecheck::{self, Location};
+
+use indexmap::{IndexMap, IndexSet};
+use serde::Serialize;
+
+/// The kinds of lint warnings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Lint {
+    /// A prop that no function condition refers to
+    UnusedProp,
+    /// A type that no function returns
+    UnreturnedType,
+    /// A type that is returned by some function, but that cannot be derived
+    /// from any props
+    UnderivableType,
+    /// A type that can be derived, but only without any input (`P_*`) props
+    NoInputPath,
+    /// A function whose condition is unsatisfiable for every choice of
+    /// metadata values
+    UnsatisfiableCondition,
+}
+
+impl Lint {
+    /// A short, human-readable title for the lint
+    pub fn title(&self) -> &'static str {
+        match self {
+            Lint::UnusedProp => "Unused prop",
+            Lint::UnreturnedType => "Unreturned type",
+            Lint::UnderivableType => "Underivable type",
+            Lint::NoInputPath => "No input path",
+            Lint::UnsatisfiableCondition => "Unsatisfiable condition",
+        }
+    }
+
+    /// The severity of the lint
+    pub fn severity(&self) -> Severity {
+        match self {
+            Lint::UnusedProp | Lint::NoInputPath => Severity::Info,
+            Lint::UnreturnedType
+            | Lint::UnderivableType
+            | Lint::UnsatisfiableCondition => Severity::Warning,
+        }
+    }
+}
+
+/// The severity of a lint warning.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Severity {
+    /// The definition is unused, but harmless
+    Info,
+    /// The definition is (likely) a mistake
+    Warning,
+}
+
+/// The type of lint warnings.
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    pub lint: Lint,
+    pub severity: Severity,
+    pub location: Location,
+    pub message: String,
+}
+
+impl Warning {
+    fn new(lint: Lint, location: Location, message: String) -> Self {
+        Self {
+            severity: lint.severity(),
+            lint,
+            location,
+            message,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Syntactic checks
+
+fn unused_props(library: &Library) -> Vec<Warning> {
+    let used: IndexSet<&MetName> = library
+        .functions
+        .values()
+        .flat_map(|fs| fs.condition.propositions())
+        .map(|ap| &ap.name)
+        .collect();
+
+    library
+        .props
+        .keys()
+        .filter(|mn| !used.contains(mn))
+        .map(|mn| {
+            Warning::new(
+                Lint::UnusedProp,
+                Location::Prop(mn.clone()),
+                format!("prop '{}' is not referenced by any condition", mn.0),
+            )
+        })
+        .collect()
+}
+
+fn unreturned_types(library: &Library) -> Vec<Warning> {
+    library
+        .types
+        .keys()
+        .filter(|mn| !returned(library, mn))
+        .map(|mn| {
+            Warning::new(
+                Lint::UnreturnedType,
+                Location::Type(mn.clone()),
+                format!("type '{}' is not returned by any function", mn.0),
+            )
+        })
+        .collect()
+}
+
+fn returned(library: &Library, mn: &MetName) -> bool {
+    library.functions.values().any(|fs| fs.ret == *mn)
+}
+
+/// Whether a prop is an input prop (that is, whether its name is
+/// `P_`-prefixed)
+fn is_input_prop(mn: &MetName) -> bool {
+    mn.local().starts_with("P_")
+}
+
+/// Whether a function is an input function (that is, whether its condition
+/// refers to an input prop)
+fn is_input_function(fs: &FunctionSignature) -> bool {
+    fs.condition
+        .propositions()
+        .iter()
+        .any(|ap| is_input_prop(&ap.name))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Datalog-backed checks
+
+/// Relaxes a header rule by dropping its EDB facts (that is, its atomic
+/// propositions) and, if `keep_types` is false, its IDB facts as well.
+fn relax(
+    lib: &datalog::RelationLibrary,
+    rule: &Rule,
+    keep_types: bool,
+) -> Rule {
+    Rule {
+        name: rule.name.clone(),
+        head: rule.head.clone(),
+        body: rule
+            .body
+            .iter()
+            .filter(|p| match p {
+                Predicate::Fact(f) => {
+                    keep_types
+                        && lib.get(&f.relation).unwrap().kind
+                            == datalog::RelationKind::IDB
+                }
+                _ => true,
+            })
+            .cloned()
+            .collect(),
+    }
+}
+
+/// The relation of the metadata of a type that can be derived using at least
+/// one input function.
+fn input_relation(relation: &Relation) -> Relation {
+    Relation(format!("&Input_{}", relation.0))
+}
+
+/// The rules deriving the input relations from a relaxed header rule: an input
+/// function derives an input fact outright, and any other function derives
+/// one if one of its arguments is an input fact.
+fn input_rules(
+    lib: &datalog::RelationLibrary,
+    fs: &FunctionSignature,
+    relaxed: &Rule,
+) -> Vec<Rule> {
+    let mut head = relaxed.head.clone();
+    head.relation = input_relation(&head.relation);
+
+    if is_input_function(fs) {
+        return vec![Rule {
+            name: format!("&input_{}", relaxed.name),
+            head,
+            body: relaxed.body.clone(),
+        }];
+    }
+
+    relaxed
+        .body
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| match p {
+            Predicate::Fact(f)
+                if lib.get(&f.relation).unwrap().kind
+                    == datalog::RelationKind::IDB =>
+            {
+                let mut body = relaxed.body.clone();
+                body[i] = Predicate::Fact(datalog::Fact {
+                    relation: input_relation(&f.relation),
+                    args: f.args.clone(),
+                });
+                Some(Rule {
+                    name: format!("&input_{}#{}", relaxed.name, i),
+                    head: head.clone(),
+                    body,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The values of a library, along with `n` fresh values of each type (where
+/// `n` is the largest number of variables in any header rule) on either side
+/// of the numeric values, as well as the values strictly between numeric
+/// values (for strict comparisons).
+fn domain(
+    library: &Library,
+    header: &[(BaseFunction, Rule)],
+) -> IndexSet<Value> {
+    let n = header
+        .iter()
+        .map(|(_, rule)| {
+            rule.vals()
+                .iter()
+                .filter(|v| matches!(v, datalog::Value::Var { .. }))
+                .count()
+        })
+        .max()
+        .unwrap_or(0) as i64;
+
+    let mut dom = library.vals();
+
+    let mut ints: Vec<i64> = dom
+        .iter()
+        .filter_map(|v| match v {
+            Value::Int(x) => Some(*x),
+            _ => None,
+        })
+        .collect();
+    ints.sort();
+    for x in &ints {
+        dom.extend(x.checked_sub(1).map(Value::Int));
+        dom.extend(x.checked_add(1).map(Value::Int));
+    }
+    let (min_int, max_int) = match (ints.first(), ints.last()) {
+        (Some(min), Some(max)) => (*min, *max),
+        _ => (1, 0),
+    };
+    for i in 1..=n {
+        dom.extend(min_int.checked_sub(i).map(Value::Int));
+        dom.extend(max_int.checked_add(i).map(Value::Int));
+    }
+
+    let mut floats: Vec<f64> = dom
+        .iter()
+        .filter_map(|v| match v {
+            Value::Float(x) if x.is_finite() => Some(*x),
+            _ => None,
+        })
+        .collect();
+    floats.sort_by(f64::total_cmp);
+    for w in floats.windows(2) {
+        dom.insert(Value::Float(w[0] + (w[1] - w[0]) / 2.0));
+    }
+    let (min_float, max_float) = match (floats.first(), floats.last()) {
+        (Some(min), Some(max)) => (*min, *max),
+        _ => (1.0, 0.0),
+    };
+    for i in 1..=n {
+        dom.insert(Value::Float(min_float - i as f64));
+        dom.insert(Value::Float(max_float + i as f64));
+    }
+
+    let fresh_strs: Vec<_> = (0..)
+        .map(|i| Value::Str(format!("&{}", i)))
+        .filter(|v| !dom.contains(v))
+        .take(n as usize)
+        .collect();
+    dom.extend(fresh_strs);
+
+    library.extend_arithmetic(&mut dom);
+
+    dom
+}
+
+fn datalog_checks<Eng: Engine>(
+    mut engine: Eng,
+    library: &Library,
+) -> Result<Vec<Warning>, datalog::Error> {
+    let compile = CompileContext(typecheck::Context(library));
+    let header = compile.header();
+
+    let mut lib = compile.signatures();
+
+    let relaxed: Vec<_> = header
+        .iter()
+        .map(|(f, rule)| (f, relax(&lib, rule, true)))
+        .collect();
+    let mut rules: Vec<_> = relaxed
+        .iter()
+        .flat_map(|(f, rule)| input_rules(&lib, &library.functions[*f], rule))
+        .collect();
+    rules.extend(relaxed.into_iter().map(|(_, rule)| rule));
+
+    for mn in library.types.keys() {
+        lib.insert(
+            input_relation(&Relation(mn.0.clone())),
+            compile.query_signature(mn),
+        );
+    }
+
+    let satisfiability_queries: IndexMap<&BaseFunction, Vec<Rule>> =
+        header.iter().fold(IndexMap::new(), |mut acc, (f, rule)| {
+            let mut query = relax(&lib, rule, false);
+            query.name = format!("&satisfiable_{}", rule.name);
+            query.head.relation = Relation("&Satisfiable".to_owned());
+            acc.entry(f).or_insert_with(Vec::new).push(query);
+            acc
+        });
+
+    engine.load(datalog::Program::new(
+        lib,
+        domain(library, &header)
+            .iter()
+            .map(|v| compile.value(v))
+            .collect(),
+        rules,
+        vec![],
+    )?)?;
+
+    let mut warnings = vec![];
+
+    // Only libraries with input props have input paths
+    let has_inputs = library.props.keys().any(is_input_prop);
+
+    for mn in library.types.keys() {
+        if !returned(library, mn) {
+            continue;
+        }
+        let (mut query, query_sig) = compile.solvability_query(mn);
+        if engine.query(&query_sig, &query)?.is_empty() {
+            warnings.push(Warning::new(
+                Lint::UnderivableType,
+                Location::Type(mn.clone()),
+                format!("type '{}' cannot be derived from any props", mn.0),
+            ));
+            continue;
+        }
+        if !has_inputs {
+            continue;
+        }
+        for p in &mut query.body {
+            if let Predicate::Fact(f) = p {
+                f.relation = input_relation(&f.relation);
+            }
+        }
+        if engine.query(&query_sig, &query)?.is_empty() {
+            warnings.push(Warning::new(
+                Lint::NoInputPath,
+                Location::Type(mn.clone()),
+                format!(
+                    "type '{}' can only be derived without input (`P_*`) props",
+                    mn.0
+                ),
+            ));
+        }
+    }
+
+    for (f, fs) in &library.functions {
+        let query_sig = compile.query_signature(&fs.ret);
+        let mut satisfiable = false;
+        for q in satisfiability_queries.get(f).into_iter().flatten() {
+            if !engine.query(&query_sig, q)?.is_empty() {
+                satisfiable = true;
+                break;
+            }
+        }
+        if !satisfiable {
+            warnings.push(Warning::new(
+                Lint::UnsatisfiableCondition,
+                Location::Function(f.clone()),
+                format!(
+                    "condition of function '{}' is unsatisfiable for every \
+                     choice of metadata",
+                    f.0
+                ),
+            ));
+        }
+    }
+
+    Ok(warnings)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Main
+
+/// Lint a (well-typed) library, using the given Datalog engine for the checks
+/// that depend on function conditions.
+pub fn library<Eng: Engine>(
+    engine: Eng,
+    library: &Library,
+) -> Result<Vec<Warning>, datalog::Error> {
+    let mut warnings = unused_props(library);
+    warnings.extend(unreturned_types(library));
+    warnings.extend(datalog_checks(engine, library)?);
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::egglog::Egglog;
+    use crate::parse;
+
+    const LIBRARY: &str = r#"
+        [Prop.P_Reads]
+        params.sample = "Str"
+
+        [Prop.Unused]
+        params = {}
+
+        [Type.Reads]
+        params.sample = "Str"
+
+        [Type.Orphan]
+        params = {}
+
+        [Type.Never]
+        params.n = "Int"
+
+        [Type.Constant]
+        params.n = "Int"
+
+        [Type.Scaled]
+        params = {}
+
+        [Function.F_Reads]
+        params = {}
+        ret = "Reads"
+        condition = ["P_Reads { sample = ret.sample }"]
+
+        [Function.never]
+        params = {}
+        ret = "Never"
+        condition = ["ret.n < 0", "ret.n > 0"]
+
+        [Function.constant]
+        params = {}
+        ret = "Constant"
+        condition = ["ret.n = 1"]
+
+        [Function.scale]
+        params.c = "Constant"
+        params.r = "Reads"
+        ret = "Scaled"
+        condition = []
+    "#;
+
+    fn lint(src: &str) -> Vec<(Lint, Severity, Location)> {
+        library(Egglog::new(true), &parse::library(src).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|w| (w.lint, w.severity, w.location))
+            .collect()
+    }
+
+    #[test]
+    fn lints_have_severities_and_locations() {
+        let mn = |s: &str| MetName(s.to_owned());
+        assert_eq!(
+            lint(LIBRARY),
+            vec![
+                (
+                    Lint::UnusedProp,
+                    Severity::Info,
+                    Location::Prop(mn("Unused"))
+                ),
+                (
+                    Lint::UnreturnedType,
+                    Severity::Warning,
+                    Location::Type(mn("Orphan"))
+                ),
+                (
+                    Lint::UnderivableType,
+                    Severity::Warning,
+                    Location::Type(mn("Never"))
+                ),
+                (
+                    Lint::NoInputPath,
+                    Severity::Info,
+                    Location::Type(mn("Constant"))
+                ),
+                (
+                    Lint::UnsatisfiableCondition,
+                    Severity::Warning,
+                    Location::Function(BaseFunction("never".to_owned()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn input_paths_are_only_checked_with_input_props() {
+        let src = LIBRARY.replace("P_Reads", "Sequenced");
+        assert!(lint(&src)
+            .iter()
+            .all(|(lint, _, _)| *lint != Lint::NoInputPath));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{cellgen, core, top_down, unparse, util};
+use crate::{cellgen, core, datalog, dl_oracle, top_down, unparse, util};
 
 use jsonrpcmsg::{Error, Id, Params, Request, Response};
 use serde::Serialize;
@@ -12,6 +12,9 @@ pub enum DeciderMessage {
     WorkingExpression,
     Provide,
     Decide { index: usize },
+    Explain,
+    Witness { index: usize },
+    Retract { path: Vec<top_down::FunParam> },
     Quit,
 }
 
@@ -20,6 +23,9 @@ pub enum ProviderMessage {
     WorkingExpression(String),
     Steps(Vec<cellgen::FunctionChoice>),
     AckDecide,
+    Explanations(Vec<dl_oracle::Explanation>),
+    Witness(Option<String>),
+    AckRetract,
     AckQuit,
 }
 
@@ -31,14 +37,27 @@ fn no_more_steps() -> Error {
     Error::new(2, "No more steps".to_owned())
 }
 
+fn engine_error(e: datalog::Error) -> Error {
+    Error::new(3, e.to_string())
+}
+
+fn early_cutoff(e: util::EarlyCutoff) -> Error {
+    match e {
+        util::EarlyCutoff::Engine(e) => engine_error(e),
+        _ => out_of_time(),
+    }
+}
+
 fn handle(
-    library: &core::Library,
+    problem: &core::Problem,
     controller: &mut pbn::Controller<
         util::Timer,
         top_down::TopDownStep<core::ParameterizedFunction>,
     >,
+    oracle: &mut dyn dl_oracle::Queries,
     decider_message: &DeciderMessage,
 ) -> Result<ProviderMessage, Error> {
+    let library = &problem.library;
     match decider_message {
         DeciderMessage::WorkingExpression => {
             Ok(ProviderMessage::WorkingExpression(
@@ -46,11 +65,15 @@ fn handle(
             ))
         }
         DeciderMessage::Provide => {
-            let options = controller.provide().map_err(|_| out_of_time())?;
+            let options = controller.provide().map_err(early_cutoff)?;
+            let costs = oracle.step_costs(&options).map_err(engine_error)?;
+            let counts = oracle.step_counts(&options).map_err(engine_error)?;
             let function_choices = cellgen::fill(
                 library,
+                &controller.working_expression(),
                 &options,
-                cellgen::exp(&library, &controller.working_expression()),
+                &costs,
+                &counts,
             )
             .unwrap()
             .into_iter()
@@ -64,11 +87,43 @@ fn handle(
             Ok(ProviderMessage::Steps(function_choices))
         }
         DeciderMessage::Decide { index } => {
-            let mut options =
-                controller.provide().map_err(|_| out_of_time())?;
+            let mut options = controller.provide().map_err(early_cutoff)?;
             controller.decide(options.swap_remove(*index));
             Ok(ProviderMessage::AckDecide)
         }
+        DeciderMessage::Explain => Ok(ProviderMessage::Explanations(
+            oracle
+                .explain(controller.working_expression())
+                .map_err(engine_error)?,
+        )),
+        DeciderMessage::Witness { index } => {
+            let options = controller.provide().map_err(early_cutoff)?;
+            let f = match options.get(*index) {
+                Some(top_down::TopDownStep::Extend(_, f, _)) => f,
+                // A reused expression is its own witness
+                Some(top_down::TopDownStep::Reuse(_, e)) => {
+                    return Ok(ProviderMessage::Witness(Some(
+                        unparse::exp(e).unwrap(),
+                    )))
+                }
+                _ => return Err(Error::invalid_params()),
+            };
+            Ok(ProviderMessage::Witness(
+                oracle
+                    .witness(f)
+                    .map_err(engine_error)?
+                    .map(|w| unparse::exp(&w).unwrap()),
+            ))
+        }
+        DeciderMessage::Retract { path } => {
+            let step = core::Goal::retraction(
+                controller.working_expression(),
+                path.clone(),
+            )
+            .ok_or_else(Error::invalid_params)?;
+            controller.decide(step);
+            Ok(ProviderMessage::AckRetract)
+        }
         DeciderMessage::Quit => Ok(ProviderMessage::AckQuit),
     }
 }
@@ -76,26 +131,50 @@ fn handle(
 ////////////////////////////////////////////////////////////////////////////////
 // Parsing/unparsing
 
+fn index_param(r: &Request) -> Result<usize, Error> {
+    match r.params.as_ref().ok_or_else(Error::invalid_params)? {
+        Params::Array(values) => {
+            if values.len() == 1 {
+                values[0]
+                    .as_u64()
+                    .and_then(|v| usize::try_from(v).ok())
+                    .ok_or_else(Error::invalid_params)
+            } else {
+                Err(Error::invalid_params())
+            }
+        }
+        Params::Object(_) => Err(Error::invalid_params()),
+    }
+}
+
+fn path_param(r: &Request) -> Result<Vec<top_down::FunParam>, Error> {
+    match r.params.as_ref().ok_or_else(Error::invalid_params)? {
+        Params::Array(values) => values
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(|s| top_down::FunParam(s.to_owned()))
+                    .ok_or_else(Error::invalid_params)
+            })
+            .collect(),
+        Params::Object(_) => Err(Error::invalid_params()),
+    }
+}
+
 fn request_to_message(r: &Request) -> Result<DeciderMessage, Error> {
     match r.method.as_str() {
         "working_expression" => Ok(DeciderMessage::WorkingExpression),
         "provide" => Ok(DeciderMessage::Provide),
-        "decide" => {
-            match r.params.as_ref().ok_or_else(|| Error::invalid_params())? {
-                Params::Array(values) => {
-                    if values.len() == 1 {
-                        let index = values[0]
-                            .as_u64()
-                            .and_then(|v| usize::try_from(v).ok())
-                            .ok_or_else(|| Error::invalid_params())?;
-                        Ok(DeciderMessage::Decide { index })
-                    } else {
-                        Err(Error::invalid_params())
-                    }
-                }
-                Params::Object(_) => Err(Error::invalid_params()),
-            }
-        }
+        "decide" => Ok(DeciderMessage::Decide {
+            index: index_param(r)?,
+        }),
+        "explain" => Ok(DeciderMessage::Explain),
+        "witness" => Ok(DeciderMessage::Witness {
+            index: index_param(r)?,
+        }),
+        "retract" => Ok(DeciderMessage::Retract {
+            path: path_param(r)?,
+        }),
         "quit" => Ok(DeciderMessage::Quit),
         _ => Err(Error::method_not_found()),
     }
@@ -110,6 +189,11 @@ fn message_to_response(
             serde_json::to_value(function_choices).unwrap()
         }
         ProviderMessage::AckDecide => json!("ack_decide"),
+        ProviderMessage::Explanations(explanations) => {
+            serde_json::to_value(explanations).unwrap()
+        }
+        ProviderMessage::Witness(w) => json!(w),
+        ProviderMessage::AckRetract => json!("ack_retract"),
         ProviderMessage::AckQuit => json!("ack_quit"),
     }
 }
@@ -162,11 +246,12 @@ fn maybe_respond_success(v: serde_json::Value, id: Option<Id>) {
 // Main
 
 pub fn interact(
-    library: &core::Library,
+    problem: &core::Problem,
     controller: &mut pbn::Controller<
         util::Timer,
         top_down::TopDownStep<core::ParameterizedFunction>,
     >,
+    oracle: &mut dyn dl_oracle::Queries,
 ) -> Result<(), String> {
     loop {
         let request = match parse_input() {
@@ -186,7 +271,7 @@ pub fn interact(
         };
 
         let provider_message =
-            match handle(library, controller, &decider_message) {
+            match handle(problem, controller, oracle, &decider_message) {
                 Ok(pm) => pm,
                 Err(e) => {
                     maybe_respond_error(e, request.id);
@@ -205,3 +290,258 @@ pub fn interact(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{menu, parse};
+
+    const LIBRARY: &str = r#"
+        [Type.Num]
+        params.n = "Int"
+
+        [Function.zero]
+        params = {}
+        ret = "Num"
+        condition = ["ret.n = 0"]
+
+        [Function.inc]
+        params.x = "Num"
+        ret = "Num"
+        condition = ["ret.n = x.n + 1"]
+    "#;
+
+    fn problem() -> core::Problem {
+        core::Problem {
+            library: parse::library(LIBRARY).unwrap(),
+            program: parse::program(
+                r#"
+                Prop = []
+
+                [Goal]
+                name = "Num"
+                args.n = 2
+                "#,
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Answers a JSON-RPC request like [`interact`] does
+    fn call(
+        problem: &core::Problem,
+        controller: &mut menu::Controller,
+        oracle: &mut dyn dl_oracle::Queries,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, Error> {
+        let request = jsonrpcmsg::deserialize::from_request_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }))
+        .unwrap();
+        let decider_message = request_to_message(&request)?;
+        handle(problem, controller, oracle, &decider_message)
+            .map(|pm| message_to_response(&pm))
+    }
+
+    /// Decides the (only) choice for a function, by its title
+    fn choose(
+        problem: &core::Problem,
+        controller: &mut menu::Controller,
+        oracle: &mut dyn dl_oracle::Queries,
+        title: &str,
+    ) {
+        let steps =
+            call(problem, controller, oracle, "provide", json!([])).unwrap();
+        let index = steps
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["function_title"] == title)
+            .unwrap_or_else(|| panic!("no choice for {} in {}", title, steps))
+            ["metadata_choices"][0]["choice_index"]
+            .clone();
+        assert_eq!(
+            call(problem, controller, oracle, "decide", json!([index])),
+            Ok(json!("ack_decide"))
+        );
+    }
+
+    #[test]
+    fn navigation_resumes_after_retract() {
+        let problem = problem();
+        let (mut controller, mut oracle) = menu::Algorithm::PBNHoneybee
+            .controller_with_oracle(
+                util::Timer::infinite(),
+                problem.clone(),
+                false,
+            )
+            .unwrap();
+        let oracle = oracle.as_mut();
+
+        for title in ["inc", "inc", "zero"] {
+            choose(&problem, &mut controller, oracle, title);
+        }
+        let complete = controller.working_expression().clone();
+        assert!(controller.valid());
+
+        assert_eq!(
+            call(&problem, &mut controller, oracle, "retract", json!(["x"])),
+            Ok(json!("ack_retract"))
+        );
+        assert!(!controller.valid());
+        assert!(matches!(
+            controller
+                .working_expression()
+                .at(&[top_down::FunParam("x".to_owned())]),
+            Some(top_down::Sketch::Hole(_))
+        ));
+
+        for title in ["inc", "zero"] {
+            choose(&problem, &mut controller, oracle, title);
+        }
+        assert_eq!(controller.working_expression(), &complete);
+    }
+
+    #[test]
+    fn retract_rejects_invalid_paths() {
+        let problem = problem();
+        let (mut controller, mut oracle) = menu::Algorithm::PBNHoneybee
+            .controller_with_oracle(
+                util::Timer::infinite(),
+                problem.clone(),
+                false,
+            )
+            .unwrap();
+        let oracle = oracle.as_mut();
+
+        // There is nothing to retract in a blank expression
+        assert_eq!(
+            call(&problem, &mut controller, oracle, "retract", json!([])),
+            Err(Error::invalid_params())
+        );
+
+        choose(&problem, &mut controller, oracle, "inc");
+        for path in [json!(["x"]), json!(["y"]), json!(["x", "x"])] {
+            assert_eq!(
+                call(&problem, &mut controller, oracle, "retract", path),
+                Err(Error::invalid_params())
+            );
+        }
+        assert_eq!(
+            call(&problem, &mut controller, oracle, "retract", json!([1])),
+            Err(Error::invalid_params())
+        );
+    }
+
+    // Summarizing is cheaper than assembling, and short reads are cheaper
+    // than long ones
+    const COSTS: &str = r#"
+        [Type.Reads]
+        params.kind = "Str"
+
+        [Type.Report]
+        params.kind = "Str"
+
+        [Function.illumina]
+        params = {}
+        ret = "Reads"
+        condition = ['ret.kind = "short"']
+        info.cost = 1
+
+        [Function.nanopore]
+        params = {}
+        ret = "Reads"
+        condition = ['ret.kind = "long"']
+        info.cost = 5
+
+        [Function.summarize]
+        params.r = "Reads"
+        ret = "Report"
+        condition = ["ret.kind = r.kind"]
+        info.cost = 1
+
+        [Function.assemble]
+        params.r = "Reads"
+        ret = "Report"
+        condition = ["ret.kind = r.kind"]
+        info.cost = 3
+    "#;
+
+    #[test]
+    fn provide_ranks_choices_by_completion_cost() {
+        let problem = core::Problem {
+            library: parse::library(COSTS).unwrap(),
+            program: parse::program(
+                r#"
+                Prop = []
+
+                [Goal]
+                name = "Report"
+                "#,
+            )
+            .unwrap(),
+        };
+        let (mut controller, mut oracle) = menu::Algorithm::PBNHoneybee
+            .controller_with_oracle(
+                util::Timer::infinite(),
+                problem.clone(),
+                false,
+            )
+            .unwrap();
+
+        let steps = call(
+            &problem,
+            &mut controller,
+            oracle.as_mut(),
+            "provide",
+            json!([]),
+        )
+        .unwrap();
+        let ranking: Vec<_> = steps
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| {
+                (
+                    c["function_title"].clone(),
+                    c["cost"].clone(),
+                    c["metadata_choices"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|mc| {
+                            (mc["metadata"]["kind"].clone(), mc["cost"].clone())
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+
+        // Choices are ranked by their cheapest completion rather than by title
+        assert_eq!(
+            ranking,
+            vec![
+                (
+                    json!("summarize"),
+                    json!(2.0),
+                    vec![
+                        (json!("short"), json!(2.0)),
+                        (json!("long"), json!(6.0))
+                    ]
+                ),
+                (
+                    json!("assemble"),
+                    json!(4.0),
+                    vec![
+                        (json!("short"), json!(4.0)),
+                        (json!("long"), json!(8.0))
+                    ]
+                ),
+            ]
+        );
+    }
+}
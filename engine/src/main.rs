@@ -112,6 +112,63 @@ enum Command {
         algorithm: honeybee::menu::Algorithm,
     },
 
+    /// Complete a Honeybee program without interaction
+    Complete {
+        /// The library file to use (.hblib.toml)
+        #[arg(short, long, value_name = "FILE")]
+        library: PathBuf,
+
+        /// The Honeybee program to use (.hb.toml)
+        #[arg(short, long, value_name = "FILE")]
+        program: PathBuf,
+
+        /// The codegen style to use
+        #[arg(
+            short,
+            long,
+            value_name = "STYLE",
+            default_value = "PlainTextNotebook"
+        )]
+        style: honeybee::menu::CodegenStyle,
+
+        /// Path to output synthesized expression
+        #[arg(short, long, value_name = "FILE", default_value = "/dev/null")]
+        out: PathBuf,
+
+        /// The algorithm to use
+        #[arg(
+            short,
+            long,
+            value_name = "ALGORITHM",
+            default_value = "PBNHoneybeeMinCost"
+        )]
+        algorithm: honeybee::menu::Algorithm,
+    },
+
+    /// Synthesize up to k complete programs that differ in the functions they
+    /// use
+    Diverse {
+        /// The library file to use (.hblib.toml)
+        #[arg(short, long, value_name = "FILE")]
+        library: PathBuf,
+
+        /// The Honeybee program to use (.hb.toml)
+        #[arg(short, long, value_name = "FILE")]
+        program: PathBuf,
+
+        /// The maximum number of programs to synthesize
+        #[arg(short, value_name = "K", default_value_t = 3)]
+        k: usize,
+
+        /// The codegen style to use
+        #[arg(short, long, value_name = "STYLE", default_value = "Simple")]
+        style: honeybee::menu::CodegenStyle,
+
+        /// The (soft) time cutoff to use for synthesis (in seconds)
+        #[arg(short, long, value_name = "SECONDS", default_value_t = 10)]
+        timeout: u64,
+    },
+
     /// Check if a Honeybee problem is solvable
     Check {
         /// The library file to use (.hblib.toml)
@@ -123,6 +180,21 @@ enum Command {
         program: PathBuf,
     },
 
+    /// Dump the Datalog program that the oracle uses for a Honeybee problem
+    Dump {
+        /// The library file to use (.hblib.toml)
+        #[arg(short, long, value_name = "FILE")]
+        library: PathBuf,
+
+        /// The Honeybee program to use (.hb.toml)
+        #[arg(short, long, value_name = "FILE")]
+        program: PathBuf,
+
+        /// The Datalog style to use
+        #[arg(short, long, value_name = "STYLE", default_value = "Plain")]
+        style: honeybee::menu::DatalogStyle,
+    },
+
     /// Validate that a Honeybee library is parseable and well-typed
     Validate {
         /// The library file (.hblib.toml)
@@ -130,6 +202,13 @@ enum Command {
         library: PathBuf,
     },
 
+    /// Lint a Honeybee library for definitions that can never be used
+    Lint {
+        /// The library file (.hblib.toml)
+        #[arg(short, long, value_name = "FILE")]
+        library: PathBuf,
+    },
+
     /// Run a benchmark suite
     Benchmark {
         /// The benchmark suite directories to use (comma-separated list)
@@ -161,6 +240,25 @@ enum Command {
         parallel: bool,
     },
 
+    /// Check that the Datalog engines agree on a benchmark suite
+    Differential {
+        /// The benchmark suite directories to use (comma-separated list)
+        #[arg(short, long, value_name = "DIRS")]
+        suite: String,
+
+        /// Filter to benchmark entries that contain this substring
+        #[arg(short, long, value_name = "SUBSTRING", default_value = "")]
+        filter: String,
+
+        /// The maximum number of sketches to explore per entry
+        #[arg(short = 'n', long, value_name = "N", default_value_t = 100)]
+        sketches: usize,
+
+        /// Run entries in parallel
+        #[arg(short, long, value_name = "BOOL", default_value_t = false)]
+        parallel: bool,
+    },
+
     /// Translate serialized JSON to Python expression
     Translate {
         /// Path to serialized JSON
@@ -195,10 +293,32 @@ impl Command {
                 custom_parse::at_most_one_path(&json),
                 algorithm,
             ),
+            Self::Complete {
+                library,
+                program,
+                style,
+                out,
+                algorithm,
+            } => {
+                main_handler::complete(library, program, style, out, algorithm)
+            }
+            Self::Diverse {
+                library,
+                program,
+                k,
+                style,
+                timeout,
+            } => main_handler::diverse(library, program, k, style, timeout),
             Self::Check { library, program } => {
                 main_handler::check(library, program)
             }
+            Self::Dump {
+                library,
+                program,
+                style,
+            } => main_handler::dump(library, program, style),
             Self::Validate { library } => main_handler::validate(library),
+            Self::Lint { library } => main_handler::lint(library),
             Self::Benchmark {
                 suite,
                 algorithms,
@@ -216,6 +336,17 @@ impl Command {
                 parallel,
                 custom_parse::limit(&limit),
             ),
+            Self::Differential {
+                suite,
+                filter,
+                sketches,
+                parallel,
+            } => main_handler::differential(
+                custom_parse::one_or_more_paths(&suite, "--suite")?,
+                filter,
+                sketches,
+                parallel,
+            ),
             Self::Translate { path, size } => {
                 main_handler::translate(path, size)
             }
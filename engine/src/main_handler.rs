@@ -10,6 +10,7 @@ use instant::Duration;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use top_down::InhabitationOracle;
 
 fn write_file(path: PathBuf, s: &str) -> Result<(), String> {
     match File::create(path) {
@@ -18,39 +19,56 @@ fn write_file(path: PathBuf, s: &str) -> Result<(), String> {
     }
 }
 
+fn type_error(e: typecheck::Error, source_map: &parse::SourceMap) -> String {
+    if let Some(report) = source_map.report(&e) {
+        return report;
+    }
+
+    format!(
+        "{} {}\n  occurred:{}",
+        Red.bold().paint("type error:"),
+        ansi_term::Style::new().bold().paint(e.message),
+        e.context
+            .into_iter()
+            .map(|ctx| format!("\n    - in {}", ctx))
+            .collect::<Vec<_>>()
+            .join("")
+    )
+}
+
+fn engine_error(e: impl std::fmt::Display) -> String {
+    format!("{} {}", Red.bold().paint("engine error:"), e)
+}
+
+fn load_library(
+    library: PathBuf,
+) -> Result<(core::Library, parse::SourceMap), String> {
+    parse::library_file(&library).map_err(|e| match e {
+        parse::LibraryError::Parse(e) => {
+            format!("{}\n{}", Red.bold().paint("parse error (library):"), e)
+        }
+        parse::LibraryError::Type(e) => {
+            type_error(e, &parse::SourceMap::default())
+        }
+    })
+}
+
 fn load_problem(
     library: PathBuf,
     program: PathBuf,
 ) -> Result<core::Problem, String> {
-    let lib_string = std::fs::read_to_string(library).map_err(|e| {
-        format!("error while reading library file: {}", e.to_string())
-    })?;
-    let prog_string = std::fs::read_to_string(program).map_err(|e| {
-        format!("error while reading program file: {}", e.to_string())
-    })?;
+    let (library, mut source_map) = load_library(library)?;
 
-    let library = parse::library(&lib_string).map_err(|e| {
-        format!("{}\n{}", Red.bold().paint("parse error (library):"), e)
-    })?;
+    let (program, program_source_map) =
+        parse::program_file(&program).map_err(|e| {
+            format!("{}\n{}", Red.bold().paint("parse error (program):"), e)
+        })?;
 
-    let program = parse::program(&prog_string).map_err(|e| {
-        format!("{}\n{}", Red.bold().paint("parse error (program):"), e)
-    })?;
+    source_map.extend(program_source_map);
 
     let problem = core::Problem { library, program };
 
-    typecheck::problem(&problem).map_err(|e| {
-        format!(
-            "{} {}\n  occurred:{}",
-            Red.bold().paint("type error:"),
-            ansi_term::Style::new().bold().paint(e.message),
-            e.context
-                .into_iter()
-                .map(|ctx| format!("\n    - in {}", ctx))
-                .collect::<Vec<_>>()
-                .join("")
-        )
-    })?;
+    typecheck::problem(&problem).map_err(|e| type_error(e, &source_map))?;
 
     Ok(problem)
 }
@@ -93,18 +111,25 @@ pub fn interact(
     let timer = util::Timer::infinite();
 
     if machine_readable {
-        let lib = problem.library.clone();
-        let mut controller = algorithm.controller(timer, problem, false);
-        return machine_readable::interact(&lib, &mut controller);
+        let (mut controller, mut oracle) = algorithm
+            .controller_with_oracle(timer, problem.clone(), false)
+            .map_err(engine_error)?;
+        return machine_readable::interact(
+            &problem,
+            &mut controller,
+            oracle.as_mut(),
+        );
     }
 
-    let mut controller = algorithm.controller(timer, problem, false);
+    let mut controller = algorithm
+        .controller(timer, problem, false)
+        .map_err(engine_error)?;
 
     let mut round = 0;
     while !controller.valid() {
         round += 1;
 
-        let mut options = controller.provide().unwrap();
+        let mut options = controller.provide().map_err(engine_error)?;
 
         if options.is_empty() {
             if !quiet {
@@ -145,7 +170,32 @@ pub fn interact(
                             ))
                         )
                     }
-                    top_down::TopDownStep::Seq(_, _) => {
+                    top_down::TopDownStep::Reuse(h, e) => {
+                        println!(
+                            "{}",
+                            Yellow.paint(format!(
+                                "{} ↦ {} (reused)",
+                                top_down::pretty_hole_string(h),
+                                codegen::Simple::single(&e),
+                            ))
+                        )
+                    }
+                    top_down::TopDownStep::Wrap(h, f, fp, sub, args) => {
+                        let mut args = args;
+                        args.insert(fp, sub);
+                        println!(
+                            "{}",
+                            Yellow.paint(format!(
+                                "{} ↦ {} (attached)",
+                                top_down::pretty_hole_string(h),
+                                codegen::Simple::single(
+                                    &top_down::Sketch::App(f, args),
+                                ),
+                            ))
+                        )
+                    }
+                    top_down::TopDownStep::Retract(_)
+                    | top_down::TopDownStep::Seq(_, _) => {
                         println!("<unexpected>")
                     }
                 }
@@ -213,49 +263,245 @@ pub fn interact(
     Ok(())
 }
 
+/// Complete a Honeybee program without interaction (by default, with a
+/// minimum-cost expression)
+pub fn complete(
+    library: PathBuf,
+    program: PathBuf,
+    style: menu::CodegenStyle,
+    out: PathBuf,
+    algorithm: menu::Algorithm,
+) -> Result<(), String> {
+    let problem = load_problem(library, program)?;
+    let gen = style.codegen(problem.library.clone())?;
+    let start = problem.start();
+
+    let mut synth = algorithm.any_synthesizer(problem).map_err(engine_error)?;
+    let hf = match synth
+        .provide_any(&util::Timer::infinite(), &start)
+        .map_err(engine_error)?
+    {
+        Some(hf) => hf,
+        None => {
+            println!("{}", Red.bold().paint("Not possible!"));
+            return Ok(());
+        }
+    };
+
+    let mut e = start;
+    for (h, rhs) in hf {
+        e = e.substitute(h, &rhs);
+    }
+
+    let output = gen.exp(&e)?;
+
+    println!("{}", output);
+
+    match write_file(out, &output) {
+        Ok(()) => (),
+        Err(e) => eprintln!("file write error: {}", e),
+    };
+
+    Ok(())
+}
+
+/// Synthesize up to k complete programs that differ in the functions they use
+/// (cheapest first)
+pub fn diverse(
+    library: PathBuf,
+    program: PathBuf,
+    k: usize,
+    style: menu::CodegenStyle,
+    timeout: u64,
+) -> Result<(), String> {
+    let problem = load_problem(library, program)?;
+    let gen = style.codegen(problem.library.clone())?;
+    let start = problem.start();
+
+    let mut synth = menu::diverse_synthesizer(problem).map_err(engine_error)?;
+    let hfs = synth
+        .provide_diverse(
+            &util::Timer::finite(Duration::from_secs(timeout)),
+            &start,
+            k,
+        )
+        .map_err(engine_error)?;
+
+    if hfs.is_empty() {
+        println!("{}", Red.bold().paint("Not possible!"));
+        return Ok(());
+    }
+
+    for (i, hf) in hfs.into_iter().enumerate() {
+        let mut e = start.clone();
+        for (h, rhs) in hf {
+            e = e.substitute(h, &rhs);
+        }
+
+        println!(
+            "{}\n\n{}\n",
+            Fixed(8).paint(format!("══ Option {} {}", i + 1, "═".repeat(40))),
+            gen.exp(&e)?
+        );
+    }
+
+    Ok(())
+}
+
 /// Check if a Honeybee problem is solvable
 pub fn check(library: PathBuf, program: PathBuf) -> Result<(), String> {
     let problem = load_problem(library, program)?;
-    let chosen_metadata = problem.program.goal.args.clone();
+    let goals = problem.program.goals.clone();
+    let start = problem.start();
     let engine = egglog::Egglog::new(true);
-    let mut oracle = dl_oracle::Oracle::new(engine, problem).unwrap();
-    let vgm = oracle.valid_goal_metadata();
-    if vgm.contains(&chosen_metadata) {
+    let mut oracle =
+        dl_oracle::Oracle::new(engine, problem).map_err(engine_error)?;
+    let mut solvable = true;
+    for goal in &goals {
+        if !oracle.goal_solvable(goal).map_err(engine_error)? {
+            solvable = false;
+            break;
+        }
+    }
+    if solvable {
         println!("{}", Green.bold().paint("Solvable!"));
+        let mut witnesses = IndexMap::new();
+        for (h, f) in oracle
+            .expansions(&util::Timer::infinite(), &start)
+            .map_err(engine_error)?
+        {
+            if witnesses.contains_key(&h) {
+                continue;
+            }
+            if let Some(w) = oracle.witness(&f).map_err(engine_error)? {
+                witnesses.insert(h, w);
+            }
+        }
+        for w in witnesses.values() {
+            println!(
+                "\n{} {}",
+                Green.bold().paint("witness:"),
+                codegen::Simple::single(w)
+            );
+        }
     } else {
         println!("{}", Red.bold().paint("Not solvable..."));
+        for explanation in oracle.explain(&start).map_err(engine_error)? {
+            println!(
+                "\n{} type '{}' cannot be derived{}",
+                Yellow.bold().paint("explanation:"),
+                explanation.typ.0,
+                derivations_text(&explanation.derivations, 1)
+            );
+        }
     }
     Ok(())
 }
 
+/// Dump the Datalog program that the oracle uses for a Honeybee problem
+pub fn dump(
+    library: PathBuf,
+    program: PathBuf,
+    style: menu::DatalogStyle,
+) -> Result<(), String> {
+    let problem = load_problem(library, program)?;
+    print!("{}", style.export(&problem)?);
+    Ok(())
+}
+
+fn derivations_text(
+    derivations: &[dl_oracle::Derivation],
+    depth: usize,
+) -> String {
+    let indent = "  ".repeat(depth);
+
+    let mut s = String::new();
+    for d in derivations {
+        s += &format!(
+            "\n{}- using function '{}', blocked by:",
+            indent, d.function.0
+        );
+        for b in &d.blockers {
+            s += &format!("\n{}  - ", indent);
+            s += &match b {
+                dl_oracle::Blocker::Context => {
+                    "the rest of the sketch (unsatisfiable)".to_owned()
+                }
+                dl_oracle::Blocker::Param {
+                    param,
+                    typ,
+                    derivations,
+                } => format!(
+                    "parameter '{}' (type '{}'), which cannot be derived{}",
+                    param.0,
+                    typ.0,
+                    derivations_text(derivations, depth + 2)
+                ),
+                dl_oracle::Blocker::Condition { condition, .. } => {
+                    format!("condition `{}`", condition)
+                }
+            };
+        }
+    }
+    s
+}
+
 /// Check if a Honeybee library is parseable and well-typed
 pub fn validate(library: PathBuf) -> Result<(), String> {
-    let lib_string = std::fs::read_to_string(library).map_err(|e| {
-        format!("error while reading library file: {}", e.to_string())
-    })?;
-
-    let library = parse::library(&lib_string).map_err(|e| {
-        format!("{}\n{}", Red.bold().paint("parse error (library):"), e)
-    })?;
-
-    typecheck::library(&library).map_err(|e| {
-        format!(
-            "{} {}\n  occurred:{}",
-            Red.bold().paint("type error:"),
-            ansi_term::Style::new().bold().paint(e.message),
-            e.context
-                .into_iter()
-                .map(|ctx| format!("\n    - in {}", ctx))
-                .collect::<Vec<_>>()
-                .join("")
-        )
-    })?;
+    let (library, source_map) = load_library(library)?;
+
+    typecheck::library(&library).map_err(|e| type_error(e, &source_map))?;
 
     println!("{}", Green.bold().paint("library validated!"));
 
     Ok(())
 }
 
+fn lint_warning(w: &lint::Warning, source_map: &parse::SourceMap) -> String {
+    if let Some(report) = source_map.lint(w) {
+        return report;
+    }
+
+    format!(
+        "{} {}",
+        match w.severity {
+            lint::Severity::Info => Blue.bold().paint("info:"),
+            lint::Severity::Warning => Yellow.bold().paint("warning:"),
+        },
+        ansi_term::Style::new().bold().paint(&w.message),
+    )
+}
+
+/// Lint a Honeybee library for definitions that can never be used
+pub fn lint(library: PathBuf) -> Result<(), String> {
+    let (library, source_map) = load_library(library)?;
+
+    typecheck::library(&library).map_err(|e| type_error(e, &source_map))?;
+
+    let engine = egglog::Egglog::new(true);
+    let warnings = lint::library(engine, &library)?;
+
+    if warnings.is_empty() {
+        println!("{}", Green.bold().paint("no lint warnings!"));
+        return Ok(());
+    }
+
+    for w in &warnings {
+        println!("{}", lint_warning(w, &source_map));
+    }
+
+    println!(
+        "{}",
+        Yellow.bold().paint(format!(
+            "{} lint warning{}",
+            warnings.len(),
+            if warnings.len() == 1 { "" } else { "s" }
+        ))
+    );
+
+    Ok(())
+}
+
 /// Translate a serialized json file to a Python program
 pub fn translate(path: PathBuf, print_size: bool) -> Result<(), String> {
     let exp_string =
@@ -294,3 +540,30 @@ pub fn benchmark(
     runner.suites(&suite_paths);
     Ok(())
 }
+
+/// Check that the Datalog engines agree on the benchmark suites
+pub fn differential(
+    suite_paths: Vec<PathBuf>,
+    entry_filter: String,
+    sketch_limit: usize,
+    parallel: bool,
+) -> Result<(), String> {
+    let config = benchmark::Config {
+        replicates: 1,
+        timeout: Duration::MAX,
+        entry_filter,
+        parallel,
+        algorithms: vec![],
+        particular_solution_limit: 0,
+    };
+    let runner = benchmark::Runner::new(config, std::io::stdout());
+    match runner.differential(&suite_paths, sketch_limit) {
+        0 => Ok(()),
+        n => Err(format!(
+            "{} the Datalog engines disagree on {} entr{}",
+            Red.bold().paint("error:"),
+            n,
+            if n == 1 { "y" } else { "ies" }
+        )),
+    }
+}
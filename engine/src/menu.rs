@@ -12,10 +12,20 @@ use serde::{Deserialize, Serialize};
 ////////////////////////////////////////////////////////////////////////////////
 // Synthesizers
 
+/// A controller for Programming By Navigation
+pub type Controller = pbn::Controller<util::Timer, core::Step>;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Algorithm {
     PBNHoneybee,
     PBNHoneybeeNoMemo,
+    PBNHoneybeeSemiNaive,
+    /// Honeybee with bottom-up steps (attaching function applications above
+    /// inputs and existing sub-expressions) in addition to the top-down ones
+    PBNHoneybeeBottomUp,
+    /// Honeybee whose Any synthesizer returns a minimum-cost completion (its
+    /// navigation is the same as that of `PBNHoneybee`)
+    PBNHoneybeeMinCost,
     PBNConstructiveOracle,
     NaiveEnumeration,
     PrunedEnumeration,
@@ -27,6 +37,9 @@ impl Algorithm {
         vec![
             Self::PBNHoneybee,
             Self::PBNHoneybeeNoMemo,
+            Self::PBNHoneybeeSemiNaive,
+            Self::PBNHoneybeeBottomUp,
+            Self::PBNHoneybeeMinCost,
             Self::PBNConstructiveOracle,
             Self::NaiveEnumeration,
             Self::PrunedEnumeration,
@@ -34,42 +47,128 @@ impl Algorithm {
     }
 
     /// Returns a controller to solve the Programming By Navigation Synthesis
-    /// Problem using the underlying synthesis algorithm
+    /// Problem using the underlying synthesis algorithm (or an error if its
+    /// Datalog engine fails to load the problem)
     pub fn controller(
         &self,
         timer: util::Timer,
         problem: core::Problem,
         save_history: bool,
-    ) -> pbn::Controller<util::Timer, core::Step> {
-        match self {
-            Algorithm::PBNHoneybee => {
+    ) -> Result<Controller, datalog::Error> {
+        Ok(self.navigation(timer, problem, save_history)?.0)
+    }
+
+    /// Like [`Algorithm::controller`], but also returns the Datalog oracle
+    /// for the questions that frontends ask besides navigation (see
+    /// [`Algorithm::oracle`]), which is the one the controller navigates with
+    /// if it has one
+    pub fn controller_with_oracle(
+        &self,
+        timer: util::Timer,
+        problem: core::Problem,
+        save_history: bool,
+    ) -> Result<(Controller, Box<dyn dl_oracle::Queries>), datalog::Error> {
+        let (controller, oracle) =
+            self.navigation(timer, problem.clone(), save_history)?;
+        let oracle = match oracle {
+            Some(oracle) => oracle,
+            None => self.oracle(problem)?,
+        };
+        Ok((controller, oracle))
+    }
+
+    /// Returns a controller along with the Datalog oracle it navigates with
+    /// (if any)
+    fn navigation(
+        &self,
+        timer: util::Timer,
+        problem: core::Problem,
+        save_history: bool,
+    ) -> Result<(Controller, Option<Box<dyn dl_oracle::Queries>>), datalog::Error>
+    {
+        let start = problem.start();
+        Ok(match self {
+            Algorithm::PBNHoneybee | Algorithm::PBNHoneybeeMinCost => {
                 let engine = egglog::Egglog::new(true);
-                let oracle = dl_oracle::Oracle::new(engine, problem).unwrap();
-                let provider =
-                    top_down::ClassicalConstructiveSynthesis::new(oracle);
-                let start = top_down::Sketch::blank();
+                let oracle = dl_oracle::Shared::new(
+                    dl_oracle::Oracle::new(engine, problem)?.incremental(),
+                );
+                let provider = top_down::ClassicalConstructiveSynthesis::new(
+                    oracle.clone(),
+                );
                 let checker = top_down::GroundChecker::new();
-                pbn::Controller::new(
-                    timer,
-                    provider,
-                    checker,
-                    start,
-                    save_history,
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    Some(Box::new(oracle)),
                 )
             }
             Algorithm::PBNHoneybeeNoMemo => {
                 let engine = egglog::Egglog::new(false);
-                let oracle = dl_oracle::Oracle::new(engine, problem).unwrap();
-                let provider =
-                    top_down::ClassicalConstructiveSynthesis::new(oracle);
-                let start = top_down::Sketch::blank();
+                let oracle = dl_oracle::Shared::new(dl_oracle::Oracle::new(
+                    engine, problem,
+                )?);
+                let provider = top_down::ClassicalConstructiveSynthesis::new(
+                    oracle.clone(),
+                );
+                let checker = top_down::GroundChecker::new();
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    Some(Box::new(oracle)),
+                )
+            }
+            Algorithm::PBNHoneybeeSemiNaive => {
+                let engine = seminaive::SemiNaive::new();
+                let oracle = dl_oracle::Shared::new(
+                    dl_oracle::Oracle::new(engine, problem)?.incremental(),
+                );
+                let provider = top_down::ClassicalConstructiveSynthesis::new(
+                    oracle.clone(),
+                );
+                let checker = top_down::GroundChecker::new();
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    Some(Box::new(oracle)),
+                )
+            }
+            Algorithm::PBNHoneybeeBottomUp => {
+                let engine = egglog::Egglog::new(true);
+                let oracle = dl_oracle::Shared::new(
+                    dl_oracle::Oracle::new(engine, problem)?.incremental(),
+                );
+                let provider = pbn::CompoundProvider::new(vec![
+                    Box::new(top_down::ClassicalConstructiveSynthesis::new(
+                        oracle.clone(),
+                    )),
+                    Box::new(top_down::BottomUpSynthesis::new(oracle.clone())),
+                ]);
                 let checker = top_down::GroundChecker::new();
-                pbn::Controller::new(
-                    timer,
-                    provider,
-                    checker,
-                    start,
-                    save_history,
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    Some(Box::new(oracle)),
                 )
             }
             Algorithm::PBNConstructiveOracle => {
@@ -78,14 +177,16 @@ impl Algorithm {
                     enumerate::EnumerativeSynthesis::new(problem, pruner);
                 let provider =
                     top_down::ClassicalConstructiveSynthesis::new(oracle);
-                let start = top_down::Sketch::blank();
                 let checker = top_down::GroundChecker::new();
-                pbn::Controller::new(
-                    timer,
-                    provider,
-                    checker,
-                    start,
-                    save_history,
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    None,
                 )
             }
             Algorithm::NaiveEnumeration => {
@@ -94,14 +195,16 @@ impl Algorithm {
                     enumerate::EnumerativeSynthesis::new(problem, pruner);
                 let provider =
                     traditional_synthesis::AllBasedStepProvider(all_synth);
-                let start = top_down::Sketch::blank();
                 let checker = top_down::GroundChecker::new();
-                pbn::Controller::new(
-                    timer,
-                    provider,
-                    checker,
-                    start,
-                    save_history,
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    None,
                 )
             }
             Algorithm::PrunedEnumeration => {
@@ -110,33 +213,70 @@ impl Algorithm {
                     enumerate::EnumerativeSynthesis::new(problem, pruner);
                 let provider =
                     traditional_synthesis::AllBasedStepProvider(all_synth);
-                let start = top_down::Sketch::blank();
                 let checker = top_down::GroundChecker::new();
-                pbn::Controller::new(
-                    timer,
-                    provider,
-                    checker,
-                    start,
-                    save_history,
+                (
+                    pbn::Controller::new(
+                        timer,
+                        provider,
+                        checker,
+                        start,
+                        save_history,
+                    ),
+                    None,
                 )
             }
-        }
+        })
+    }
+
+    /// Returns the Datalog oracle that the underlying synthesis algorithm uses
+    /// for the questions that frontends ask besides navigation, like
+    /// explanations (algorithms that have no Datalog oracle use the one of
+    /// `PBNHoneybee`)
+    pub fn oracle(
+        &self,
+        problem: core::Problem,
+    ) -> Result<Box<dyn dl_oracle::Queries>, datalog::Error> {
+        Ok(match self {
+            Algorithm::PBNHoneybeeNoMemo => {
+                let engine = egglog::Egglog::new(false);
+                Box::new(dl_oracle::Oracle::new(engine, problem)?)
+            }
+            Algorithm::PBNHoneybeeSemiNaive => {
+                let engine = seminaive::SemiNaive::new();
+                Box::new(dl_oracle::Oracle::new(engine, problem)?)
+            }
+            Algorithm::PBNHoneybee
+            | Algorithm::PBNHoneybeeBottomUp
+            | Algorithm::PBNHoneybeeMinCost
+            | Algorithm::PBNConstructiveOracle
+            | Algorithm::NaiveEnumeration
+            | Algorithm::PrunedEnumeration => {
+                let engine = egglog::Egglog::new(true);
+                Box::new(dl_oracle::Oracle::new(engine, problem)?)
+            }
+        })
     }
 
     /// Returns an AnySynthesizer to solve the traditional Any task using the
-    /// underlying synthesis algorithm
+    /// underlying synthesis algorithm (or an error if its Datalog engine fails
+    /// to load the problem)
     pub fn any_synthesizer(
         &self,
         problem: core::Problem,
-    ) -> Box<
-        dyn traditional_synthesis::AnySynthesizer<
-            F = core::ParameterizedFunction,
+    ) -> Result<
+        Box<
+            dyn traditional_synthesis::AnySynthesizer<
+                F = core::ParameterizedFunction,
+            >,
         >,
+        datalog::Error,
     > {
-        match self {
-            Algorithm::PBNHoneybee => {
+        Ok(match self {
+            // Bottom-up steps are only offered for navigation
+            Algorithm::PBNHoneybee | Algorithm::PBNHoneybeeBottomUp => {
                 let engine = egglog::Egglog::new(true);
-                let oracle = dl_oracle::Oracle::new(engine, problem).unwrap();
+                let oracle =
+                    dl_oracle::Oracle::new(engine, problem)?.incremental();
                 let provider =
                     top_down::ClassicalConstructiveSynthesis::new(oracle);
                 let checker = top_down::GroundChecker::new();
@@ -146,9 +286,32 @@ impl Algorithm {
                     );
                 Box::new(synth)
             }
+            Algorithm::PBNHoneybeeMinCost => {
+                let engine = egglog::Egglog::new(true);
+                let oracle =
+                    dl_oracle::Oracle::new(engine, problem)?.incremental();
+                let checker = top_down::GroundChecker::new();
+                let synth = traditional_synthesis::MinCostAnySynthesizer::new(
+                    oracle, checker,
+                );
+                Box::new(synth)
+            }
             Algorithm::PBNHoneybeeNoMemo => {
                 let engine = egglog::Egglog::new(false);
-                let oracle = dl_oracle::Oracle::new(engine, problem).unwrap();
+                let oracle = dl_oracle::Oracle::new(engine, problem)?;
+                let provider =
+                    top_down::ClassicalConstructiveSynthesis::new(oracle);
+                let checker = top_down::GroundChecker::new();
+                let synth =
+                    traditional_synthesis::StepProviderBasedAnySynthesizer::new(
+                        provider, checker,
+                    );
+                Box::new(synth)
+            }
+            Algorithm::PBNHoneybeeSemiNaive => {
+                let engine = seminaive::SemiNaive::new();
+                let oracle =
+                    dl_oracle::Oracle::new(engine, problem)?.incremental();
                 let provider =
                     top_down::ClassicalConstructiveSynthesis::new(oracle);
                 let checker = top_down::GroundChecker::new();
@@ -183,7 +346,7 @@ impl Algorithm {
                     enumerate::EnumerativeSynthesis::new(problem, pruner);
                 Box::new(synth)
             }
-        }
+        })
     }
 }
 
@@ -195,6 +358,26 @@ impl std::str::FromStr for Algorithm {
     }
 }
 
+/// Returns a DiverseSynthesizer to solve the top-k diverse task using the
+/// Datalog oracle (or an error if its Datalog engine fails to load the problem)
+pub fn diverse_synthesizer(
+    problem: core::Problem,
+) -> Result<
+    Box<
+        dyn traditional_synthesis::DiverseSynthesizer<
+            F = core::ParameterizedFunction,
+        >,
+    >,
+    datalog::Error,
+> {
+    let engine = egglog::Egglog::new(true);
+    let oracle = dl_oracle::Oracle::new(engine, problem)?.incremental();
+    let checker = top_down::GroundChecker::new();
+    Ok(Box::new(traditional_synthesis::MinCostAnySynthesizer::new(
+        oracle, checker,
+    )))
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Code generators
 
@@ -238,3 +421,38 @@ impl std::str::FromStr for CodegenStyle {
         serde_json::from_str(&format!("\"{}\"", s))
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// Datalog exporters
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum DatalogStyle {
+    Plain,
+    Souffle,
+}
+
+impl DatalogStyle {
+    /// The list of all the possible Datalog exporters
+    pub fn all() -> Vec<Self> {
+        vec![Self::Plain, Self::Souffle]
+    }
+
+    /// Exports the Datalog program (and initial queries) that the Datalog
+    /// oracle uses for a (well-typed) problem
+    pub fn export(&self, problem: &core::Problem) -> Result<String, String> {
+        let (_, program) = dl_oracle::compile(problem)?;
+        let queries = dl_oracle::initial_queries(problem);
+        match self {
+            Self::Plain => Ok(datalog::pretty(&program, &queries)),
+            Self::Souffle => Ok(souffle::export(&program, &queries)),
+        }
+    }
+}
+
+impl std::str::FromStr for DatalogStyle {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(&format!("\"{}\"", s))
+    }
+}
@@ -9,9 +9,16 @@
 //! with [`exp`].
 
 use crate::core::*;
+use crate::lint;
 use crate::top_down::FunParam;
+use crate::typecheck::{self, Location};
 
 use chumsky::prelude::*;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
 
 // Shorthand
 
@@ -20,32 +27,73 @@ impl<S, T> P<T> for S where S: Parser<char, T, Error = Simple<char>> {}
 
 // Errors
 
-fn error(title: &str, code: i32, src: &str, err: &Simple<char>) -> String {
+/// Converts a byte range of a string into a character range (which is what
+/// `ariadne` expects).
+fn char_range(src: &str, range: Range<usize>) -> Range<usize> {
+    let start = src[..range.start].chars().count();
+    start..start + src[range].chars().count()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn report(
+    kind: ariadne::ReportKind,
+    title: &str,
+    code: i32,
+    name: &str,
+    src: &str,
+    span: Range<usize>,
+    label: &str,
+    note: Option<String>,
+) -> String {
     use ariadne::*;
 
+    let color = match kind {
+        ReportKind::Warning => Color::Yellow,
+        ReportKind::Advice => Color::Fixed(147),
+        _ => Color::Red,
+    };
+
+    let mut report = Report::build(kind, name.to_owned(), span.start)
+        .with_code(code)
+        .with_message(title)
+        .with_label(
+            Label::new((name.to_owned(), span))
+                .with_message(format!("{}", label.fg(color)))
+                .with_color(color),
+        );
+
+    if let Some(note) = note {
+        report = report.with_note(note);
+    }
+
+    let mut buf: Vec<u8> = vec![];
+    report
+        .finish()
+        .write(sources(vec![(name.to_owned(), src)]), &mut buf)
+        .unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+/// Renders a formula parse error, where `offset` is the (character) offset of
+/// the formula in `src`.
+fn error(
+    title: &str,
+    code: i32,
+    name: &str,
+    src: &str,
+    offset: usize,
+    err: &Simple<char>,
+) -> String {
     let err_span = err.span();
     let err_expected = err
         .expected()
         .filter_map(|mtok| mtok.map(|tok| format!("`{}`", tok)))
         .collect::<Vec<_>>();
 
-    let error_color = Color::Red;
-
-    let mut report =
-        Report::build(ReportKind::Error, "expression", err_span.start)
-            .with_code(code)
-            .with_message(title)
-            .with_label(
-                Label::new(("expression", err_span))
-                    .with_message(format!(
-                        "{}",
-                        "Unexpected token".fg(error_color),
-                    ))
-                    .with_color(error_color),
-            );
+    let mut note = None;
 
     if !err_expected.is_empty() {
-        report = report.with_note(format!(
+        note = Some(format!(
             "{}{}",
             if err_expected.len() == 1 {
                 format!("Expected {}", err_expected[0])
@@ -59,12 +107,32 @@ fn error(title: &str, code: i32, src: &str, err: &Simple<char>) -> String {
         ));
     }
 
-    let mut buf: Vec<u8> = vec![];
-    report
-        .finish()
-        .write(sources(vec![("expression", src)]), &mut buf)
-        .unwrap();
-    String::from_utf8(buf).unwrap()
+    report(
+        ariadne::ReportKind::Error,
+        title,
+        code,
+        name,
+        src,
+        offset + err_span.start..offset + err_span.end,
+        "Unexpected token",
+        note,
+    )
+}
+
+fn toml_error(name: &str, src: &str, err: &toml::de::Error) -> String {
+    match err.span() {
+        Some(span) => report(
+            ariadne::ReportKind::Error,
+            "TOML parse error",
+            1,
+            name,
+            src,
+            char_range(src, span),
+            err.message(),
+            None,
+        ),
+        None => err.to_string(),
+    }
 }
 
 // Helpers
@@ -74,7 +142,6 @@ fn ident_rest() -> impl P<String> {
         char::is_ascii_lowercase(c)
             || char::is_ascii_uppercase(c)
             || char::is_ascii_digit(c)
-            || *c == '-'
             || *c == '_'
     })
     .repeated()
@@ -93,14 +160,120 @@ fn upper_ident() -> impl P<String> {
         .map(|(first, rest)| format!("{}{}", first, rest))
 }
 
+fn qualified_upper_ident() -> impl P<String> {
+    lower_ident()
+        .then_ignore(just('.'))
+        .repeated()
+        .then(upper_ident())
+        .map(|(namespaces, name)| {
+            namespaces
+                .into_iter()
+                .chain([name])
+                .collect::<Vec<_>>()
+                .join(".")
+        })
+}
+
+/// Escape sequences in string literals (as written by [`crate::util::quote`])
+fn escaped_char() -> impl P<char> {
+    just('\\').ignore_then(choice((
+        just('\\'),
+        just('"'),
+        just('n').to('\n'),
+        just('r').to('\r'),
+        just('t').to('\t'),
+        just('u')
+            .ignore_then(
+                filter(char::is_ascii_hexdigit)
+                    .repeated()
+                    .exactly(4)
+                    .collect::<String>(),
+            )
+            .try_map(|hex, span| {
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| Simple::custom(span, "invalid character"))
+            }),
+    )))
+}
+
+fn signed_int() -> impl P<String> {
+    just('-').or_not().then(text::int(10)).map(
+        |(sign, digits): (Option<char>, String)| match sign {
+            Some(_) => format!("-{}", digits),
+            None => digits,
+        },
+    )
+}
+
+/// Float literals, which must have a fractional part or an exponent (or both)
+/// to be distinguished from integer literals; for example, `0.05`, `.05`,
+/// `5e-2`, and `-1.5E8`.
+fn float() -> impl P<f64> {
+    let exponent = one_of("eE")
+        .ignore_then(one_of("+-").or_not())
+        .then(text::digits(10))
+        .map(|(sign, digits): (Option<char>, String)| match sign {
+            Some(sign) => format!("e{}{}", sign, digits),
+            None => format!("e{}", digits),
+        });
+
+    let fraction = just('.').ignore_then(text::digits(10));
+
+    let mantissa = choice((
+        text::digits(10).then(fraction.or_not()),
+        fraction.map(|frac| ("0".to_owned(), Some(frac))),
+    ));
+
+    just('-')
+        .or_not()
+        .then(mantissa)
+        .then(exponent.or_not())
+        .try_map(|((sign, (whole, frac)), exp), span| {
+            if frac.is_none() && exp.is_none() {
+                return Err(Simple::custom(span, "not a float"));
+            }
+            format!(
+                "{}{}.{}{}",
+                sign.map_or("", |_| "-"),
+                whole,
+                frac.unwrap_or_else(|| "0".to_owned()),
+                exp.unwrap_or_default()
+            )
+            .parse()
+            .map_err(|_| Simple::custom(span, "invalid float"))
+        })
+}
+
+/// The special float values (written as in TOML)
+fn special_float() -> impl P<f64> {
+    choice((
+        just('-')
+            .ignore_then(text::keyword("inf"))
+            .to(f64::NEG_INFINITY),
+        just('+')
+            .or_not()
+            .ignore_then(text::keyword("inf"))
+            .to(f64::INFINITY),
+        text::keyword("nan").to(f64::NAN),
+    ))
+}
+
 // Main
 
 fn value() -> impl P<Value> {
     choice((
         just("true").to(Value::Bool(true)),
         just("false").to(Value::Bool(false)),
-        text::int(10).map(|s: String| Value::Int(s.parse().unwrap())),
-        none_of("\"")
+        float().map(Value::Float),
+        signed_int().try_map(|s, span| {
+            s.parse()
+                .map(Value::Int)
+                .map_err(|_| Simple::custom(span, "integer out of range"))
+        }),
+        escaped_char()
+            .or(none_of("\\\""))
             .repeated()
             .collect()
             .delimited_by(just('"'), just('"'))
@@ -109,7 +282,7 @@ fn value() -> impl P<Value> {
 }
 
 fn met_option<T>(rhs: impl P<T>) -> impl P<Met<Option<T>>> {
-    upper_ident()
+    qualified_upper_ident()
         .then(
             (lower_ident()
                 .then(just('=').padded())
@@ -117,6 +290,7 @@ fn met_option<T>(rhs: impl P<T>) -> impl P<Met<Option<T>>> {
                 .padded()
                 .map(|((lhs, _), rhs)| (MetParam(lhs), rhs)))
             .separated_by(just(','))
+            .padded()
             .delimited_by(just('{'), just('}'))
             .padded(),
         )
@@ -127,7 +301,7 @@ fn met_option<T>(rhs: impl P<T>) -> impl P<Met<Option<T>>> {
         })
 }
 
-fn formula_atom() -> impl P<FormulaAtom> {
+fn simple_formula_atom() -> impl P<FormulaAtom> {
     choice((
         value().map(FormulaAtom::Lit),
         just("ret.")
@@ -141,35 +315,105 @@ fn formula_atom() -> impl P<FormulaAtom> {
             .map(|((fp, _), mp)| {
                 FormulaAtom::Param(FunParam(fp), MetParam(mp))
             }),
+        // Tried last, since `inf` and `nan` may also be function parameters
+        special_float()
+            .padded()
+            .map(|x| FormulaAtom::Lit(Value::Float(x))),
     ))
 }
 
-fn formula() -> impl P<Formula> {
+fn formula_atom() -> impl P<FormulaAtom> {
+    #[derive(Clone)]
+    enum Op {
+        Add,
+        Sub,
+    }
+
+    recursive(|formula_atom| {
+        let operand = simple_formula_atom()
+            .or(formula_atom
+                .delimited_by(just('(').padded(), just(')').padded()))
+            .boxed();
+
+        operand
+            .clone()
+            .then(
+                choice((just('+').to(Op::Add), just('-').to(Op::Sub)))
+                    .padded()
+                    .then(operand)
+                    .repeated(),
+            )
+            .foldl(|left, (op, right)| match op {
+                Op::Add => FormulaAtom::Add(Box::new(left), Box::new(right)),
+                Op::Sub => FormulaAtom::Sub(Box::new(left), Box::new(right)),
+            })
+    })
+}
+
+fn comparison() -> impl P<Formula> {
     #[derive(Clone)]
     enum Op {
         Eq,
         Lt,
+        Le,
+        Gt,
+        Ge,
         Neq,
     }
 
-    choice((
-        formula_atom()
+    formula_atom()
+        .then(
+            choice((
+                just('=').to(Op::Eq),
+                just("<=").to(Op::Le),
+                just('<').to(Op::Lt),
+                just(">=").to(Op::Ge),
+                just('>').to(Op::Gt),
+                just("!=").to(Op::Neq),
+            ))
+            .padded(),
+        )
+        .then(formula_atom())
+        .map(|((left, op), right)| match op {
+            Op::Eq => Formula::Eq(left, right),
+            Op::Lt => Formula::Lt(left, right),
+            Op::Le => Formula::Le(left, right),
+            Op::Gt => Formula::Gt(left, right),
+            Op::Ge => Formula::Ge(left, right),
+            Op::Neq => Formula::Neq(left, right),
+        })
+}
+
+fn formula() -> impl P<Formula> {
+    recursive(|formula| {
+        let unary = recursive(|unary| {
+            choice((
+                text::keyword("not")
+                    .padded()
+                    .ignore_then(unary)
+                    .map(|phi| Formula::Not(Box::new(phi))),
+                formula.delimited_by(just('(').padded(), just(')').padded()),
+                comparison(),
+                met_option(formula_atom()).map(Formula::Ap),
+                text::keyword("true").padded().to(Formula::True),
+            ))
+        });
+
+        let conjunction = unary
+            .clone()
+            .then(text::keyword("and").padded().ignore_then(unary).repeated())
+            .foldl(|phi1, phi2| Formula::And(Box::new(phi1), Box::new(phi2)));
+
+        conjunction
+            .clone()
             .then(
-                choice((
-                    just('=').to(Op::Eq),
-                    just('<').to(Op::Lt),
-                    just("!=").to(Op::Neq),
-                ))
-                .padded(),
+                text::keyword("or")
+                    .padded()
+                    .ignore_then(conjunction)
+                    .repeated(),
             )
-            .then(formula_atom())
-            .map(|((left, op), right)| match op {
-                Op::Eq => Formula::Eq(left, right),
-                Op::Lt => Formula::Lt(left, right),
-                Op::Neq => Formula::Neq(left, right),
-            }),
-        met_option(formula_atom()).map(Formula::Ap),
-    ))
+            .foldl(|phi1, phi2| Formula::Or(Box::new(phi1), Box::new(phi2)))
+    })
 }
 
 // Special case: convert Formula to/from Vec<String>
@@ -180,13 +424,23 @@ impl TryFrom<Vec<String>> for Formula {
     fn try_from(strings: Vec<String>) -> Result<Self, Self::Error> {
         let mut overall_phi = Self::True;
         for s in strings {
-            match formula().parse(s.clone()) {
+            // The whole string must be a formula: otherwise, a condition such
+            // as `ret.x = 1 or ret.x = 2` with a misspelled connective would
+            // silently be parsed as its first comparison
+            match formula().then_ignore(end()).parse(s.clone()) {
                 Ok(phi) => {
                     overall_phi =
                         Self::And(Box::new(overall_phi), Box::new(phi))
                 }
                 Err(errs) => {
-                    return Err(error("Formula parse error", 0, &s, &errs[0]))
+                    return Err(error(
+                        "Formula parse error",
+                        0,
+                        "expression",
+                        &s,
+                        0,
+                        &errs[0],
+                    ))
                 }
             }
         }
@@ -194,19 +448,591 @@ impl TryFrom<Vec<String>> for Formula {
     }
 }
 
+// Source maps
+
+#[derive(Deserialize)]
+struct FunctionSpans {
+    #[serde(default)]
+    condition: Vec<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+struct LibrarySpans {
+    #[serde(rename = "Prop", default)]
+    props: IndexMap<MetName, Spanned<toml::Table>>,
+    #[serde(rename = "Type", default)]
+    types: IndexMap<MetName, Spanned<toml::Table>>,
+    #[serde(rename = "Function", default)]
+    functions: IndexMap<BaseFunction, Spanned<FunctionSpans>>,
+}
+
+/// The spans of a program, where `G` is either a single goal table or an array
+/// of them (spans cannot be recovered through an untagged enum, so each shape
+/// is tried in turn).
+#[derive(Deserialize)]
+struct ProgramSpans<G> {
+    #[serde(rename = "Prop", default)]
+    props: Vec<Spanned<toml::Table>>,
+    #[serde(rename = "Goal")]
+    goals: Option<G>,
+}
+
+/// The span of the first line of a table (its header, if it has one).
+fn header(src: &str, span: Range<usize>) -> Range<usize> {
+    match src[span.clone()].find('\n') {
+        Some(i) => span.start..span.start + i,
+        None => span,
+    }
+}
+
+/// The locations of the components of libraries and programs in their source
+/// files, used to render type errors against the original files.
+#[derive(Default)]
+pub struct SourceMap {
+    sources: Vec<(String, String)>,
+    locations: IndexMap<Location, (usize, Range<usize>)>,
+}
+
+impl SourceMap {
+    fn add_source(&mut self, name: &str, src: &str) -> usize {
+        self.sources.push((name.to_owned(), src.to_owned()));
+        self.sources.len() - 1
+    }
+
+    fn insert(
+        &mut self,
+        source: usize,
+        location: Location,
+        span: Range<usize>,
+    ) {
+        self.locations.entry(location).or_insert((source, span));
+    }
+
+    fn library(name: &str, src: &str) -> Self {
+        let mut map = Self::default();
+
+        let spans: LibrarySpans = match toml::from_str(src) {
+            Ok(spans) => spans,
+            Err(_) => return map,
+        };
+
+        let source = map.add_source(name, src);
+
+        for (mn, table) in spans.props {
+            map.insert(source, Location::Prop(mn), header(src, table.span()));
+        }
+
+        for (mn, table) in spans.types {
+            map.insert(source, Location::Type(mn), header(src, table.span()));
+        }
+
+        for (f, fs) in spans.functions {
+            map.insert(
+                source,
+                Location::Function(f.clone()),
+                header(src, fs.span()),
+            );
+            for (i, c) in fs.into_inner().condition.into_iter().enumerate() {
+                map.insert(source, Location::Condition(f.clone(), i), c.span());
+            }
+        }
+
+        map
+    }
+
+    fn program(name: &str, src: &str) -> Self {
+        let mut map = Self::default();
+
+        let (props, goals) = match toml::from_str::<
+            ProgramSpans<Vec<Spanned<toml::Table>>>,
+        >(src)
+        {
+            Ok(spans) => (spans.props, spans.goals.unwrap_or_default()),
+            Err(_) => {
+                match toml::from_str::<ProgramSpans<Spanned<toml::Table>>>(src)
+                {
+                    Ok(spans) => {
+                        (spans.props, spans.goals.into_iter().collect())
+                    }
+                    Err(_) => return map,
+                }
+            }
+        };
+
+        let source = map.add_source(name, src);
+
+        for (i, table) in props.into_iter().enumerate() {
+            map.insert(
+                source,
+                Location::ProgramProp(i),
+                header(src, table.span()),
+            );
+        }
+
+        for (i, table) in goals.into_iter().enumerate() {
+            map.insert(source, Location::Goal(i), header(src, table.span()));
+        }
+
+        map
+    }
+
+    fn prefix_names(self, prefix: &str) -> Self {
+        let prefix_mn = |mn: MetName| MetName(format!("{}{}", prefix, mn.0));
+        let prefix_bf =
+            |f: BaseFunction| BaseFunction(format!("{}{}", prefix, f.0));
+        Self {
+            sources: self.sources,
+            locations: self
+                .locations
+                .into_iter()
+                .map(|(location, span)| {
+                    let location = match location {
+                        Location::Prop(mn) => Location::Prop(prefix_mn(mn)),
+                        Location::Type(mn) => Location::Type(prefix_mn(mn)),
+                        Location::Function(f) => {
+                            Location::Function(prefix_bf(f))
+                        }
+                        Location::Condition(f, i) => {
+                            Location::Condition(prefix_bf(f), i)
+                        }
+                        Location::ProgramProp(_) | Location::Goal(_) => {
+                            location
+                        }
+                    };
+                    (location, span)
+                })
+                .collect(),
+        }
+    }
+
+    /// Combine two source maps (the locations of the first map take
+    /// precedence).
+    pub fn extend(&mut self, other: SourceMap) {
+        let offset = self.sources.len();
+        self.sources.extend(other.sources);
+        for (location, (source, span)) in other.locations {
+            self.insert(source + offset, location, span);
+        }
+    }
+
+    /// Render a type error against its source file (if its location is known)
+    pub fn report(&self, e: &typecheck::Error) -> Option<String> {
+        let (source, span) = self.locations.get(e.location.as_ref()?)?;
+        let (name, src) = &self.sources[*source];
+        Some(report(
+            ariadne::ReportKind::Error,
+            "Type error",
+            2,
+            name,
+            src,
+            char_range(src, span.clone()),
+            &e.message,
+            Some(format!("Occurred in {}", e.context.join(", in "))),
+        ))
+    }
+
+    /// Render a lint warning against its source file (if its location is
+    /// known)
+    pub fn lint(&self, w: &lint::Warning) -> Option<String> {
+        let (source, span) = self.locations.get(&w.location)?;
+        let (name, src) = &self.sources[*source];
+        Some(report(
+            match w.severity {
+                lint::Severity::Info => ariadne::ReportKind::Advice,
+                lint::Severity::Warning => ariadne::ReportKind::Warning,
+            },
+            w.lint.title(),
+            3,
+            name,
+            src,
+            char_range(src, span.clone()),
+            &w.message,
+            None,
+        ))
+    }
+}
+
+/// Renders the first formula parse error in the conditions of a library
+/// against the library source (if there is one).
+fn formula_error(name: &str, src: &str) -> Option<String> {
+    let spans: LibrarySpans = toml::from_str(src).ok()?;
+    for fs in spans.functions.values() {
+        for c in &fs.get_ref().condition {
+            let errs = match formula()
+                .then_ignore(end())
+                .parse(c.get_ref().as_str())
+            {
+                Ok(_) => continue,
+                Err(errs) => errs,
+            };
+
+            // The error can only be pinpointed within the condition string if
+            // the string is written without escapes
+            let span = c.span();
+            let raw = &src[span.clone()];
+            if raw.len() == c.get_ref().len() + 2
+                && raw[1..raw.len() - 1] == *c.get_ref()
+            {
+                return Some(error(
+                    "Formula parse error",
+                    0,
+                    name,
+                    src,
+                    src[..span.start].chars().count() + 1,
+                    &errs[0],
+                ));
+            }
+
+            return Some(report(
+                ariadne::ReportKind::Error,
+                "Formula parse error",
+                0,
+                name,
+                src,
+                char_range(src, span),
+                "Invalid formula",
+                None,
+            ));
+        }
+    }
+    None
+}
+
+fn library_helper(
+    name: &str,
+    src: &str,
+) -> Result<(Library, SourceMap), String> {
+    let library = toml::from_str(src).map_err(|e| {
+        formula_error(name, src).unwrap_or_else(|| toml_error(name, src, &e))
+    })?;
+    Ok((library, SourceMap::library(name, src)))
+}
+
 // Top-level functions
 
-/// Parse a library
+/// The type of errors that can occur while loading a library file.
+#[derive(Debug)]
+pub enum LibraryError {
+    Parse(String),
+    Type(typecheck::Error),
+}
+
+/// Where the sources of libraries are read from.
+enum Sources<'a> {
+    /// Library files on the file system
+    Files,
+    /// Library sources in memory, keyed by their (normalized) path
+    Memory(&'a IndexMap<String, String>),
+}
+
+impl Sources<'_> {
+    /// Read the source of a library, along with its canonical path (used to
+    /// detect import cycles).
+    fn read(&self, path: &Path) -> Result<(PathBuf, String), LibraryError> {
+        let error = |e: String| {
+            LibraryError::Parse(format!(
+                "error while reading library file '{}': {}",
+                path.display(),
+                e
+            ))
+        };
+
+        match self {
+            Sources::Files => {
+                let canonical_path =
+                    path.canonicalize().map_err(|e| error(e.to_string()))?;
+                let src = std::fs::read_to_string(&canonical_path)
+                    .map_err(|e| error(e.to_string()))?;
+                Ok((canonical_path, src))
+            }
+            Sources::Memory(sources) => {
+                let mut canonical_path = PathBuf::new();
+                for component in path.components() {
+                    match component {
+                        std::path::Component::CurDir => (),
+                        std::path::Component::ParentDir => {
+                            canonical_path.pop();
+                        }
+                        _ => canonical_path.push(component),
+                    }
+                }
+                let src = canonical_path
+                    .to_str()
+                    .and_then(|key| sources.get(key))
+                    .ok_or_else(|| error("no such library source".to_owned()))?
+                    .clone();
+                Ok((canonical_path, src))
+            }
+        }
+    }
+}
+
+/// Parse a library (that has no imports)
+#[allow(dead_code)]
 pub fn library(lib: &str) -> Result<Library, String> {
-    toml::from_str(lib).map_err(|e| e.to_string())
+    match library_with_imports(lib, &IndexMap::new()) {
+        Ok((library, _)) => Ok(library),
+        Err(LibraryError::Parse(e)) => Err(e),
+        Err(LibraryError::Type(e)) => Err(format!("type error: {}", e.message)),
+    }
+}
+
+/// Parse a library file, resolving its imports.
+///
+/// Imported libraries are merged into the importing library (see
+/// [`typecheck::merge`]) after prefixing their names by their namespace.
+pub fn library_file(path: &Path) -> Result<(Library, SourceMap), LibraryError> {
+    let (canonical_path, src) = Sources::Files.read(path)?;
+    library_source_helper(
+        path,
+        canonical_path,
+        &src,
+        &Sources::Files,
+        &mut vec![],
+    )
+}
+
+/// Parse a library, resolving its imports against the provided sources
+/// (keyed by their path relative to the library) rather than the file system.
+///
+/// This is how imports are resolved in environments without a file system.
+pub fn library_with_imports(
+    lib: &str,
+    imports: &IndexMap<String, String>,
+) -> Result<(Library, SourceMap), LibraryError> {
+    let path = Path::new("library");
+    library_source_helper(
+        path,
+        path.to_owned(),
+        lib,
+        &Sources::Memory(imports),
+        &mut vec![],
+    )
+}
+
+fn library_source_helper(
+    path: &Path,
+    canonical_path: PathBuf,
+    lib_string: &str,
+    sources: &Sources,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(Library, SourceMap), LibraryError> {
+    if let Some(i) = stack.iter().position(|p| *p == canonical_path) {
+        return Err(LibraryError::Parse(format!(
+            "import cycle: {}",
+            stack[i..]
+                .iter()
+                .chain([&canonical_path])
+                .map(|p| format!("'{}'", p.display()))
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        )));
+    }
+
+    let (mut library, mut source_map) =
+        library_helper(&path.display().to_string(), lib_string)
+            .map_err(LibraryError::Parse)?;
+
+    let dir = canonical_path.parent().unwrap_or(Path::new("")).to_owned();
+    stack.push(canonical_path);
+
+    for import in std::mem::take(&mut library.imports) {
+        let in_import = |e: typecheck::Error| {
+            LibraryError::Type(
+                e.with_context(format!("import '{}'", import.path))
+                    .with_context(format!("library file '{}'", path.display())),
+            )
+        };
+
+        let import_path = dir.join(&import.path);
+        let (import_canonical_path, import_string) =
+            sources.read(&import_path)?;
+
+        let (mut imported, mut imported_source_map) =
+            match library_source_helper(
+                &import_path,
+                import_canonical_path,
+                &import_string,
+                sources,
+                stack,
+            ) {
+                Ok(x) => x,
+                Err(LibraryError::Type(e)) => return Err(in_import(e)),
+                Err(e) => return Err(e),
+            };
+
+        if let Some(namespace) = &import.namespace {
+            if lower_ident()
+                .then_ignore(end())
+                .parse(namespace.as_str())
+                .is_err()
+            {
+                return Err(LibraryError::Parse(format!(
+                    "in '{}':\ninvalid namespace '{}' (must be a lowercase identifier)",
+                    path.display(),
+                    namespace
+                )));
+            }
+            let prefix = format!("{}.", namespace);
+            imported = imported.prefix_names(&prefix);
+            imported_source_map = imported_source_map.prefix_names(&prefix);
+        }
+
+        typecheck::merge(&mut library, imported).map_err(in_import)?;
+        source_map.extend(imported_source_map);
+    }
+
+    stack.pop();
+
+    Ok((library, source_map))
 }
 
 /// Parse a program
 pub fn program(prog: &str) -> Result<Program, String> {
-    toml::from_str(prog).map_err(|e| e.to_string())
+    toml::from_str(prog).map_err(|e| toml_error("program", prog, &e))
+}
+
+/// Parse a program file
+pub fn program_file(path: &Path) -> Result<(Program, SourceMap), String> {
+    let name = path.display().to_string();
+
+    let prog_string = std::fs::read_to_string(path).map_err(|e| {
+        format!("error while reading program file '{}': {}", name, e)
+    })?;
+
+    let program = toml::from_str(&prog_string)
+        .map_err(|e| toml_error(&name, &prog_string, &e))?;
+
+    Ok((program, SourceMap::program(&name, &prog_string)))
 }
 
 /// Parse an expression
 pub fn exp(exp: &str) -> Result<Exp, String> {
     serde_json::from_str(exp).map_err(|e| e.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(s: &str) -> Value {
+        value().then_ignore(end()).parse(s).unwrap()
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(lit("0.05"), Value::Float(0.05));
+        assert_eq!(lit(".05"), Value::Float(0.05));
+        assert_eq!(lit("-.05"), Value::Float(-0.05));
+        assert_eq!(lit("5e-2"), Value::Float(0.05));
+        assert_eq!(lit("1e-8"), Value::Float(1e-8));
+        assert_eq!(lit("1.5E+8"), Value::Float(1.5e8));
+        assert_eq!(lit("-2.5e3"), Value::Float(-2500.0));
+        assert_eq!(lit("1"), Value::Int(1));
+        assert_eq!(lit("-7"), Value::Int(-7));
+    }
+
+    #[test]
+    fn negative_zero() {
+        assert_eq!(lit("-0.0"), lit("0.0"));
+    }
+
+    #[test]
+    fn float_formula() {
+        let phi = Formula::try_from(vec!["ret.p < 1e-8".to_owned()]).unwrap();
+        assert_eq!(
+            phi.conditions(),
+            vec![&Formula::Lt(
+                FormulaAtom::Ret(MetParam("p".to_owned())),
+                FormulaAtom::Lit(Value::Float(1e-8))
+            )]
+        );
+    }
+
+    #[test]
+    fn formulas_must_be_parsed_entirely() {
+        let parse = |s: &str| Formula::try_from(vec![s.to_owned()]);
+        assert!(parse("ret.n = 0 ret.n = 1").is_err());
+        assert!(parse("ret.n = 0 xor ret.n = 1").is_err());
+        assert!(parse("ret.n = 0)").is_err());
+        assert!(matches!(
+            parse("ret.n = 0 or ret.n = 1").unwrap().conditions()[..],
+            [Formula::Or(_, _)]
+        ));
+    }
+
+    #[test]
+    fn subtraction_without_spaces() {
+        let n = || MetParam("n".to_owned());
+        let x_n = || FormulaAtom::Param(FunParam("x".to_owned()), n());
+        for src in ["ret.n = x.n-1", "ret.n = x.n - 1"] {
+            let phi = Formula::try_from(vec![src.to_owned()]).unwrap();
+            assert_eq!(
+                phi.conditions(),
+                vec![&Formula::Eq(
+                    FormulaAtom::Ret(n()),
+                    FormulaAtom::Sub(
+                        Box::new(x_n()),
+                        Box::new(FormulaAtom::Lit(Value::Int(1)))
+                    )
+                )]
+            );
+        }
+    }
+
+    const LOCATED: &str = r#"[Type.Num]
+params.n = "Int"
+
+[Function.zero]
+params = {}
+ret = "Num"
+condition = ["ret.n = 0", "ret.m = 0"]
+"#;
+
+    /// The text that a location spans in a source map
+    fn located(map: &SourceMap, location: &Location) -> String {
+        let (source, span) = &map.locations[location];
+        map.sources[*source].1[span.clone()].to_owned()
+    }
+
+    #[test]
+    fn type_errors_are_located_in_their_condition() {
+        let (lib, map) =
+            library_with_imports(LOCATED, &IndexMap::new()).unwrap();
+        let e = typecheck::library(&lib).unwrap_err();
+        let location = Location::Condition(BaseFunction("zero".to_owned()), 1);
+        assert_eq!(e.location, Some(location.clone()));
+        assert_eq!(located(&map, &location), r#""ret.m = 0""#);
+        assert_eq!(
+            located(&map, &Location::Function(BaseFunction("zero".to_owned()))),
+            "[Function.zero]"
+        );
+        let report = map.report(&e).unwrap();
+        assert!(report.contains("library:7:27"), "{}", report);
+        assert!(report.contains("unknown metadata parameter 'm'"));
+    }
+
+    #[test]
+    fn formula_parse_errors_are_located_in_their_condition() {
+        let src = LOCATED.replace("ret.m = 0", "ret.n = = 0");
+        match library_with_imports(&src, &IndexMap::new()) {
+            Err(LibraryError::Parse(msg)) => {
+                // The second `=` of the second condition
+                assert!(msg.contains("library:7:36"), "{}", msg);
+                assert!(msg.contains("Formula parse error"));
+            }
+            Ok(_) => panic!("expected a parse error"),
+            Err(LibraryError::Type(e)) => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn program_components_are_located_at_their_header() {
+        let src = "[[Prop]]\nname = \"P\"\n\n[[Prop]]\nname = \"Q\"\n\n[Goal]\nname = \"A\"\n";
+        let map = SourceMap::program("program", src);
+        assert_eq!(located(&map, &Location::ProgramProp(0)), "[[Prop]]");
+        let (_, span) = &map.locations[&Location::ProgramProp(1)];
+        assert_eq!(span.start, src.rfind("[[Prop]]").unwrap());
+        assert_eq!(located(&map, &Location::Goal(0)), "[Goal]");
+    }
+}
@@ -0,0 +1,1217 @@
+//! # Semi-naive Datalog engine
+//!
+//! This module defines a pure-Rust Datalog engine, [`SemiNaive`], that
+//! evaluates [`Program`]s directly with semi-naive evaluation (rather than
+//! compiling them to another language, as the egglog backend does).
+//!
+//! Each rule is evaluated by following a *plan*: a sequence of steps that scan
+//! facts and bind, compute, or check variables, ordered so that primitives are
+//! evaluated as soon as their arguments are known. As with the egglog backend,
+//! every variable (and value) of a rule must lie in the domain of the program.
+//!
+//! Scans look up facts by the values of their arguments that are already
+//! known through hash indexes, which are built the first time a relation is
+//! scanned with a particular set of known arguments and maintained as new
+//! facts are derived.
+//!
+//! The engine records the first derivation of every fact it derives, so
+//! derivation trees come for free and are always well-founded.
+
+use crate::datalog::*;
+
+use indexmap::{IndexMap, IndexSet};
+
+type Tuple = Vec<Value>;
+
+/// The positions of the facts of a relation, indexed by their values at some
+/// of its arguments
+type Index = IndexMap<Tuple, Vec<usize>>;
+
+/// The facts of each relation, along with their indexes (keyed by the
+/// argument positions they index)
+#[derive(Default)]
+struct Database {
+    facts: IndexMap<Relation, IndexSet<Tuple>>,
+    indexes: IndexMap<Relation, IndexMap<Vec<usize>, Index>>,
+}
+
+fn project(tuple: &Tuple, columns: &[usize]) -> Tuple {
+    columns.iter().map(|i| tuple[*i].clone()).collect()
+}
+
+impl Database {
+    fn is_empty(&self) -> bool {
+        self.facts.values().all(|tuples| tuples.is_empty())
+    }
+
+    fn contains(&self, relation: &Relation, tuple: &Tuple) -> bool {
+        self.facts
+            .get(relation)
+            .is_some_and(|tuples| tuples.contains(tuple))
+    }
+
+    /// Adds a fact, returning whether or not it is new
+    fn insert(&mut self, relation: &Relation, tuple: Tuple) -> bool {
+        let tuples = self.facts.entry(relation.clone()).or_default();
+        let (pos, new) = tuples.insert_full(tuple);
+        if new {
+            for (columns, index) in
+                self.indexes.get_mut(relation).into_iter().flatten()
+            {
+                index
+                    .entry(project(&tuples[pos], columns))
+                    .or_default()
+                    .push(pos);
+            }
+        }
+        new
+    }
+
+    /// Builds the indexes needed to scan relations by the given columns (if
+    /// they have not been built already)
+    fn index(&mut self, scans: &[(Relation, Vec<usize>)]) {
+        for (relation, columns) in scans {
+            if columns.is_empty() {
+                continue;
+            }
+            let tuples = self.facts.get(relation).into_iter().flatten();
+            self.indexes
+                .entry(relation.clone())
+                .or_default()
+                .entry(columns.clone())
+                .or_insert_with(|| {
+                    let mut index = Index::new();
+                    for (pos, tuple) in tuples.enumerate() {
+                        index
+                            .entry(project(tuple, columns))
+                            .or_default()
+                            .push(pos);
+                    }
+                    index
+                });
+        }
+    }
+
+    /// Returns the facts of a relation whose values at `columns` are `key`
+    /// (which must have been indexed if `columns` is nonempty)
+    fn scan<'a>(
+        &'a self,
+        relation: &Relation,
+        columns: &[usize],
+        key: &Tuple,
+    ) -> Box<dyn Iterator<Item = &'a Tuple> + 'a> {
+        let tuples = match self.facts.get(relation) {
+            Some(tuples) => tuples,
+            None => return Box::new(std::iter::empty()),
+        };
+        if columns.is_empty() {
+            return Box::new(tuples.iter());
+        }
+        match self.indexes[relation][columns].get(key) {
+            Some(positions) => {
+                Box::new(positions.iter().map(|pos| &tuples[*pos]))
+            }
+            None => Box::new(std::iter::empty()),
+        }
+    }
+}
+
+/// The first derivation of a fact: the rule that derived it and the facts in
+/// the body of that rule (in order).
+type Justification = (String, Vec<(Relation, Tuple)>);
+
+////////////////////////////////////////////////////////////////////////////////
+// Plans
+
+#[derive(Debug, Clone)]
+enum Term {
+    Const(Value),
+    Var(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Term(Term),
+    Add(Term, Term),
+    Sub(Term, Term),
+}
+
+#[derive(Debug, Clone)]
+enum Comparison {
+    Eq,
+    Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Arg {
+    /// Look up facts by a value known before the scan
+    Key(Term),
+    /// Check a value bound earlier in the same scan
+    Check(Term),
+    Bind(usize),
+    Ignore,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    /// Scan the facts for the `index`th predicate of the body (only the new
+    /// facts of the last round if `delta` is true), looking them up by the
+    /// values of their `Key` arguments (at positions `columns`)
+    Scan {
+        index: usize,
+        relation: Relation,
+        args: Vec<Arg>,
+        columns: Vec<usize>,
+        delta: bool,
+    },
+    /// Bind a variable to each value of its type in the domain
+    Enumerate { var: usize, typ: ValueType },
+    /// Bind a variable to the value of an expression (if it is in the domain)
+    Assign { var: usize, expr: Expr },
+    /// Check that a comparison holds
+    Check {
+        comparison: Comparison,
+        left: Expr,
+        right: Term,
+    },
+}
+
+struct Plan {
+    head: Vec<Term>,
+    facts: Vec<Option<Relation>>,
+    vars: usize,
+    steps: Vec<Step>,
+}
+
+struct Planner<'a> {
+    vars: IndexSet<&'a Value>,
+    bound: Vec<bool>,
+}
+
+impl<'a> Planner<'a> {
+    fn term(&self, v: &Value) -> Term {
+        match self.vars.get_index_of(v) {
+            Some(i) => Term::Var(i),
+            None => Term::Const(v.clone()),
+        }
+    }
+
+    fn known(&self, v: &Value) -> bool {
+        match self.vars.get_index_of(v) {
+            Some(i) => self.bound[i],
+            None => true,
+        }
+    }
+
+    fn unknown_var(&self, v: &Value) -> usize {
+        self.vars.get_index_of(v).unwrap()
+    }
+
+    fn comparison(
+        &self,
+        comparison: Comparison,
+        left: &Value,
+        right: &Value,
+    ) -> Option<Step> {
+        if self.known(left) && self.known(right) {
+            Some(Step::Check {
+                comparison,
+                left: Expr::Term(self.term(left)),
+                right: self.term(right),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a step for an arithmetic primitive `left op right = result` if
+    /// at most one of its values is unknown, where `solve_right` computes the
+    /// right value from the left value and result (and likewise `solve_left`
+    /// computes the left value from the result and right value).
+    fn arithmetic(
+        &self,
+        (left, right, result): (&Value, &Value, &Value),
+        op: fn(Term, Term) -> Expr,
+        solve_right: fn(Term, Term) -> Expr,
+        solve_left: fn(Term, Term) -> Expr,
+    ) -> Option<Step> {
+        match (self.known(left), self.known(right), self.known(result)) {
+            (true, true, true) => Some(Step::Check {
+                comparison: Comparison::Eq,
+                left: op(self.term(left), self.term(right)),
+                right: self.term(result),
+            }),
+            (true, true, false) => Some(Step::Assign {
+                var: self.unknown_var(result),
+                expr: op(self.term(left), self.term(right)),
+            }),
+            (true, false, true) => Some(Step::Assign {
+                var: self.unknown_var(right),
+                expr: solve_right(self.term(left), self.term(result)),
+            }),
+            (false, true, true) => Some(Step::Assign {
+                var: self.unknown_var(left),
+                expr: solve_left(self.term(result), self.term(right)),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns a step for a primitive if it can be evaluated (that is, if at
+    /// most one of its variables is unknown and it can be computed).
+    fn primitive(&self, p: &Predicate) -> Option<Step> {
+        match p {
+            Predicate::Fact(_) => None,
+            Predicate::PrimEq(left, right) => {
+                match (self.known(left), self.known(right)) {
+                    (true, false) => Some(Step::Assign {
+                        var: self.unknown_var(right),
+                        expr: Expr::Term(self.term(left)),
+                    }),
+                    (false, true) => Some(Step::Assign {
+                        var: self.unknown_var(left),
+                        expr: Expr::Term(self.term(right)),
+                    }),
+                    _ => self.comparison(Comparison::Eq, left, right),
+                }
+            }
+            Predicate::PrimNeq(left, right) => {
+                self.comparison(Comparison::Neq, left, right)
+            }
+            Predicate::PrimLt(left, right) => {
+                self.comparison(Comparison::Lt, left, right)
+            }
+            Predicate::PrimLe(left, right) => {
+                self.comparison(Comparison::Le, left, right)
+            }
+            Predicate::PrimGt(left, right) => {
+                self.comparison(Comparison::Gt, left, right)
+            }
+            Predicate::PrimGe(left, right) => {
+                self.comparison(Comparison::Ge, left, right)
+            }
+            // x + y = z iff y = z - x iff x = z - y
+            Predicate::PrimAdd(left, right, result) => self.arithmetic(
+                (left, right, result),
+                Expr::Add,
+                |x, z| Expr::Sub(z, x),
+                Expr::Sub,
+            ),
+            // x - y = z iff y = x - z iff x = z + y
+            Predicate::PrimSub(left, right, result) => self.arithmetic(
+                (left, right, result),
+                Expr::Sub,
+                Expr::Sub,
+                Expr::Add,
+            ),
+        }
+    }
+
+    fn bind(&mut self, step: &Step) {
+        match step {
+            Step::Scan { args, .. } => {
+                for arg in args {
+                    if let Arg::Bind(i) = arg {
+                        self.bound[*i] = true;
+                    }
+                }
+            }
+            Step::Enumerate { var, .. } | Step::Assign { var, .. } => {
+                self.bound[*var] = true
+            }
+            Step::Check { .. } => (),
+        }
+    }
+
+    fn scan(&mut self, index: usize, f: &Fact, delta: bool) -> Step {
+        let known: Vec<bool> = f
+            .args
+            .iter()
+            .map(|ov| ov.as_ref().is_some_and(|v| self.known(v)))
+            .collect();
+        let mut args = vec![];
+        for (ov, known) in f.args.iter().zip(known) {
+            args.push(match ov {
+                None => Arg::Ignore,
+                Some(v) if known => Arg::Key(self.term(v)),
+                Some(v) if self.known(v) => Arg::Check(self.term(v)),
+                Some(v) => {
+                    // Mark as bound immediately for repeated variables
+                    let i = self.unknown_var(v);
+                    self.bound[i] = true;
+                    Arg::Bind(i)
+                }
+            });
+        }
+        Step::Scan {
+            index,
+            relation: f.relation.clone(),
+            columns: args
+                .iter()
+                .enumerate()
+                .filter_map(|(i, arg)| match arg {
+                    Arg::Key(_) => Some(i),
+                    _ => None,
+                })
+                .collect(),
+            args,
+            delta,
+        }
+    }
+
+    fn known_args(&self, f: &Fact) -> usize {
+        f.args
+            .iter()
+            .filter(|ov| ov.as_ref().is_some_and(|v| self.known(v)))
+            .count()
+    }
+}
+
+impl Plan {
+    /// The relations scanned by the plan (and the columns they are looked up
+    /// by), separated into those that scan all facts and only new facts
+    fn scans(&self) -> [Vec<(Relation, Vec<usize>)>; 2] {
+        let mut scans = [vec![], vec![]];
+        for step in &self.steps {
+            if let Step::Scan {
+                relation,
+                columns,
+                delta,
+                ..
+            } = step
+            {
+                scans[*delta as usize]
+                    .push((relation.clone(), columns.clone()));
+            }
+        }
+        scans
+    }
+
+    /// Plans a rule (scanning only the new facts for the `delta`th predicate
+    /// of the body, if any), or returns `None` if the rule mentions a value
+    /// outside of the domain (in which case it can never fire).
+    fn new(rule: &Rule, dom: &Domain, delta: Option<usize>) -> Option<Self> {
+        let vals = rule.vals();
+
+        if vals.iter().any(|v| {
+            !matches!(v, Value::Var { .. } | Value::Bool(_)) && !dom.contains(v)
+        }) {
+            return None;
+        }
+
+        let vars: IndexSet<&Value> = vals
+            .iter()
+            .filter(|v| matches!(v, Value::Var { .. }))
+            .collect();
+
+        let mut planner = Planner {
+            bound: vec![false; vars.len()],
+            vars,
+        };
+
+        let mut remaining: Vec<usize> = (0..rule.body.len()).collect();
+        let mut steps = vec![];
+
+        while !remaining.is_empty() {
+            if let Some((pos, step)) =
+                remaining.iter().enumerate().find_map(|(pos, i)| {
+                    planner.primitive(&rule.body[*i]).map(|step| (pos, step))
+                })
+            {
+                remaining.remove(pos);
+                planner.bind(&step);
+                steps.push(step);
+                continue;
+            }
+
+            let fact_pos = remaining
+                .iter()
+                .position(|i| Some(*i) == delta)
+                .or_else(|| {
+                    remaining
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(pos, i)| match &rule.body[*i] {
+                            Predicate::Fact(f) => {
+                                Some((pos, planner.known_args(f)))
+                            }
+                            _ => None,
+                        })
+                        .rev()
+                        .max_by_key(|(_, known)| *known)
+                        .map(|(pos, _)| pos)
+                });
+
+            if let Some(pos) = fact_pos {
+                let i = remaining.remove(pos);
+                match &rule.body[i] {
+                    Predicate::Fact(f) => {
+                        let step = planner.scan(i, f, Some(i) == delta);
+                        steps.push(step);
+                    }
+                    _ => unreachable!(),
+                }
+                continue;
+            }
+
+            // Only primitives with more than one unknown variable remain
+            let v = rule.body[remaining[0]]
+                .vals()
+                .into_iter()
+                .find(|v| !planner.known(v))
+                .unwrap();
+            let step = Step::Enumerate {
+                var: planner.unknown_var(&v),
+                typ: v.unsafe_infer(),
+            };
+            planner.bind(&step);
+            steps.push(step);
+        }
+
+        // Variables that only appear in the head range over the whole domain
+        for (i, v) in planner.vars.iter().enumerate() {
+            if !planner.bound[i] {
+                steps.push(Step::Enumerate {
+                    var: i,
+                    typ: v.unsafe_infer(),
+                });
+            }
+        }
+
+        Some(Self {
+            head: rule
+                .head
+                .args
+                .iter()
+                .map(|ov| planner.term(ov.as_ref().unwrap()))
+                .collect(),
+            facts: rule
+                .body
+                .iter()
+                .map(|p| match p {
+                    Predicate::Fact(f) => Some(f.relation.clone()),
+                    _ => None,
+                })
+                .collect(),
+            vars: planner.vars.len(),
+            steps,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Evaluation
+
+fn term<'a>(t: &'a Term, bindings: &'a [Option<Value>]) -> &'a Value {
+    match t {
+        Term::Const(v) => v,
+        Term::Var(i) => bindings[*i].as_ref().unwrap(),
+    }
+}
+
+fn expr(e: &Expr, bindings: &[Option<Value>]) -> Option<Value> {
+    match e {
+        Expr::Term(t) => Some(term(t, bindings).clone()),
+        Expr::Add(left, right) => {
+            match (term(left, bindings), term(right, bindings)) {
+                (Value::Int(x), Value::Int(y)) => x.checked_add(*y),
+                _ => None,
+            }
+            .map(Value::Int)
+        }
+        Expr::Sub(left, right) => {
+            match (term(left, bindings), term(right, bindings)) {
+                (Value::Int(x), Value::Int(y)) => x.checked_sub(*y),
+                _ => None,
+            }
+            .map(Value::Int)
+        }
+    }
+}
+
+fn compare(comparison: &Comparison, left: &Value, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Int(x), Value::Int(y)) => x.partial_cmp(y),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        _ => None,
+    };
+    match comparison {
+        Comparison::Eq => left == right,
+        Comparison::Neq => left != right,
+        Comparison::Lt => ordering.is_some_and(|o| o.is_lt()),
+        Comparison::Le => ordering.is_some_and(|o| o.is_le()),
+        Comparison::Gt => ordering.is_some_and(|o| o.is_gt()),
+        Comparison::Ge => ordering.is_some_and(|o| o.is_ge()),
+    }
+}
+
+/// The state of a single evaluation of a plan.
+struct Evaluation<'a> {
+    plan: &'a Plan,
+    db: &'a Database,
+    delta: &'a Database,
+    dom: &'a IndexMap<ValueType, Vec<Value>>,
+    bindings: Vec<Option<Value>>,
+    matched: Vec<Option<&'a Tuple>>,
+    results: Vec<(Tuple, Vec<(Relation, Tuple)>)>,
+    limit: usize,
+}
+
+impl<'a> Evaluation<'a> {
+    fn in_domain(&self, v: &Value) -> bool {
+        self.dom
+            .get(&v.unsafe_infer())
+            .is_some_and(|vals| vals.contains(v))
+    }
+
+    fn done(&self) -> bool {
+        self.results.len() >= self.limit
+    }
+
+    fn run(&mut self, k: usize) {
+        let plan = self.plan;
+        let step = match plan.steps.get(k) {
+            Some(step) => step,
+            None => {
+                let head = plan
+                    .head
+                    .iter()
+                    .map(|t| term(t, &self.bindings).clone())
+                    .collect();
+                let body = plan
+                    .facts
+                    .iter()
+                    .zip(self.matched.iter())
+                    .filter_map(|(orel, otuple)| {
+                        Some((orel.clone()?, (*otuple)?.clone()))
+                    })
+                    .collect();
+                self.results.push((head, body));
+                return;
+            }
+        };
+
+        match step {
+            Step::Scan {
+                index,
+                relation,
+                args,
+                columns,
+                delta,
+            } => {
+                let source = if *delta { self.delta } else { self.db };
+                let key = args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        Arg::Key(t) => Some(term(t, &self.bindings).clone()),
+                        _ => None,
+                    })
+                    .collect();
+                'tuples: for tuple in source.scan(relation, columns, &key) {
+                    for (arg, v) in args.iter().zip(tuple.iter()) {
+                        match arg {
+                            Arg::Key(_) => (),
+                            Arg::Check(t) => {
+                                if term(t, &self.bindings) != v {
+                                    continue 'tuples;
+                                }
+                            }
+                            Arg::Bind(i) => self.bindings[*i] = Some(v.clone()),
+                            Arg::Ignore => (),
+                        }
+                    }
+                    self.matched[*index] = Some(tuple);
+                    self.run(k + 1);
+                    if self.done() {
+                        break;
+                    }
+                }
+                self.matched[*index] = None;
+                for arg in args {
+                    if let Arg::Bind(i) = arg {
+                        self.bindings[*i] = None;
+                    }
+                }
+            }
+            Step::Enumerate { var, typ } => {
+                let dom = self.dom;
+                let vals = match dom.get(typ) {
+                    Some(vals) => vals,
+                    None => return,
+                };
+                for v in vals {
+                    self.bindings[*var] = Some(v.clone());
+                    self.run(k + 1);
+                    if self.done() {
+                        break;
+                    }
+                }
+                self.bindings[*var] = None;
+            }
+            Step::Assign { var, expr: e } => {
+                if let Some(v) = expr(e, &self.bindings) {
+                    if self.in_domain(&v) {
+                        self.bindings[*var] = Some(v);
+                        self.run(k + 1);
+                        self.bindings[*var] = None;
+                    }
+                }
+            }
+            Step::Check {
+                comparison,
+                left,
+                right,
+            } => {
+                if let Some(v) = expr(left, &self.bindings) {
+                    if compare(comparison, &v, term(right, &self.bindings)) {
+                        self.run(k + 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Engine
+
+struct State {
+    all: Domain,
+    dom: IndexMap<ValueType, Vec<Value>>,
+    db: Database,
+    provenance: IndexMap<(Relation, Tuple), Justification>,
+}
+
+impl State {
+    /// Evaluates a plan, stopping after `limit` (possibly duplicate) results.
+    fn evaluate(
+        &mut self,
+        plan: &Plan,
+        delta: &mut Database,
+        limit: usize,
+    ) -> Vec<(Tuple, Vec<(Relation, Tuple)>)> {
+        let [all, new] = plan.scans();
+        self.db.index(&all);
+        delta.index(&new);
+        let mut evaluation = Evaluation {
+            plan,
+            db: &self.db,
+            delta,
+            dom: &self.dom,
+            bindings: vec![None; plan.vars],
+            matched: vec![None; plan.facts.len()],
+            results: vec![],
+            limit,
+        };
+        evaluation.run(0);
+        evaluation.results
+    }
+
+    fn tree(&self, relation: &Relation, tuple: &Tuple) -> DerivationTree {
+        let fact = Fact {
+            relation: relation.clone(),
+            args: tuple.iter().cloned().map(Some).collect(),
+        };
+        match self.provenance.get(&(relation.clone(), tuple.clone())) {
+            Some((rule, body)) => DerivationTree {
+                fact,
+                rule: Some(rule.clone()),
+                children: body
+                    .iter()
+                    .map(|(rel, tuple)| self.tree(rel, tuple))
+                    .collect(),
+            },
+            None => DerivationTree {
+                fact,
+                rule: None,
+                children: vec![],
+            },
+        }
+    }
+}
+
+fn is_idb(lib: &RelationLibrary, relation: &Relation) -> bool {
+    lib.get(relation)
+        .is_some_and(|sig| sig.kind == RelationKind::IDB)
+}
+
+/// A pure-Rust semi-naive Datalog engine
+pub struct SemiNaive {
+    state: Option<State>,
+}
+
+impl SemiNaive {
+    pub fn new() -> Self {
+        Self { state: None }
+    }
+
+    fn state(&mut self) -> Result<&mut State, Error> {
+        self.state.as_mut().ok_or(Error::NotLoaded)
+    }
+}
+
+impl Engine for SemiNaive {
+    fn load(&mut self, program: Program) -> Result<(), Error> {
+        let mut dom: IndexMap<ValueType, Vec<Value>> = IndexMap::new();
+
+        // Booleans are never restricted by the domain
+        dom.insert(
+            ValueType::Bool,
+            vec![Value::Bool(true), Value::Bool(false)],
+        );
+        for v in &program.dom {
+            dom.entry(v.unsafe_infer()).or_default().push(v.clone());
+        }
+
+        let mut db = Database::default();
+
+        for rel in program.lib.keys() {
+            db.facts.insert(rel.clone(), IndexSet::new());
+        }
+
+        for f in &program.ground_facts {
+            db.insert(
+                &f.relation,
+                f.args.iter().map(|ov| ov.clone().unwrap()).collect(),
+            );
+        }
+
+        let mut state = State {
+            all: dom.values().flatten().cloned().collect(),
+            dom,
+            db,
+            provenance: IndexMap::new(),
+        };
+
+        // The plans for the first (naive) round, followed by the plans for
+        // the subsequent (semi-naive) rounds, which use at least one new fact
+        let naive: Vec<(&Rule, Plan)> = program
+            .rules
+            .iter()
+            .filter_map(|r| Some((r, Plan::new(r, &program.dom, None)?)))
+            .collect();
+
+        let mut semi_naive: Vec<(&Rule, Plan)> = vec![];
+        for r in &program.rules {
+            for (i, p) in r.body.iter().enumerate() {
+                if let Predicate::Fact(f) = p {
+                    if is_idb(&program.lib, &f.relation) {
+                        semi_naive.extend(
+                            Plan::new(r, &program.dom, Some(i)).map(|p| (r, p)),
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut delta = Database::default();
+        let mut plans = &naive;
+
+        loop {
+            let mut new = Database::default();
+
+            for (rule, plan) in plans {
+                for (head, body) in state.evaluate(plan, &mut delta, usize::MAX)
+                {
+                    let rel = &rule.head.relation;
+                    if state.db.contains(rel, &head)
+                        || !new.insert(rel, head.clone())
+                    {
+                        continue;
+                    }
+                    state
+                        .provenance
+                        .insert((rel.clone(), head), (rule.name.clone(), body));
+                }
+            }
+
+            if new.is_empty() {
+                break;
+            }
+
+            for (rel, tuples) in &new.facts {
+                for tuple in tuples {
+                    state.db.insert(rel, tuple.clone());
+                }
+            }
+
+            delta = new;
+            plans = &semi_naive;
+        }
+
+        self.state = Some(state);
+        Ok(())
+    }
+
+    fn query(
+        &mut self,
+        _signature: &RelationSignature,
+        rule: &Rule,
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        let state = self.state()?;
+        Ok(match Plan::new(rule, &state.all, None) {
+            Some(plan) => state
+                .evaluate(&plan, &mut Database::default(), usize::MAX)
+                .into_iter()
+                .map(|(head, _)| head)
+                .collect::<IndexSet<_>>()
+                .into_iter()
+                .collect(),
+            None => vec![],
+        })
+    }
+
+    fn query_with_provenance(
+        &mut self,
+        _signature: &RelationSignature,
+        rule: &Rule,
+    ) -> Result<Vec<(Vec<Value>, DerivationTree)>, Error> {
+        let state = self.state()?;
+        let plan = match Plan::new(rule, &state.all, None) {
+            Some(plan) => plan,
+            None => return Ok(vec![]),
+        };
+
+        let mut seen = IndexSet::new();
+        let mut ret = vec![];
+
+        for (head, body) in
+            state.evaluate(&plan, &mut Database::default(), usize::MAX)
+        {
+            if !seen.insert(head.clone()) {
+                continue;
+            }
+            let tree = DerivationTree {
+                fact: Fact {
+                    relation: rule.head.relation.clone(),
+                    args: head.iter().cloned().map(Some).collect(),
+                },
+                rule: Some(rule.name.clone()),
+                children: body
+                    .iter()
+                    .map(|(rel, tuple)| state.tree(rel, tuple))
+                    .collect(),
+            };
+            ret.push((head, tree));
+        }
+
+        Ok(ret)
+    }
+
+    fn exists(
+        &mut self,
+        _signature: &RelationSignature,
+        rule: &Rule,
+        bound: &[Option<Value>],
+    ) -> Result<bool, Error> {
+        let state = self.state()?;
+        Ok(match Plan::new(&rule.bind(bound), &state.all, None) {
+            Some(plan) => !state
+                .evaluate(&plan, &mut Database::default(), 1)
+                .is_empty(),
+            None => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Value {
+        Value::Var {
+            name: name.to_owned(),
+            typ: ValueType::Int,
+        }
+    }
+
+    fn fact(relation: &str, args: Vec<Value>) -> Fact {
+        Fact {
+            relation: Relation(relation.to_owned()),
+            args: args.into_iter().map(Some).collect(),
+        }
+    }
+
+    fn rule(head: Fact, body: Vec<Predicate>) -> Rule {
+        Rule {
+            name: "test".to_owned(),
+            head,
+            body,
+        }
+    }
+
+    fn signature(arity: usize, kind: RelationKind) -> RelationSignature {
+        RelationSignature {
+            params: vec![ValueType::Int; arity],
+            kind,
+        }
+    }
+
+    fn ints(xs: &[i64]) -> Domain {
+        xs.iter().copied().map(Value::Int).collect()
+    }
+
+    /// Loads a program over the integers 0 to 9 with a unary IDB `R`, a unary
+    /// EDB `Q` and a binary EDB `E`
+    fn load(
+        rules: Vec<Rule>,
+        q: &[i64],
+        e: &[(i64, i64)],
+    ) -> Result<SemiNaive, Error> {
+        let lib = IndexMap::from([
+            (Relation("R".to_owned()), signature(1, RelationKind::IDB)),
+            (Relation("Q".to_owned()), signature(1, RelationKind::EDB)),
+            (Relation("E".to_owned()), signature(2, RelationKind::EDB)),
+            (Relation("T".to_owned()), signature(2, RelationKind::IDB)),
+        ]);
+        let mut ground_facts = vec![];
+        for x in q {
+            ground_facts.push(fact("Q", vec![Value::Int(*x)]));
+        }
+        for (x, y) in e {
+            ground_facts.push(fact("E", vec![Value::Int(*x), Value::Int(*y)]));
+        }
+        let program = Program::new(
+            lib,
+            ints(&(0..10).collect::<Vec<_>>()),
+            rules,
+            ground_facts,
+        )?;
+        let mut engine = SemiNaive::new();
+        engine.load(program)?;
+        Ok(engine)
+    }
+
+    fn query(
+        engine: &mut SemiNaive,
+        relation: &str,
+        arity: usize,
+    ) -> IndexSet<Vec<i64>> {
+        let vars: Vec<Value> =
+            (0..arity).map(|i| var(&format!("v{}", i))).collect();
+        engine
+            .query(
+                &signature(arity, RelationKind::IDB),
+                &rule(
+                    fact("&Query", vars.clone()),
+                    vec![Predicate::Fact(fact(relation, vars))],
+                ),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|vals| {
+                vals.into_iter()
+                    .map(|v| match v {
+                        Value::Int(x) => x,
+                        v => panic!("expected Int, got {:?}", v),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arithmetic_is_solved_for_any_unknown() {
+        // R(x) :- Q(z), x + 1 = z
+        let add = rule(
+            fact("R", vec![var("x")]),
+            vec![
+                Predicate::Fact(fact("Q", vec![var("z")])),
+                Predicate::PrimAdd(var("x"), Value::Int(1), var("z")),
+            ],
+        );
+        let plan = Plan::new(&add, &ints(&[1]), None).unwrap();
+        assert!(matches!(
+            plan.steps.as_slice(),
+            [
+                Step::Scan { .. },
+                Step::Assign {
+                    var: 0,
+                    expr: Expr::Sub(Term::Var(1), Term::Const(Value::Int(1)))
+                }
+            ]
+        ));
+
+        // R(y) :- Q(x), x - y = 2
+        let sub = rule(
+            fact("R", vec![var("y")]),
+            vec![
+                Predicate::Fact(fact("Q", vec![var("x")])),
+                Predicate::PrimSub(var("x"), var("y"), Value::Int(2)),
+            ],
+        );
+
+        let mut engine = load(vec![add], &[0, 3, 9], &[]).unwrap();
+        assert_eq!(
+            query(&mut engine, "R", 1),
+            IndexSet::from([vec![2], vec![8]])
+        );
+
+        let mut engine = load(vec![sub], &[0, 3, 9], &[]).unwrap();
+        assert_eq!(
+            query(&mut engine, "R", 1),
+            IndexSet::from([vec![1], vec![7]])
+        );
+    }
+
+    #[test]
+    fn arithmetic_results_outside_the_domain_are_dropped() {
+        // R(x) :- Q(y), y + 5 = x (only 0 + 5 and 4 + 5 are in the domain)
+        let r = rule(
+            fact("R", vec![var("x")]),
+            vec![
+                Predicate::Fact(fact("Q", vec![var("y")])),
+                Predicate::PrimAdd(var("y"), Value::Int(5), var("x")),
+            ],
+        );
+        let mut engine = load(vec![r], &[0, 4, 7], &[]).unwrap();
+        assert_eq!(
+            query(&mut engine, "R", 1),
+            IndexSet::from([vec![5], vec![9]])
+        );
+    }
+
+    #[test]
+    fn repeated_variables_must_agree() {
+        // R(x) :- E(x, x)
+        let r = rule(
+            fact("R", vec![var("x")]),
+            vec![Predicate::Fact(fact("E", vec![var("x"), var("x")]))],
+        );
+        let plan = Plan::new(&r, &ints(&[]), None).unwrap();
+        assert!(matches!(
+            plan.steps.as_slice(),
+            [Step::Scan { args, .. }]
+                if matches!(
+                    args.as_slice(),
+                    [Arg::Bind(0), Arg::Check(Term::Var(0))]
+                )
+        ));
+
+        let mut engine = load(vec![r], &[], &[(1, 1), (1, 2), (3, 3)]).unwrap();
+        assert_eq!(
+            query(&mut engine, "R", 1),
+            IndexSet::from([vec![1], vec![3]])
+        );
+    }
+
+    #[test]
+    fn delta_plans_scan_new_facts_first() {
+        // T(x, z) :- E(x, y), T(y, z)
+        let step = rule(
+            fact("T", vec![var("x"), var("z")]),
+            vec![
+                Predicate::Fact(fact("E", vec![var("x"), var("y")])),
+                Predicate::Fact(fact("T", vec![var("y"), var("z")])),
+            ],
+        );
+        // T(x, y) :- E(x, y)
+        let base = rule(
+            fact("T", vec![var("x"), var("y")]),
+            vec![Predicate::Fact(fact("E", vec![var("x"), var("y")]))],
+        );
+
+        let plan = Plan::new(&step, &ints(&[]), Some(1)).unwrap();
+        assert!(matches!(
+            plan.steps.as_slice(),
+            [
+                Step::Scan {
+                    index: 1,
+                    delta: true,
+                    ..
+                },
+                Step::Scan {
+                    index: 0,
+                    delta: false,
+                    ..
+                }
+            ]
+        ));
+
+        // The transitive closure of a path needs one round per edge
+        let mut engine =
+            load(vec![step, base], &[], &[(0, 1), (1, 2), (2, 3), (3, 4)])
+                .unwrap();
+        let closure: IndexSet<Vec<i64>> = (0..5)
+            .flat_map(|x| (x + 1..5).map(move |y| vec![x, y]))
+            .collect();
+        assert_eq!(query(&mut engine, "T", 2), closure);
+    }
+
+    #[test]
+    fn scans_look_up_known_arguments() {
+        // T(x, z) :- T(x, y), T(y, z)
+        let step = rule(
+            fact("T", vec![var("x"), var("z")]),
+            vec![
+                Predicate::Fact(fact("T", vec![var("x"), var("y")])),
+                Predicate::Fact(fact("T", vec![var("y"), var("z")])),
+            ],
+        );
+        // T(x, y) :- E(x, y)
+        let base = rule(
+            fact("T", vec![var("x"), var("y")]),
+            vec![Predicate::Fact(fact("E", vec![var("x"), var("y")]))],
+        );
+
+        let plan = Plan::new(&step, &ints(&[]), Some(0)).unwrap();
+        match plan.steps.as_slice() {
+            [Step::Scan { columns: first, .. }, Step::Scan {
+                columns: second,
+                args,
+                ..
+            }] => {
+                assert!(first.is_empty());
+                assert_eq!(second, &vec![0]);
+                assert!(matches!(args.as_slice(), [Arg::Key(_), Arg::Bind(_)]));
+            }
+            steps => panic!("unexpected plan: {:?}", steps),
+        }
+
+        // The index on the first argument of T must be kept up to date as
+        // the closure is derived over several rounds
+        let mut engine = load(
+            vec![step, base],
+            &[],
+            &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 6)],
+        )
+        .unwrap();
+        let closure: IndexSet<Vec<i64>> = (0..7)
+            .flat_map(|x| (x + 1..7).map(move |y| vec![x, y]))
+            .collect();
+        assert_eq!(query(&mut engine, "T", 2), closure);
+
+        let state = engine.state().unwrap();
+        let index = &state.db.indexes[&Relation("T".to_owned())][&vec![0]];
+        assert_eq!(index[&vec![Value::Int(0)]].len(), 6);
+    }
+
+    #[test]
+    fn exists_stops_at_the_first_answer() {
+        // R(x) :- Q(x)
+        let r = rule(
+            fact("R", vec![var("x")]),
+            vec![Predicate::Fact(fact("Q", vec![var("x")]))],
+        );
+        let mut engine = load(vec![r.clone()], &[1, 2, 3], &[]).unwrap();
+
+        let state = engine.state().unwrap();
+        let plan = Plan::new(&r, &state.all, None).unwrap();
+        assert_eq!(state.evaluate(&plan, &mut Database::default(), 1).len(), 1);
+        assert_eq!(
+            state
+                .evaluate(&plan, &mut Database::default(), usize::MAX)
+                .len(),
+            3
+        );
+
+        let sig = signature(1, RelationKind::IDB);
+        assert!(engine.exists(&sig, &r, &[Some(Value::Int(2))]).unwrap());
+        assert!(!engine.exists(&sig, &r, &[Some(Value::Int(4))]).unwrap());
+        assert!(engine.exists(&sig, &r, &[None]).unwrap());
+    }
+}
@@ -0,0 +1,362 @@
+//! # Soufflé export
+//!
+//! This module exports Datalog programs (and queries) to the syntax of the
+//! [Soufflé](https://souffle-lang.github.io/) Datalog engine so that they can
+//! be inspected and run outside of Honeybee.
+//!
+//! The export mirrors the egglog backend: the domain is exported as one
+//! relation per value type, and every value of a rule is constrained to lie in
+//! it. Soufflé has no booleans, so booleans are encoded as the numbers 0
+//! (false) and 1 (true). Names that are not valid Soufflé identifiers (such as
+//! the `ret.x` variables of the oracle) are mangled into ones that are.
+//!
+//! Several queries may share a head relation (such as the solvability queries
+//! of a program with several goals), so each query gets its own output
+//! relation.
+
+use crate::datalog::*;
+use crate::util;
+
+use indexmap::{IndexMap, IndexSet};
+
+struct Exporter {
+    content: String,
+    names: IndexMap<String, String>,
+    used: IndexSet<String>,
+}
+
+impl Exporter {
+    fn new() -> Self {
+        Self {
+            content: String::new(),
+            names: IndexMap::new(),
+            used: IndexSet::new(),
+        }
+    }
+
+    fn writeln(&mut self, s: &str) {
+        self.content += s;
+        self.content += "\n";
+    }
+
+    /// Returns a valid (and unique) identifier for a name.
+    fn name(&mut self, name: &str) -> String {
+        if let Some(mangled) = self.names.get(name) {
+            return mangled.clone();
+        }
+
+        let mangled = self.fresh(name);
+        self.names.insert(name.to_owned(), mangled.clone());
+        mangled
+    }
+
+    /// Returns a valid identifier for a name that differs from every
+    /// identifier returned so far (even if the name has been seen before).
+    fn fresh(&mut self, name: &str) -> String {
+        let mut base: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if !base.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            base.insert(0, '_');
+        }
+
+        let mut mangled = base.clone();
+        let mut i = 1;
+        while self.used.contains(&mangled) {
+            mangled = format!("{}_{}", base, i);
+            i += 1;
+        }
+
+        self.used.insert(mangled.clone());
+        mangled
+    }
+
+    fn value_type(vt: &ValueType) -> &'static str {
+        match vt {
+            ValueType::Bool | ValueType::Int => "number",
+            ValueType::Float => "float",
+            ValueType::Str => "symbol",
+        }
+    }
+
+    fn domain_relation(&mut self, vt: &ValueType) -> String {
+        self.name(match vt {
+            ValueType::Bool => "&Bool",
+            ValueType::Int => "&Int",
+            ValueType::Float => "&Float",
+            ValueType::Str => "&Str",
+        })
+    }
+
+    fn value(&mut self, v: &Value) -> String {
+        match v {
+            Value::Bool(b) => (*b as i64).to_string(),
+            Value::Int(x) => x.to_string(),
+            Value::Float(x) if x.is_finite() => {
+                let s = x.to_string();
+                if s.contains('.') {
+                    s
+                } else {
+                    format!("{}.0", s)
+                }
+            }
+            Value::Float(x) => format!("to_float(\"{}\")", x),
+            Value::Str(s) => util::quote(s),
+            Value::Var { name, .. } => self.name(name),
+        }
+    }
+
+    fn declaration(&mut self, rel: &str, sig: &RelationSignature) {
+        let params: Vec<String> = sig
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, vt)| format!("x{}: {}", i, Self::value_type(vt)))
+            .collect();
+        self.writeln(&format!(".decl {}({})", rel, params.join(", ")));
+    }
+
+    fn atom(&mut self, rel: &str, args: &[Option<Value>]) -> String {
+        let args: Vec<String> = args
+            .iter()
+            .map(|ov| match ov {
+                Some(v) => self.value(v),
+                None => "_".to_owned(),
+            })
+            .collect();
+        format!("{}({})", rel, args.join(", "))
+    }
+
+    fn fact(&mut self, f: &Fact) -> String {
+        let rel = self.name(&f.relation.0);
+        self.atom(&rel, &f.args)
+    }
+
+    fn binary(&mut self, op: &str, left: &Value, right: &Value) -> String {
+        format!("{} {} {}", self.value(left), op, self.value(right))
+    }
+
+    fn arithmetic(
+        &mut self,
+        op: &str,
+        left: &Value,
+        right: &Value,
+        result: &Value,
+    ) -> String {
+        format!(
+            "{} = {} {} {}",
+            self.value(result),
+            self.value(left),
+            op,
+            self.value(right)
+        )
+    }
+
+    fn predicate(&mut self, p: &Predicate) -> String {
+        match p {
+            Predicate::Fact(f) => self.fact(f),
+            Predicate::PrimEq(left, right) => self.binary("=", left, right),
+            Predicate::PrimLt(left, right) => self.binary("<", left, right),
+            Predicate::PrimLe(left, right) => self.binary("<=", left, right),
+            Predicate::PrimGt(left, right) => self.binary(">", left, right),
+            Predicate::PrimGe(left, right) => self.binary(">=", left, right),
+            Predicate::PrimNeq(left, right) => self.binary("!=", left, right),
+            Predicate::PrimAdd(left, right, result) => {
+                self.arithmetic("+", left, right, result)
+            }
+            Predicate::PrimSub(left, right, result) => {
+                self.arithmetic("-", left, right, result)
+            }
+        }
+    }
+
+    /// Writes a rule, whose head relation is `head` (an identifier)
+    fn rule(&mut self, r: &Rule, head: &str) {
+        let mut body: Vec<String> =
+            r.body.iter().map(|p| self.predicate(p)).collect();
+        for v in r.vals() {
+            let rel = self.domain_relation(&v.unsafe_infer());
+            body.push(format!("{}({})", rel, self.value(&v)));
+        }
+
+        self.writeln(&format!("// {}", r.name));
+        let head = self.atom(head, &r.head.args);
+        self.writeln(&format!("{} :-\n    {}.", head, body.join(",\n    ")));
+    }
+
+    fn program(
+        &mut self,
+        prog: &Program,
+        queries: &[(Rule, RelationSignature)],
+    ) {
+        self.writeln("// Domain\n");
+
+        for vt in [
+            ValueType::Bool,
+            ValueType::Int,
+            ValueType::Float,
+            ValueType::Str,
+        ] {
+            let rel = self.domain_relation(&vt);
+            self.writeln(&format!(
+                ".decl {}(x0: {})",
+                rel,
+                Self::value_type(&vt)
+            ));
+        }
+
+        let bool_rel = self.domain_relation(&ValueType::Bool);
+        self.writeln(&format!("{}(0).", bool_rel));
+        self.writeln(&format!("{}(1).", bool_rel));
+
+        for v in &prog.dom {
+            let rel = self.domain_relation(&v.unsafe_infer());
+            let v = self.value(v);
+            self.writeln(&format!("{}({}).", rel, v));
+        }
+
+        self.writeln("\n// Relations\n");
+
+        for (rel, sig) in &prog.lib {
+            let rel = self.name(&rel.0);
+            self.declaration(&rel, sig);
+        }
+
+        self.writeln("\n// Rules\n");
+
+        for r in &prog.rules {
+            let head = self.name(&r.head.relation.0);
+            self.rule(r, &head);
+            self.writeln("");
+        }
+
+        self.writeln("// Facts\n");
+
+        for f in &prog.ground_facts {
+            let f = self.fact(f);
+            self.writeln(&format!("{}.", f));
+        }
+
+        if queries.is_empty() {
+            return;
+        }
+
+        self.writeln("\n// Queries");
+
+        for (r, sig) in queries {
+            self.writeln("");
+            let rel = self.fresh(&r.head.relation.0);
+            self.declaration(&rel, sig);
+            self.writeln(&format!(".output {}", rel));
+            self.rule(r, &rel);
+        }
+    }
+}
+
+/// Export a Datalog program along with a set of queries (whose head relations
+/// are marked as outputs) to Soufflé.
+pub fn export(
+    program: &Program,
+    queries: &[(Rule, RelationSignature)],
+) -> String {
+    let mut exporter = Exporter::new();
+    exporter.program(program, queries);
+    exporter.content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core, dl_oracle, parse};
+
+    /// The relations declared by an export, in order
+    fn declarations(export: &str) -> Vec<&str> {
+        export
+            .lines()
+            .filter_map(|line| line.strip_prefix(".decl "))
+            .map(|decl| &decl[..decl.find('(').unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn names_are_mangled_into_unique_identifiers() {
+        let mut exporter = Exporter::new();
+        assert_eq!(exporter.name("ret.x"), "ret_x");
+        assert_eq!(exporter.name("ret_x"), "ret_x_1");
+        assert_eq!(exporter.name("ret.x"), "ret_x");
+        assert_eq!(exporter.name("&Int"), "_Int");
+        assert_eq!(exporter.name("0x"), "_0x");
+        assert_eq!(exporter.name("bio.Reads"), "bio_Reads");
+        assert_eq!(exporter.fresh("bio.Reads"), "bio_Reads_1");
+        assert_eq!(exporter.name("bio.Reads"), "bio_Reads");
+    }
+
+    #[test]
+    fn relations_are_declared_exactly_once() {
+        let library = parse::library(
+            r#"
+[Type.Reads]
+params.n = "Int"
+
+[Type.Bam]
+params.n = "Int"
+
+[Function.sequence]
+params = {}
+ret = "Reads"
+condition = ["ret.n = 1"]
+
+[Function.align]
+params.reads = "Reads"
+ret = "Bam"
+condition = ["ret.n = reads.n"]
+"#,
+        )
+        .unwrap();
+        let program = parse::program(
+            r#"
+Prop = []
+
+[[Goal]]
+name = "Reads"
+args.n = 1
+
+[[Goal]]
+name = "Bam"
+args.n = 1
+"#,
+        )
+        .unwrap();
+        let problem = core::Problem { library, program };
+
+        let (_, datalog_program) = dl_oracle::compile(&problem).unwrap();
+        let queries = dl_oracle::initial_queries(&problem);
+        let export = export(&datalog_program, &queries);
+
+        let decls = declarations(&export);
+        let unique: IndexSet<&str> = decls.iter().copied().collect();
+        assert_eq!(decls.len(), unique.len(), "{}", export);
+
+        // Both goals have a solvability query, which must each get their
+        // own output relation
+        let outputs: Vec<&str> = export
+            .lines()
+            .filter_map(|line| line.strip_prefix(".output "))
+            .collect();
+        assert_eq!(outputs.len(), queries.len());
+        assert!(outputs.contains(&"_Solvability"));
+        assert!(outputs.contains(&"_Solvability_1"));
+
+        // Every relation that is used is declared under a valid identifier
+        for decl in decls {
+            assert!(decl
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        }
+        for line in export.lines().filter(|l| l.contains(":-")) {
+            let head = &line[..line.find('(').unwrap()];
+            assert!(unique.contains(head), "{}", line);
+        }
+    }
+}
@@ -3,7 +3,16 @@
 //! This module defines a particular kind of steps and expressions to work with
 //! the Programming By Navigation framework. In the instantiation provided by
 //! this module, expressions are sketches (function applications and holes), and
-//! steps extend these holes with a new function application.
+//! steps extend these holes with a new function application. Steps can also
+//! work bottom-up (or rather, middle-out) by attaching a new function
+//! application above an existing one.
+//!
+//! Sketches are trees, but since function applications are pure, structurally
+//! identical sub-expressions denote the same value; a sketch can therefore be
+//! read as a DAG in which such sub-expressions are shared. Steps may fill a
+//! hole by reusing an already-built (ground) sub-expression, and downstream
+//! consumers (such as code generators) are expected to compute each shared
+//! sub-expression only once.
 //!
 //! A variety of notions of validity could be built on top of this concrete
 //! instantiation; this module makes no requirement on which sketches are valid
@@ -112,11 +121,24 @@ impl<F: Function> pbn::ValidityChecker for GroundChecker<F> {
 
 /// The type of top-down steps.
 ///
-/// Top-down steps can either extend a hole with a new function application, or
-/// they can be a sequence of other top-down steps.
+/// Top-down steps can either extend a hole with a new function application,
+/// reuse a ground sub-expression of the working expression for a hole (so that
+/// it is shared), retract the function application at a path back into a hole
+/// (see [`Sketch::retract`]), fill a hole with a new function application that
+/// takes a given ground expression as one of its arguments (see
+/// [`Sketch::wrap`]), or they can be a sequence of other top-down steps.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TopDownStep<F: Function> {
     Extend(HoleName, F, IndexMap<FunParam, Sketch<F>>),
+    Reuse(HoleName, Sketch<F>),
+    Retract(Vec<FunParam>),
+    Wrap(
+        HoleName,
+        F,
+        FunParam,
+        Sketch<F>,
+        IndexMap<FunParam, Sketch<F>>,
+    ),
     Seq(Box<Self>, Box<Self>),
 }
 
@@ -209,6 +231,136 @@ impl<F: Function> Sketch<F> {
         }
     }
 
+    /// Returns the sub-expression at a path of parameters (if any)
+    pub fn at(&self, path: &[FunParam]) -> Option<&Self> {
+        match path.split_first() {
+            None => Some(self),
+            Some((fp, rest)) => match self {
+                Self::Hole(_) => None,
+                Self::App(_, args) => args.get(fp)?.at(rest),
+            },
+        }
+    }
+
+    /// Returns the paths of all the function applications in an expression
+    /// (parents before children)
+    pub fn app_paths(&self) -> Vec<Vec<FunParam>> {
+        match self {
+            Self::Hole(_) => vec![],
+            Self::App(_, args) => {
+                let mut ret = vec![vec![]];
+                for (fp, arg) in args {
+                    for mut path in arg.app_paths() {
+                        path.insert(0, fp.clone());
+                        ret.push(path);
+                    }
+                }
+                ret
+            }
+        }
+    }
+
+    /// Fill a hole with an application of `f` that takes the ground
+    /// application `sub` as its `fp` argument (the other arguments are taken
+    /// from `args`); returns `None` if there is no such hole, if `sub` is not
+    /// a ground application, or if the arguments do not match the arity of `f`
+    pub fn wrap(
+        &self,
+        h: HoleName,
+        f: &F,
+        fp: &FunParam,
+        sub: &Self,
+        args: &IndexMap<FunParam, Self>,
+    ) -> Option<Self> {
+        if !self.has_subterm(&Self::Hole(h))
+            || !matches!(sub, Self::App(..))
+            || !sub.ground()
+        {
+            return None;
+        }
+
+        let arity = f.arity();
+        if !arity.contains(fp) || arity.len() != args.len() + 1 {
+            return None;
+        }
+
+        let mut new_args = IndexMap::new();
+        for p in arity {
+            let arg = if p == *fp {
+                sub.clone()
+            } else {
+                args.get(&p)?.clone()
+            };
+            new_args.insert(p, arg);
+        }
+
+        Some(self.substitute(h, &Self::App(f.clone(), new_args)))
+    }
+
+    /// Replace the function application at a path of parameters with a fresh
+    /// hole, keeping the rest of the expression (including the siblings of the
+    /// application) intact; returns `None` if there is no application at the
+    /// path
+    ///
+    /// Only the occurrence at the path is retracted, so retracting one
+    /// occurrence of a shared sub-expression un-shares it: the other
+    /// occurrences are kept (and can be reused again to re-share it).
+    pub fn retract(&self, path: &[FunParam]) -> Option<Self> {
+        match self.at(path)? {
+            Self::Hole(_) => None,
+            Self::App(..) => {
+                let h = self.fresh().next().unwrap();
+                Some(self.replace(path, &Self::Hole(h)))
+            }
+        }
+    }
+
+    fn replace(&self, path: &[FunParam], e: &Self) -> Self {
+        match (path.split_first(), self) {
+            (None, _) => e.clone(),
+            (Some((fp, rest)), Self::App(f, args)) => Self::App(
+                f.clone(),
+                args.iter()
+                    .map(|(k, v)| {
+                        (
+                            k.clone(),
+                            if k == fp {
+                                v.replace(rest, e)
+                            } else {
+                                v.clone()
+                            },
+                        )
+                    })
+                    .collect(),
+            ),
+            (Some(_), Self::Hole(_)) => self.clone(),
+        }
+    }
+
+    /// Returns the distinct ground function applications in an expression
+    /// (children before parents)
+    pub fn ground_subterms(&self) -> Vec<&Self> {
+        let mut ret = vec![];
+        self.ground_subterms_helper(&mut ret);
+        ret
+    }
+
+    fn ground_subterms_helper<'a>(&'a self, acc: &mut Vec<&'a Self>) -> bool {
+        match self {
+            Self::Hole(_) => false,
+            Self::App(_, args) => {
+                let mut ground = true;
+                for v in args.values() {
+                    ground &= v.ground_subterms_helper(acc);
+                }
+                if ground && !acc.contains(&self) {
+                    acc.push(self);
+                }
+                ground
+            }
+        }
+    }
+
     fn max_hole(&self) -> HoleName {
         match self {
             Self::Hole(h) => *h,
@@ -266,6 +418,28 @@ impl<F: Function> pbn::Step for TopDownStep<F> {
                     None
                 }
             }
+            Self::Reuse(h, e2) => {
+                if e2.ground()
+                    && e.has_subterm(e2)
+                    && e.has_subterm(&Self::Exp::Hole(*h))
+                {
+                    Some(e.substitute(*h, e2))
+                } else {
+                    None
+                }
+            }
+            Self::Retract(path) => e.retract(path),
+            // Like reused expressions, wrapped expressions are shared with the
+            // working expression, unless they are inputs (nullary
+            // applications, which can be introduced anywhere)
+            Self::Wrap(h, f, fp, sub, args) => match sub {
+                Self::Exp::App(_, sub_args)
+                    if sub_args.is_empty() || e.has_subterm(sub) =>
+                {
+                    e.wrap(*h, f, fp, sub, args)
+                }
+                _ => None,
+            },
             Self::Seq(s1, s2) => s1.apply(e).and_then(|e2| s2.apply(&e2)),
         }
     }
@@ -310,15 +484,321 @@ impl<O: InhabitationOracle> pbn::StepProvider<util::Timer>
         timer: &Timer,
         e: &<Self::Step as pbn::Step>::Exp,
     ) -> Result<Vec<Self::Step>, EarlyCutoff> {
+        let expansions = self.oracle.expansions(timer, e)?;
         let mut ret = vec![];
-        for (h, f) in self.oracle.expansions(timer, e)? {
+        for (h, f) in &expansions {
             let holes = e.fresh().map(Sketch::Hole);
             ret.push(TopDownStep::Extend(
-                h,
+                *h,
                 f.clone(),
                 f.arity().into_iter().zip(holes).collect(),
             ));
         }
+        // A ground sub-expression can be reused for a hole whenever its head
+        // function is a valid expansion of that hole (its arguments are
+        // already complete); reusing a nullary application would be the same
+        // as extending the hole with it
+        let subterms = e.ground_subterms();
+        for (h, f) in &expansions {
+            for sub in &subterms {
+                if let Sketch::App(g, args) = sub {
+                    if g == f && !args.is_empty() {
+                        ret.push(TopDownStep::Reuse(*h, (*sub).clone()));
+                    }
+                }
+            }
+        }
         Ok(ret)
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// Bottom-Up Synthesis
+//   (Attaching function applications above existing ones)
+
+/// The type of attachments: a hole, a function whose application can fill it,
+/// the parameter of that function, and the ground expression (an existing
+/// sub-expression or an input) that it can take as its argument there
+pub type Attachment<F> = (HoleName, F, FunParam, Sketch<F>);
+
+/// The type of forward oracles for use in bottom-up synthesis (the counterpart
+/// of [`InhabitationOracle`]).
+pub trait ForwardOracle {
+    type F: Function;
+    fn attachments(
+        &mut self,
+        timer: &Timer,
+        e: &Sketch<Self::F>,
+    ) -> Result<Vec<Attachment<Self::F>>, EarlyCutoff>;
+}
+
+/// Bottom-up synthesis, which provides the steps that fill a hole with a new
+/// function application above an existing expression (the other arguments of
+/// the new application are fresh holes, still directed towards the goal).
+pub struct BottomUpSynthesis<O: ForwardOracle> {
+    pub oracle: O,
+}
+
+impl<O: ForwardOracle> BottomUpSynthesis<O> {
+    pub fn new(oracle: O) -> Self {
+        Self { oracle }
+    }
+}
+
+impl<O: ForwardOracle> pbn::StepProvider<util::Timer> for BottomUpSynthesis<O> {
+    type Step = TopDownStep<O::F>;
+    fn provide(
+        &mut self,
+        timer: &Timer,
+        e: &<Self::Step as pbn::Step>::Exp,
+    ) -> Result<Vec<Self::Step>, EarlyCutoff> {
+        let mut ret = vec![];
+        for (h, f, fp, sub) in self.oracle.attachments(timer, e)? {
+            let holes = e.fresh().map(Sketch::Hole);
+            let args = f
+                .arity()
+                .into_iter()
+                .filter(|p| *p != fp)
+                .zip(holes)
+                .collect();
+            ret.push(TopDownStep::Wrap(h, f, fp, sub, args));
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pbn::{Step, StepProvider};
+
+    /// A function with a name and keyword parameters
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Fun(&'static str, Vec<&'static str>);
+
+    impl Function for Fun {
+        fn arity(&self) -> Vec<FunParam> {
+            self.1.iter().map(|fp| FunParam(fp.to_string())).collect()
+        }
+    }
+
+    fn leaf() -> Fun {
+        Fun("leaf", vec![])
+    }
+
+    fn trim() -> Fun {
+        Fun("trim", vec!["reads"])
+    }
+
+    fn pair() -> Fun {
+        Fun("pair", vec!["a", "b"])
+    }
+
+    fn app(f: Fun, args: Vec<Sketch<Fun>>) -> Sketch<Fun> {
+        Sketch::App(f.clone(), f.arity().into_iter().zip(args).collect())
+    }
+
+    fn trimmed() -> Sketch<Fun> {
+        app(trim(), vec![app(leaf(), vec![])])
+    }
+
+    /// An inhabitation oracle that always returns the same expansions
+    struct Fixed(Vec<Expansion<Fun>>);
+
+    impl InhabitationOracle for Fixed {
+        type F = Fun;
+
+        fn expansions(
+            &mut self,
+            _timer: &Timer,
+            _e: &Sketch<Fun>,
+        ) -> Result<Vec<Expansion<Fun>>, EarlyCutoff> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn reuse_shares_ground_subexpressions() {
+        let e = app(pair(), vec![trimmed(), Sketch::Hole(1)]);
+
+        assert_eq!(
+            TopDownStep::Reuse(1, trimmed()).apply(&e),
+            Some(app(pair(), vec![trimmed(), trimmed()]))
+        );
+
+        // Only ground sub-expressions of the working expression can be reused
+        // (for holes that it has)
+        let partial = app(trim(), vec![Sketch::Hole(2)]);
+        let e2 = app(pair(), vec![partial.clone(), Sketch::Hole(1)]);
+        assert_eq!(TopDownStep::Reuse(1, partial).apply(&e2), None);
+        assert_eq!(
+            TopDownStep::Reuse(1, app(trim(), vec![trimmed()])).apply(&e),
+            None
+        );
+        assert_eq!(TopDownStep::Reuse(3, trimmed()).apply(&e), None);
+    }
+
+    #[test]
+    fn provide_offers_reuse_for_valid_expansions() {
+        let e = app(pair(), vec![trimmed(), Sketch::Hole(1)]);
+        let timer = Timer::infinite();
+
+        let mut synth =
+            ClassicalConstructiveSynthesis::new(Fixed(vec![(1, trim())]));
+        assert_eq!(
+            synth.provide(&timer, &e).unwrap(),
+            vec![
+                TopDownStep::Extend(
+                    1,
+                    trim(),
+                    IndexMap::from([(
+                        FunParam("reads".to_owned()),
+                        Sketch::Hole(2)
+                    )])
+                ),
+                TopDownStep::Reuse(1, trimmed()),
+            ]
+        );
+
+        // Reusing a nullary application would be the same as extending with
+        // it, and functions that are not valid expansions are never reused
+        let mut synth =
+            ClassicalConstructiveSynthesis::new(Fixed(vec![(1, leaf())]));
+        assert_eq!(
+            synth.provide(&timer, &e).unwrap(),
+            vec![TopDownStep::Extend(1, leaf(), IndexMap::new())]
+        );
+    }
+
+    #[test]
+    fn retract_keeps_siblings() {
+        let fp = |s: &str| FunParam(s.to_owned());
+        let e = app(pair(), vec![trimmed(), Sketch::Hole(1)]);
+
+        assert_eq!(
+            e.retract(&[fp("a"), fp("reads")]),
+            Some(app(
+                pair(),
+                vec![app(trim(), vec![Sketch::Hole(2)]), Sketch::Hole(1)]
+            ))
+        );
+        assert_eq!(e.retract(&[]), Some(Sketch::Hole(2)));
+
+        // Holes and missing parameters cannot be retracted
+        assert_eq!(e.retract(&[fp("b")]), None);
+        assert_eq!(e.retract(&[fp("c")]), None);
+        assert_eq!(e.retract(&[fp("a"), fp("a")]), None);
+        assert_eq!(e.retract(&[fp("b"), fp("reads")]), None);
+    }
+
+    #[test]
+    fn retracting_a_shared_subexpression_unshares_it() {
+        let e = app(pair(), vec![trimmed(), trimmed()]);
+        assert_eq!(
+            e.retract(&[FunParam("a".to_owned())]),
+            Some(app(pair(), vec![Sketch::Hole(1), trimmed()]))
+        );
+    }
+
+    #[test]
+    fn wrap_fills_a_hole_above_a_ground_expression() {
+        let fp = |s: &str| FunParam(s.to_owned());
+        let e = app(pair(), vec![trimmed(), Sketch::Hole(1)]);
+        let input = app(leaf(), vec![]);
+
+        assert_eq!(
+            e.wrap(1, &trim(), &fp("reads"), &input, &IndexMap::new()),
+            Some(app(pair(), vec![trimmed(), trimmed()]))
+        );
+        assert_eq!(
+            e.wrap(
+                1,
+                &pair(),
+                &fp("b"),
+                &trimmed(),
+                &IndexMap::from([(fp("a"), Sketch::Hole(2))])
+            ),
+            Some(app(
+                pair(),
+                vec![trimmed(), app(pair(), vec![Sketch::Hole(2), trimmed()])]
+            ))
+        );
+
+        // The hole must exist, the expression must be a ground application,
+        // and the arguments must match the arity of the function
+        let none = IndexMap::new();
+        assert_eq!(e.wrap(2, &trim(), &fp("reads"), &input, &none), None);
+        assert_eq!(
+            e.wrap(1, &trim(), &fp("reads"), &Sketch::Hole(1), &none),
+            None
+        );
+        assert_eq!(
+            e.wrap(
+                1,
+                &trim(),
+                &fp("reads"),
+                &app(trim(), vec![Sketch::Hole(2)]),
+                &none
+            ),
+            None
+        );
+        assert_eq!(e.wrap(1, &trim(), &fp("a"), &input, &none), None);
+        assert_eq!(e.wrap(1, &pair(), &fp("a"), &input, &none), None);
+
+        // Only inputs and sub-expressions of the working expression can be
+        // attached as steps
+        let wrap = |sub: Sketch<Fun>| {
+            TopDownStep::Wrap(1, trim(), fp("reads"), sub, IndexMap::new())
+        };
+        assert!(wrap(input).apply(&e).is_some());
+        assert!(wrap(trimmed()).apply(&e).is_some());
+        assert_eq!(wrap(app(trim(), vec![trimmed()])).apply(&e), None);
+    }
+
+    /// A forward oracle that always returns the same attachments
+    struct FixedForward(Vec<Attachment<Fun>>);
+
+    impl ForwardOracle for FixedForward {
+        type F = Fun;
+
+        fn attachments(
+            &mut self,
+            _timer: &Timer,
+            _e: &Sketch<Fun>,
+        ) -> Result<Vec<Attachment<Fun>>, EarlyCutoff> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn attachments_leave_fresh_holes_for_the_other_arguments() {
+        let e = app(pair(), vec![trimmed(), Sketch::Hole(1)]);
+        let a = FunParam("a".to_owned());
+        let b = FunParam("b".to_owned());
+
+        let mut synth = BottomUpSynthesis::new(FixedForward(vec![(
+            1,
+            pair(),
+            b.clone(),
+            trimmed(),
+        )]));
+        let steps = synth.provide(&Timer::infinite(), &e).unwrap();
+        assert_eq!(
+            steps,
+            vec![TopDownStep::Wrap(
+                1,
+                pair(),
+                b,
+                trimmed(),
+                IndexMap::from([(a, Sketch::Hole(2))])
+            )]
+        );
+        assert_eq!(
+            steps[0].apply(&e),
+            Some(app(
+                pair(),
+                vec![trimmed(), app(pair(), vec![Sketch::Hole(2), trimmed()])]
+            ))
+        );
+    }
+}
@@ -1,9 +1,11 @@
+use crate::core::{BaseFunction, ParameterizedFunction};
 use crate::top_down::*;
 use crate::util;
 
 use pbn::*;
 
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
+use std::collections::BinaryHeap;
 
 /// The type of hole fillings (mappings from hole names to sketches).
 pub type HoleFilling<F> = IndexMap<HoleName, Sketch<F>>;
@@ -111,3 +113,431 @@ impl<
         }
     }
 }
+
+/// The type of inhabitation oracles that also know the costs of functions (for
+/// use in minimum-cost synthesis).
+pub trait CostOracle: InhabitationOracle {
+    /// Returns the cost of a single application of a function
+    fn application_cost(&self, f: &Self::F) -> f64;
+
+    /// Returns the minimal total cost of a complete expression rooted at a
+    /// function (or `None` if there is no such expression)
+    fn minimal_cost(
+        &mut self,
+        timer: &util::Timer,
+        f: &Self::F,
+    ) -> Result<Option<f64>, util::EarlyCutoff>;
+}
+
+/// An entry of the frontier of [`MinCostAnySynthesizer`]: a sketch along with
+/// its estimated total cost and its cost so far.
+struct FrontierEntry<S> {
+    estimate: f64,
+    cost: f64,
+    order: usize,
+    sketch: S,
+}
+
+impl<S> FrontierEntry<S> {
+    /// Many sketches usually share the minimum estimate, so ties are broken
+    /// towards the sketches closest to completion (those with the greatest
+    /// cost so far, then the most recent) to avoid exploring all of them
+    fn priority(&self, other: &Self) -> std::cmp::Ordering {
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then(self.cost.total_cmp(&other.cost))
+            .then(self.order.cmp(&other.order))
+    }
+}
+
+impl<S> PartialEq for FrontierEntry<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority(other).is_eq()
+    }
+}
+
+impl<S> Eq for FrontierEntry<S> {}
+
+impl<S> PartialOrd for FrontierEntry<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S> Ord for FrontierEntry<S> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority(other)
+    }
+}
+
+/// A priority queue of sketches that pops the one of least estimated total
+/// cost first.
+struct Frontier<S> {
+    heap: BinaryHeap<FrontierEntry<S>>,
+    pushed: usize,
+}
+
+impl<S> Frontier<S> {
+    fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            pushed: 0,
+        }
+    }
+
+    fn push(&mut self, estimate: f64, cost: f64, sketch: S) {
+        self.heap.push(FrontierEntry {
+            estimate,
+            cost,
+            order: self.pushed,
+            sketch,
+        });
+        self.pushed += 1;
+    }
+
+    /// Returns the estimated total cost, cost so far, and sketch of the next
+    /// entry
+    fn pop(&mut self) -> Option<(f64, f64, S)> {
+        self.heap
+            .pop()
+            .map(|entry| (entry.estimate, entry.cost, entry.sketch))
+    }
+}
+
+/// A synthesizer solving the traditional Any task that returns a minimum-cost
+/// complete expression (with unit costs, a minimum-size one) using best-first
+/// search over the expansions of a cost oracle.
+///
+/// The estimated cost of a sketch is the cost of its function applications plus
+/// the minimal total cost of completing each of its holes with one of their
+/// expansions. By Strong Completeness, every completion of a hole begins with
+/// one of its expansions, so the estimate never exceeds the true cost and the
+/// first complete sketch to be explored has minimum cost.
+pub struct MinCostAnySynthesizer<
+    O: CostOracle,
+    V: ValidityChecker<Exp = Sketch<O::F>>,
+> {
+    oracle: O,
+    checker: V,
+}
+
+impl<O: CostOracle, V: ValidityChecker<Exp = Sketch<O::F>>>
+    MinCostAnySynthesizer<O, V>
+{
+    pub fn new(oracle: O, checker: V) -> Self {
+        Self { oracle, checker }
+    }
+
+    /// Visits complete sketches in order of cost until `visit` returns `true`
+    fn search(
+        &mut self,
+        timer: &util::Timer,
+        start: &Sketch<O::F>,
+        mut visit: impl FnMut(&Sketch<O::F>) -> bool,
+    ) -> Result<(), util::EarlyCutoff> {
+        let mut frontier = Frontier::new();
+        frontier.push(0.0, 0.0, start.clone());
+
+        while let Some((estimate, cost, e)) = frontier.pop() {
+            timer.tick()?;
+
+            if self.checker.check(&e) {
+                if visit(&e) {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            if e.size() > util::MAX_EXP_SIZE {
+                continue;
+            }
+
+            let mut bounds: IndexMap<HoleName, Vec<(O::F, f64)>> =
+                IndexMap::new();
+            for (h, f) in self.oracle.expansions(timer, &e)? {
+                if let Some(c) = self.oracle.minimal_cost(timer, &f)? {
+                    bounds.entry(h).or_default().push((f, c));
+                }
+            }
+
+            let lower: IndexMap<HoleName, f64> = bounds
+                .iter()
+                .map(|(h, fs)| {
+                    (
+                        *h,
+                        fs.iter()
+                            .map(|(_, c)| *c)
+                            .fold(f64::INFINITY, f64::min),
+                    )
+                })
+                .collect();
+
+            // The estimate of a sketch is only refined once it is explored, so
+            // it may need to be put back in line
+            let refined = cost + lower.values().sum::<f64>();
+            if refined > estimate {
+                frontier.push(refined, cost, e);
+                continue;
+            }
+
+            // Every hole must be filled, so it suffices to branch on the one
+            // with the fewest expansions
+            let (h, fs) = match bounds.iter().min_by_key(|(_, fs)| fs.len()) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let rest: f64 = lower
+                .iter()
+                .filter(|(h2, _)| *h2 != h)
+                .map(|(_, c)| *c)
+                .sum();
+
+            for (f, c) in fs {
+                let holes = e.fresh().map(Sketch::Hole);
+                let step = TopDownStep::Extend(
+                    *h,
+                    f.clone(),
+                    f.arity().into_iter().zip(holes).collect(),
+                );
+                frontier.push(
+                    cost + rest + c,
+                    cost + self.oracle.application_cost(f),
+                    step.apply(&e).unwrap(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<O: CostOracle, V: ValidityChecker<Exp = Sketch<O::F>>> AnySynthesizer
+    for MinCostAnySynthesizer<O, V>
+{
+    type F = O::F;
+
+    fn provide_any(
+        &mut self,
+        timer: &util::Timer,
+        start: &Sketch<Self::F>,
+    ) -> Result<Option<HoleFilling<Self::F>>, util::EarlyCutoff> {
+        let mut ret = None;
+        self.search(timer, start, |e| {
+            ret = start.pattern_match(e);
+            true
+        })?;
+        Ok(ret)
+    }
+}
+
+/// The type of synthesizers solving the top-k diverse task (for sketches):
+/// returning up to k complete expressions that pairwise differ in the
+/// functions they use.
+pub trait DiverseSynthesizer {
+    type F: Function;
+    fn provide_diverse(
+        &mut self,
+        timer: &util::Timer,
+        start: &Sketch<Self::F>,
+        k: usize,
+    ) -> Result<Vec<HoleFilling<Self::F>>, util::EarlyCutoff>;
+}
+
+/// Returns the set of functions that an expression applies (regardless of
+/// their metadata).
+fn functions(e: &Sketch<ParameterizedFunction>) -> IndexSet<BaseFunction> {
+    match e {
+        Sketch::Hole(_) => IndexSet::new(),
+        Sketch::App(f, args) => {
+            let mut ret = IndexSet::from([f.name.clone()]);
+            for arg in args.values() {
+                ret.extend(functions(arg));
+            }
+            ret
+        }
+    }
+}
+
+impl<
+        O: CostOracle<F = ParameterizedFunction>,
+        V: ValidityChecker<Exp = Sketch<O::F>>,
+    > DiverseSynthesizer for MinCostAnySynthesizer<O, V>
+{
+    type F = O::F;
+
+    /// Complete expressions are visited in order of cost and kept if no kept
+    /// expression uses the same set of functions (applications of the same
+    /// function with different metadata do not make expressions meaningfully
+    /// different). If time runs out, the expressions kept so far are returned.
+    fn provide_diverse(
+        &mut self,
+        timer: &util::Timer,
+        start: &Sketch<Self::F>,
+        k: usize,
+    ) -> Result<Vec<HoleFilling<Self::F>>, util::EarlyCutoff> {
+        let mut ret = vec![];
+
+        if k == 0 {
+            return Ok(ret);
+        }
+
+        let mut seen = vec![];
+        match self.search(timer, start, |e| {
+            let fs = functions(e);
+            if !seen.contains(&fs) {
+                seen.push(fs);
+                ret.extend(start.pattern_match(e));
+            }
+            ret.len() >= k
+        }) {
+            Ok(()) | Err(util::EarlyCutoff::TimerExpired) => Ok(ret),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Library, Problem};
+    use crate::{menu, parse};
+
+    // Both report functions are cheapest for short reads, so keeping only the
+    // cheapest metadata of each function would lose the nanopore pipelines
+    const LIBRARY: &str = r#"
+        [Type.Reads]
+        params.kind = "Str"
+
+        [Type.Report]
+        params.kind = "Str"
+
+        [Function.illumina]
+        params = {}
+        ret = "Reads"
+        condition = ['ret.kind = "short"']
+        info.cost = 1
+
+        [Function.nanopore]
+        params = {}
+        ret = "Reads"
+        condition = ['ret.kind = "long"']
+        info.cost = 5
+
+        [Function.summarize]
+        params.r = "Reads"
+        ret = "Report"
+        condition = ["ret.kind = r.kind"]
+        info.cost = 1
+
+        [Function.assemble]
+        params.r = "Reads"
+        ret = "Report"
+        condition = ["ret.kind = r.kind"]
+        info.cost = 3
+    "#;
+
+    fn problem() -> Problem {
+        Problem {
+            library: parse::library(LIBRARY).unwrap(),
+            program: parse::program(
+                r#"
+                Prop = []
+
+                [Goal]
+                name = "Report"
+                "#,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn cost(library: &Library, e: &Sketch<ParameterizedFunction>) -> f64 {
+        match e {
+            Sketch::Hole(_) => 0.0,
+            Sketch::App(f, args) => {
+                library.functions.get(&f.name).unwrap().cost()
+                    + args.values().map(|arg| cost(library, arg)).sum::<f64>()
+            }
+        }
+    }
+
+    fn complete(
+        start: &Sketch<ParameterizedFunction>,
+        hf: HoleFilling<ParameterizedFunction>,
+    ) -> Sketch<ParameterizedFunction> {
+        let mut e = start.clone();
+        for (h, rhs) in hf {
+            e = e.substitute(h, &rhs);
+        }
+        e
+    }
+
+    fn names(fs: &[&str]) -> IndexSet<BaseFunction> {
+        fs.iter().map(|f| BaseFunction(f.to_string())).collect()
+    }
+
+    #[test]
+    fn min_cost_is_minimal() {
+        let problem = problem();
+        let library = problem.library.clone();
+        let start = problem.start();
+
+        let mut synth = menu::Algorithm::PBNHoneybeeMinCost
+            .any_synthesizer(problem)
+            .unwrap();
+        let e = complete(
+            &start,
+            synth
+                .provide_any(&util::Timer::infinite(), &start)
+                .unwrap()
+                .unwrap(),
+        );
+
+        assert_eq!(cost(&library, &e), 2.0);
+        assert_eq!(functions(&e), names(&["summarize", "illumina"]));
+    }
+
+    #[test]
+    fn diverse_in_cost_order_with_distinct_functions() {
+        let problem = problem();
+        let library = problem.library.clone();
+        let start = problem.start();
+
+        let mut synth = menu::diverse_synthesizer(problem.clone()).unwrap();
+        let es: Vec<_> = synth
+            .provide_diverse(&util::Timer::infinite(), &start, 10)
+            .unwrap()
+            .into_iter()
+            .map(|hf| complete(&start, hf))
+            .collect();
+
+        let costs: Vec<f64> = es.iter().map(|e| cost(&library, e)).collect();
+        assert_eq!(costs, vec![2.0, 4.0, 6.0, 8.0]);
+
+        let fss: Vec<_> = es.iter().map(functions).collect();
+        assert_eq!(
+            fss,
+            vec![
+                names(&["summarize", "illumina"]),
+                names(&["assemble", "illumina"]),
+                names(&["summarize", "nanopore"]),
+                names(&["assemble", "nanopore"]),
+            ]
+        );
+
+        // Asking for fewer keeps the cheapest
+        let mut synth = menu::diverse_synthesizer(problem).unwrap();
+        let hfs = synth
+            .provide_diverse(&util::Timer::infinite(), &start, 3)
+            .unwrap();
+        assert_eq!(hfs.len(), 3);
+        assert_eq!(
+            hfs.into_iter()
+                .map(|hf| cost(&library, &complete(&start, hf)))
+                .collect::<Vec<_>>(),
+            vec![2.0, 4.0, 6.0]
+        );
+    }
+}
@@ -10,8 +10,10 @@
 use crate::core::*;
 use crate::eval;
 use crate::top_down::{FunParam, Sketch};
+use crate::util;
 
 use indexmap::{IndexMap, IndexSet};
+use serde::Serialize;
 
 /// A typing context; this must be created first in order to type-check.
 ///
@@ -20,24 +22,51 @@ use indexmap::{IndexMap, IndexSet};
 /// Honeybee core syntax) are provided in the `impl` block for this struct.
 pub struct Context<'a>(pub &'a Library);
 
+/// The components of libraries and programs that type errors can be located
+/// at.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum Location {
+    Prop(MetName),
+    Type(MetName),
+    Function(BaseFunction),
+    /// A condition of a function (indexed by its position in the list of
+    /// conditions; see [`Formula::conditions`])
+    Condition(BaseFunction, usize),
+    ProgramProp(usize),
+    /// A goal of a program (indexed by its position in the list of goals)
+    Goal(usize),
+}
+
 /// The type of type errors.
+///
+/// The location of an error is the most specific component of the library or
+/// program that the error is known to occur in.
 #[derive(Debug)]
 pub struct Error {
     pub context: Vec<String>,
     pub message: String,
+    pub location: Option<Location>,
     _private: (),
 }
 
 impl Error {
-    fn with_context(mut self, ctx: String) -> Self {
+    pub fn with_context(mut self, ctx: String) -> Self {
         self.context.push(ctx);
         self
     }
 
+    fn with_location(mut self, location: Location) -> Self {
+        if self.location.is_none() {
+            self.location = Some(location);
+        }
+        self
+    }
+
     fn new(message: String) -> Self {
         Self {
             context: vec![],
             message,
+            location: None,
             _private: (),
         }
     }
@@ -58,11 +87,24 @@ impl Error {
         Self::new(format!("unknown base function '{}'", bf.0))
     }
 
+    fn collision(kind: &str, name: &str) -> Self {
+        Self::new(format!(
+            "{} '{}' is defined differently by multiple libraries",
+            kind, name
+        ))
+    }
+
     fn argcount(got: usize, expected: usize) -> Self {
         Self::new(format!("got {} args, expected {}", got, expected))
     }
 }
 
+/// A hint for integer literals that are used as floats (integer literals are
+/// never implicitly converted).
+fn float_hint(x: i64) -> String {
+    format!(" (write {}.0 for a Float)", x)
+}
+
 type Check = Result<(), Error>;
 type Infer<T> = Result<T, Error>;
 
@@ -75,6 +117,62 @@ pub fn library(library: &Library) -> Check {
         .map_err(|e| e.with_context("library".to_owned()))
 }
 
+/// Merge an imported library into a library.
+///
+/// Props, types, and functions may only be defined once, unless the
+/// definitions are identical (so the same library can be imported along
+/// multiple paths). The config of the imported library is ignored.
+pub fn merge(library: &mut Library, import: Library) -> Check {
+    for (name, sig) in import.props {
+        match library.props.get(&name) {
+            Some(existing) if existing.params != sig.params => {
+                return Err(Error::collision("prop", &name.0))
+            }
+            Some(_) => (),
+            None => {
+                library.props.insert(name, sig);
+            }
+        }
+    }
+
+    for (name, sig) in import.types {
+        match library.types.get(&name) {
+            Some(existing) if existing.params != sig.params => {
+                return Err(Error::collision("type", &name.0))
+            }
+            Some(_) => (),
+            None => {
+                library.types.insert(name, sig);
+            }
+        }
+    }
+
+    for (f, fs) in import.functions {
+        match library.functions.get(&f) {
+            Some(existing) if *existing != fs => {
+                return Err(Error::collision("function", &f.0))
+            }
+            Some(_) => (),
+            None => {
+                library.functions.insert(f, fs);
+            }
+        }
+    }
+
+    if let Some(import_preamble) = import.preamble {
+        let preamble = library.preamble.get_or_insert_with(Vec::new);
+        let mut merged = import_preamble;
+        for entry in preamble.drain(..) {
+            if !merged.contains(&entry) {
+                merged.push(entry);
+            }
+        }
+        *preamble = merged;
+    }
+
+    Ok(())
+}
+
 /// Create a context from a problem and use that context to check the problem.
 pub fn problem(problem: &Problem) -> Check {
     let context = Context(&problem.library);
@@ -95,16 +193,32 @@ impl Context<'_> {
         let ambiguous_names: IndexSet<_> =
             pnames.intersection(&tnames).collect();
 
-        if !ambiguous_names.is_empty() {
+        if let Some(name) = ambiguous_names.first() {
             return Err(Error::new(format!(
                 "ambiguous prop/type names: {:?}",
                 ambiguous_names
-            )));
+            ))
+            .with_location(Location::Type((*name).clone())));
+        }
+
+        for (name, sig) in &self.0.props {
+            self.check_met_signature(sig).map_err(|e| {
+                e.with_location(Location::Prop(name.clone()))
+                    .with_context(format!("metadata signature '{}'", name.0))
+            })?;
+        }
+
+        for (name, sig) in &self.0.types {
+            self.check_met_signature(sig).map_err(|e| {
+                e.with_location(Location::Type(name.clone()))
+                    .with_context(format!("metadata signature '{}'", name.0))
+            })?;
         }
 
         for (f, fs) in &self.0.functions {
-            self.check_function_signature(fs).map_err(|e| {
-                e.with_context(format!("function signature '{}'", f.0))
+            self.check_function_signature(f, fs).map_err(|e| {
+                e.with_location(Location::Function(f.clone()))
+                    .with_context(format!("function signature '{}'", f.0))
             })?;
         }
 
@@ -112,20 +226,46 @@ impl Context<'_> {
     }
 
     fn check_program(&self, program: &Program) -> Check {
-        for p in &program.props {
-            let _ = self
-                .infer_proposition(p)
-                .map_err(|e| e.with_context("propositions".to_owned()))?;
+        for (i, p) in program.props.iter().enumerate() {
+            let _ = self.infer_proposition(p).map_err(|e| {
+                e.with_location(Location::ProgramProp(i))
+                    .with_context("propositions".to_owned())
+            })?;
         }
 
-        let _ = self
-            .infer_type(&program.goal)
-            .map_err(|e| e.with_context("goal".to_owned()))?;
+        if program.goals.is_empty() {
+            return Err(Error::new("program has no goals".to_owned()));
+        }
+
+        for (i, g) in program.goals.iter().enumerate() {
+            let _ = self.infer_type(g).map_err(|e| {
+                e.with_location(Location::Goal(i))
+                    .with_context("goal".to_owned())
+            })?;
+        }
 
         Ok(())
     }
 
-    fn check_function_signature(&self, fs: &FunctionSignature) -> Check {
+    fn check_met_signature(&self, sig: &MetSignature) -> Check {
+        for (mp, vt) in &sig.params {
+            if let ValueType::Enum(ss) = vt {
+                if ss.is_empty() {
+                    return Err(Error::new(format!(
+                        "enum metadata parameter '{}' has no values",
+                        mp.0
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_function_signature(
+        &self,
+        f: &BaseFunction,
+        fs: &FunctionSignature,
+    ) -> Check {
         for type_name in fs.params.values() {
             let _ = self
                 .0
@@ -138,7 +278,24 @@ impl Context<'_> {
             .types
             .get(&fs.ret)
             .ok_or_else(|| Error::mn(&fs.ret))?;
-        self.check_formula(fs, &fs.condition)
+        if let Some(cost) = fs.info.as_ref().and_then(|inf| inf.get("cost")) {
+            match cost {
+                toml::Value::Integer(n) if *n >= 0 => (),
+                toml::Value::Float(x) if *x >= 0.0 => (),
+                _ => {
+                    return Err(Error::new(format!(
+                        "cost {} is not a non-negative number",
+                        cost
+                    )))
+                }
+            }
+        }
+        for (i, phi) in fs.condition.conditions().into_iter().enumerate() {
+            self.check_formula(fs, phi).map_err(|e| {
+                e.with_location(Location::Condition(f.clone(), i))
+            })?;
+        }
+        Ok(())
     }
 
     fn check_formula(&self, fs: &FunctionSignature, phi: &Formula) -> Check {
@@ -147,21 +304,25 @@ impl Context<'_> {
             Formula::Eq(fa1, fa2) | Formula::Neq(fa1, fa2) => {
                 self.check_formula_atom_types_equal(fs, fa1, fa2)
             }
-            Formula::Lt(fa1, fa2) => {
+            Formula::Lt(fa1, fa2)
+            | Formula::Le(fa1, fa2)
+            | Formula::Gt(fa1, fa2)
+            | Formula::Ge(fa1, fa2) => {
                 let vt1 = self.infer_formula_atom(fs, fa1)?;
-                if vt1 != ValueType::Int {
+                if vt1 != ValueType::Int && vt1 != ValueType::Float {
                     return Err(Error::new(format!(
-                        "formula atom {:?} has type {:?}, expected Int",
+                        "formula atom {:?} has type {:?}, expected Int or Float",
                         fa1, vt1,
                     )));
                 }
                 self.check_formula_atom_types_equal(fs, fa1, fa2)
             }
             Formula::Ap(ap) => self.check_atomic_proposition(fs, ap),
-            Formula::And(phi1, phi2) => {
+            Formula::And(phi1, phi2) | Formula::Or(phi1, phi2) => {
                 self.check_formula(fs, phi1)?;
                 self.check_formula(fs, phi2)
             }
+            Formula::Not(phi) => self.check_formula(fs, phi),
         }
     }
 
@@ -173,15 +334,56 @@ impl Context<'_> {
     ) -> Result<(), Error> {
         let vt1 = self.infer_formula_atom(fs, fa1)?;
         let vt2 = self.infer_formula_atom(fs, fa2)?;
+        if let ValueType::Enum(_) = vt1 {
+            return self.check_formula_atom(fs, fa2, &vt1);
+        }
+        if let ValueType::Enum(_) = vt2 {
+            return self.check_formula_atom(fs, fa1, &vt2);
+        }
         if vt1 != vt2 {
             return Err(Error::new(format!(
-                "formula atom {:?} has different type ({:?}) than formula atom {:?} ({:?})",
-                fa1, vt1, fa2, vt2
+                "formula atom {:?} has different type ({:?}) than formula atom {:?} ({:?}){}",
+                fa1,
+                vt1,
+                fa2,
+                vt2,
+                [fa1, fa2]
+                    .into_iter()
+                    .find_map(|fa| match fa {
+                        FormulaAtom::Lit(Value::Int(x))
+                            if vt1 == ValueType::Float
+                                || vt2 == ValueType::Float =>
+                            Some(float_hint(*x)),
+                        _ => None,
+                    })
+                    .unwrap_or_default()
             )));
         }
         Ok(())
     }
 
+    fn check_formula_atom(
+        &self,
+        fs: &FunctionSignature,
+        fa: &FormulaAtom,
+        expected_vt: &ValueType,
+    ) -> Check {
+        if let FormulaAtom::Lit(v) = fa {
+            return self.check_value(v, expected_vt);
+        }
+
+        let got_vt = self.infer_formula_atom(fs, fa)?;
+
+        if got_vt != *expected_vt {
+            return Err(Error::new(format!(
+                "formula atom {:?} is type {:?} but expected {:?}",
+                fa, got_vt, expected_vt
+            )));
+        }
+
+        Ok(())
+    }
+
     fn check_atomic_proposition(
         &self,
         fs: &FunctionSignature,
@@ -209,17 +411,8 @@ impl Context<'_> {
                 None => continue,
             };
 
-            let got_vt = self.infer_formula_atom(fs, fa)?;
-
-            if got_vt != *expected_vt {
-                return Err(Error::new(format!(
-                    "argument {:?} of atomic proposition {:?} is type {:?} but expected {:?}",
-                    fa,
-                    ap.name,
-                    got_vt,
-                    expected_vt
-                )).with_context(ap.context()));
-            }
+            self.check_formula_atom(fs, fa, expected_vt)
+                .map_err(|e| e.with_context(ap.context()))?;
         }
 
         Ok(())
@@ -253,6 +446,25 @@ impl Context<'_> {
                 .ok_or_else(|| Error::mp(mp))
                 .cloned(),
             FormulaAtom::Lit(v) => Ok(self.infer_value(v)),
+            FormulaAtom::Add(fa1, fa2) | FormulaAtom::Sub(fa1, fa2) => {
+                if fa.param_count() > util::MAX_ARITHMETIC_PARAMS {
+                    return Err(Error::new(format!(
+                        "formula atom {:?} has more than {} metadata parameters",
+                        fa,
+                        util::MAX_ARITHMETIC_PARAMS,
+                    )));
+                }
+                for fa in [fa1, fa2] {
+                    let vt = self.infer_formula_atom(fs, fa)?;
+                    if vt != ValueType::Int {
+                        return Err(Error::new(format!(
+                            "formula atom {:?} has type {:?}, expected Int",
+                            fa, vt,
+                        )));
+                    }
+                }
+                Ok(ValueType::Int)
+            }
         }
     }
 
@@ -273,15 +485,8 @@ impl Context<'_> {
                 .params
                 .get(mp)
                 .ok_or_else(|| Error::mp(mp).with_context(met.context()))?;
-            let got_vt = self.infer_value(v);
-
-            if got_vt != *expected_vt {
-                return Err(Error::new(format!(
-                    "argument {:?} of {:?} is type {:?} but expected {:?}",
-                    v, met.name, got_vt, expected_vt
-                ))
-                .with_context(met.context()));
-            }
+            self.check_value(v, expected_vt)
+                .map_err(|e| e.with_context(met.context()))?;
         }
 
         Ok(sig.clone())
@@ -291,14 +496,79 @@ impl Context<'_> {
         self.infer_met(&self.0.props, met)
     }
 
-    pub fn infer_type(&self, met: &Met<Value>) -> Infer<MetSignature> {
-        self.infer_met(&self.0.types, met)
+    /// Infer the signature of a (partial) type; unlike propositions, types may
+    /// have omitted arguments.
+    pub fn infer_type(&self, met: &PartialType) -> Infer<MetSignature> {
+        let sig = self
+            .0
+            .types
+            .get(&met.name)
+            .ok_or_else(|| Error::mn(&met.name))?;
+
+        for (mp, ov) in &met.args {
+            let expected_vt = sig
+                .params
+                .get(mp)
+                .ok_or_else(|| Error::mp(mp).with_context(met.context()))?;
+
+            let v = match ov {
+                Some(v) => v,
+                None => continue,
+            };
+
+            self.check_value(v, expected_vt)
+                .map_err(|e| e.with_context(met.context()))?;
+        }
+
+        Ok(sig.clone())
+    }
+
+    /// Check that a value has a value type (a string has an enum type if it is
+    /// one of the declared values of the enum).
+    ///
+    /// NaN is not a value of any type: comparisons with NaN never hold, so the
+    /// negation of a comparison (which the Datalog oracle and the evaluator
+    /// compile to the opposite comparison) would not hold either.
+    pub fn check_value(&self, v: &Value, vt: &ValueType) -> Check {
+        let got_vt = self.infer_value(v);
+        match (v, vt) {
+            (Value::Float(x), _) if x.is_nan() => {
+                return Err(Error::new(format!(
+                    "value {:?} is not a number",
+                    v
+                )));
+            }
+            (Value::Str(s), ValueType::Enum(ss)) => {
+                if !ss.contains(s) {
+                    return Err(Error::new(format!(
+                        "value {:?} is not one of the declared values {:?}",
+                        v, ss
+                    )));
+                }
+            }
+            _ => {
+                if got_vt != *vt {
+                    return Err(Error::new(format!(
+                        "value {:?} is type {:?} but expected {:?}{}",
+                        v,
+                        got_vt,
+                        vt,
+                        match (v, vt) {
+                            (Value::Int(x), ValueType::Float) => float_hint(*x),
+                            _ => "".to_owned(),
+                        }
+                    )));
+                }
+            }
+        }
+        Ok(())
     }
 
     pub fn infer_value(&self, v: &Value) -> ValueType {
         match v {
             Value::Bool(_) => ValueType::Bool,
             Value::Int(_) => ValueType::Int,
+            Value::Float(_) => ValueType::Float,
             Value::Str(_) => ValueType::Str,
         }
     }
@@ -323,13 +593,7 @@ impl Context<'_> {
                     .ok_or_else(|| Error::bf(&f.name))?;
 
                 // Compute domain
-                let mut vals = IndexSet::new();
-                for fs in self.0.functions.values() {
-                    vals.extend(fs.vals());
-                }
-                for p in &program.props {
-                    vals.extend(p.args.values().cloned());
-                }
+                let mut vals = self.0.program_vals(program);
                 vals.extend(f.metadata.values().cloned());
 
                 // Recursively infer values and check proper domain
@@ -369,3 +633,100 @@ impl Context<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn library(condition: &str) -> Library {
+        let src = format!(
+            r#"
+            [Type.Reads]
+            params.kind = {{ Enum = ["illumina", "nanopore"] }}
+
+            [Function.sequence]
+            params = {{}}
+            ret = "Reads"
+            condition = ['{}']
+            "#,
+            condition
+        );
+        parse::library(&src).unwrap()
+    }
+
+    fn problem(kind: &str) -> Problem {
+        let program = parse::program(&format!(
+            r#"
+            Prop = []
+
+            [Goal]
+            name = "Reads"
+            args.kind = "{}"
+            "#,
+            kind
+        ))
+        .unwrap();
+        Problem {
+            library: library(r#"ret.kind = "illumina""#),
+            program,
+        }
+    }
+
+    #[test]
+    fn enum_conditions_must_be_in_domain() {
+        assert!(super::library(&library(r#"ret.kind = "illumina""#)).is_ok());
+        assert!(super::library(&library(r#"ret.kind = "pacbio""#)).is_err());
+        assert!(super::library(&library(r#""pacbio" = ret.kind"#)).is_err());
+    }
+
+    #[test]
+    fn enum_goals_must_be_in_domain() {
+        assert!(super::problem(&problem("nanopore")).is_ok());
+        assert!(super::problem(&problem("pacbio")).is_err());
+    }
+
+    #[test]
+    fn empty_enums_are_rejected() {
+        let mut lib = library(r#"ret.kind = "illumina""#);
+        lib.types[0].params[0] = ValueType::Enum(vec![]);
+        assert!(super::library(&lib).is_err());
+    }
+
+    #[test]
+    fn nan_is_rejected() {
+        let library = parse::library(
+            r#"
+            [Prop.Threshold]
+            params.p = "Float"
+
+            [Type.Reads]
+            params = {}
+
+            [Function.filter]
+            params = {}
+            ret = "Reads"
+            condition = ["Threshold { p = _ }"]
+            "#,
+        )
+        .unwrap();
+        let problem = |p: &str| Problem {
+            library: library.clone(),
+            program: parse::program(&format!(
+                r#"
+                [[Prop]]
+                name = "Threshold"
+                args.p = {}
+
+                [Goal]
+                name = "Reads"
+                "#,
+                p
+            ))
+            .unwrap(),
+        };
+        assert!(super::problem(&problem("0.05")).is_ok());
+        assert!(super::problem(&problem("inf")).is_ok());
+        assert!(super::problem(&problem("nan")).is_err());
+    }
+}
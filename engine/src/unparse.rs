@@ -1,10 +1,165 @@
 //! # Unparsing Honeybee core syntax
 //!
-//! This module provides serialization for expressions to JSON.
+//! This module provides serialization for expressions to JSON, as well as
+//! unparsing for formulas back to their surface syntax.
 
 use crate::core::*;
+use crate::util;
 
 /// Serialize an expression to JSON
 pub fn exp(e: &Exp) -> Result<String, String> {
     serde_json::to_string(e).map_err(|e| e.to_string())
 }
+
+fn value(v: &Value) -> String {
+    match v {
+        Value::Bool(b) => b.to_string(),
+        Value::Int(x) => x.to_string(),
+        Value::Float(x) if x.is_nan() => "nan".to_owned(),
+        Value::Float(x) if x.is_infinite() => {
+            if *x > 0.0 { "inf" } else { "-inf" }.to_owned()
+        }
+        Value::Float(x) => format!("{:?}", x),
+        Value::Str(s) => util::quote(s),
+    }
+}
+
+fn formula_atom(fa: &FormulaAtom) -> String {
+    match fa {
+        FormulaAtom::Param(fp, mp) => format!("{}.{}", fp.0, mp.0),
+        FormulaAtom::Ret(mp) => format!("ret.{}", mp.0),
+        FormulaAtom::Lit(v) => value(v),
+        FormulaAtom::Add(fa1, fa2) => arithmetic(fa1, "+", fa2),
+        FormulaAtom::Sub(fa1, fa2) => arithmetic(fa1, "-", fa2),
+    }
+}
+
+// Arithmetic is left-associative, so only the right operand needs parentheses
+fn arithmetic(fa1: &FormulaAtom, op: &str, fa2: &FormulaAtom) -> String {
+    let right = match fa2 {
+        FormulaAtom::Add(_, _) | FormulaAtom::Sub(_, _) => {
+            format!("({})", formula_atom(fa2))
+        }
+        _ => formula_atom(fa2),
+    };
+    format!("{} {} {}", formula_atom(fa1), op, right)
+}
+
+fn atomic_proposition(ap: &AtomicProposition) -> String {
+    if ap.args.is_empty() {
+        return format!("{} {{}}", ap.name.0);
+    }
+    format!(
+        "{} {{ {} }}",
+        ap.name.0,
+        ap.args
+            .iter()
+            .map(|(mp, ofa)| format!(
+                "{} = {}",
+                mp.0,
+                match ofa {
+                    Some(fa) => formula_atom(fa),
+                    None => "_".to_owned(),
+                }
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// Precedence levels: 0 for disjunctions, 1 for conjunctions, and 2 for
+// everything else
+fn formula_prec(f: &Formula, prec: usize) -> String {
+    let (s, level) = match f {
+        Formula::True => ("true".to_owned(), 2),
+        Formula::Eq(fa1, fa2) => (comparison(fa1, "=", fa2), 2),
+        Formula::Lt(fa1, fa2) => (comparison(fa1, "<", fa2), 2),
+        Formula::Le(fa1, fa2) => (comparison(fa1, "<=", fa2), 2),
+        Formula::Gt(fa1, fa2) => (comparison(fa1, ">", fa2), 2),
+        Formula::Ge(fa1, fa2) => (comparison(fa1, ">=", fa2), 2),
+        Formula::Neq(fa1, fa2) => (comparison(fa1, "!=", fa2), 2),
+        Formula::Ap(ap) => (atomic_proposition(ap), 2),
+        Formula::And(phi1, phi2) => (
+            format!("{} and {}", formula_prec(phi1, 1), formula_prec(phi2, 2)),
+            1,
+        ),
+        Formula::Or(phi1, phi2) => (
+            format!("{} or {}", formula_prec(phi1, 0), formula_prec(phi2, 1)),
+            0,
+        ),
+        Formula::Not(phi) => (format!("not {}", formula_prec(phi, 2)), 2),
+    };
+    if level < prec {
+        format!("({})", s)
+    } else {
+        s
+    }
+}
+
+fn comparison(fa1: &FormulaAtom, op: &str, fa2: &FormulaAtom) -> String {
+    format!("{} {} {}", formula_atom(fa1), op, formula_atom(fa2))
+}
+
+/// Unparse a formula to its surface syntax
+pub fn formula(f: &Formula) -> String {
+    formula_prec(f, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(phi: Formula) {
+        let s = formula(&phi);
+        assert_eq!(
+            Formula::try_from(vec![s.clone()]),
+            Ok(Formula::conjunct([phi].into_iter())),
+            "{}",
+            s
+        );
+    }
+
+    fn parse(s: &str) -> Formula {
+        Formula::try_from(vec![s.to_owned()]).unwrap()
+    }
+
+    #[test]
+    fn formulas_round_trip() {
+        for s in [
+            "ret.n = x.n - (1 + y.n)",
+            "not (ret.n < 1 or ret.n >= 3) and ret.b != true",
+            "P { a = _, b = x.s, c = \"q\\\"\" } or not Q { } or R {}",
+            "ret.x = 1 or ret.x = 2 and (ret.x = 3 or ret.x = 4)",
+        ] {
+            round_trip(parse(s));
+        }
+    }
+
+    #[test]
+    fn true_round_trips() {
+        round_trip(Formula::True);
+        round_trip(Formula::conjunct(
+            [parse("ret.n = 1"), parse("ret.n < 2")].into_iter(),
+        ));
+    }
+
+    #[test]
+    fn special_floats_round_trip() {
+        for x in [
+            1e-8,
+            -0.5,
+            1e300,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ] {
+            round_trip(Formula::Lt(
+                FormulaAtom::Ret(MetParam("p".to_owned())),
+                FormulaAtom::Lit(Value::Float(x)),
+            ));
+        }
+
+        // The special floats do not shadow function parameters of that name
+        round_trip(parse("inf.n = nan.n"));
+    }
+}
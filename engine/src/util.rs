@@ -1,9 +1,12 @@
 //! # Utilities
 
+use crate::datalog;
+
 use indexmap::IndexMap;
 use instant::Duration;
 use instant::Instant;
 use pbn::Timer as _;
+use serde::{Deserialize, Serialize};
 
 ////////////////////////////////////////////////////////////////////////////////
 // Early cutoff
@@ -13,6 +16,7 @@ use pbn::Timer as _;
 pub enum EarlyCutoff {
     TimerExpired,
     OutOfMemory,
+    Engine(datalog::Error),
 }
 
 impl std::fmt::Display for EarlyCutoff {
@@ -20,16 +24,32 @@ impl std::fmt::Display for EarlyCutoff {
         match self {
             EarlyCutoff::TimerExpired => write!(f, "TimerExpired"),
             EarlyCutoff::OutOfMemory => write!(f, "OutOfMemory"),
+            EarlyCutoff::Engine(e) => write!(f, "{}", e),
         }
     }
 }
 
 impl std::error::Error for EarlyCutoff {}
 
+impl From<datalog::Error> for EarlyCutoff {
+    fn from(e: datalog::Error) -> Self {
+        EarlyCutoff::Engine(e)
+    }
+}
+
 /// The maximum size of an expression to consider to avoid stack overflows.
 /// (This number must be bigger than any expression that is to be synthesized.)
 pub const MAX_EXP_SIZE: usize = 500;
 
+/// The maximum number of integers that arithmetic may add to the domain of a
+/// problem (see [`crate::core::Library::extend_arithmetic`]).
+pub const MAX_ARITHMETIC_VALS: usize = 1000;
+
+/// The maximum number of metadata parameter occurrences in an arithmetic
+/// formula atom (each occurrence ranges over the whole domain, so computing
+/// the values of a term is exponential in this number).
+pub const MAX_ARITHMETIC_PARAMS: usize = 2;
+
 ////////////////////////////////////////////////////////////////////////////////
 // Timer
 
@@ -121,3 +141,156 @@ pub fn subscript_numbers(s: &str) -> String {
         })
         .collect()
 }
+
+/// The bit pattern that a float is compared (and hashed) by, with negative
+/// zero normalized to positive zero so that `-0.0` and `0.0` are the same
+/// value (all other floats, including NaNs, are distinguished by their bits).
+pub fn float_bits(x: f64) -> u64 {
+    if x == 0.0 {
+        0.0f64.to_bits()
+    } else {
+        x.to_bits()
+    }
+}
+
+/// Write a string as a double-quoted string literal.
+///
+/// Backslashes, double quotes, newlines, carriage returns, and tabs are
+/// escaped with a backslash, and all other control characters are escaped as
+/// `\uXXXX`; all other characters (including non-ASCII characters) are written
+/// verbatim. This syntax is shared by Honeybee formulas, Python, and Soufflé.
+pub fn quote(s: &str) -> String {
+    let mut ret = "\"".to_owned();
+    for c in s.chars() {
+        match c {
+            '\\' => ret += "\\\\",
+            '"' => ret += "\\\"",
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            '\t' => ret += "\\t",
+            c if c.is_control() => ret += &format!("\\u{:04x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Either a single value or a list of values (deserializes from either form,
+/// e.g. from a TOML table or an array of tables).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    /// Returns the values as a list
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(x) => vec![x],
+            OneOrMany::Many(xs) => xs,
+        }
+    }
+}
+
+/// A number of things that may be infinite, or finite but too many to count
+/// in a [`u64`]; serializes as either a number, `"overflow"`, or `"infinite"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "RawCount", into = "RawCount")]
+pub enum Count {
+    Finite(u64),
+    Overflow,
+    Infinite,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawCount {
+    Finite(u64),
+    Unbounded(String),
+}
+
+impl TryFrom<RawCount> for Count {
+    type Error = String;
+
+    fn try_from(raw: RawCount) -> Result<Self, Self::Error> {
+        match raw {
+            RawCount::Finite(n) => Ok(Count::Finite(n)),
+            RawCount::Unbounded(s) if s == "overflow" => Ok(Count::Overflow),
+            RawCount::Unbounded(s) if s == "infinite" => Ok(Count::Infinite),
+            RawCount::Unbounded(s) => Err(format!("invalid count '{}'", s)),
+        }
+    }
+}
+
+impl From<Count> for RawCount {
+    fn from(count: Count) -> Self {
+        match count {
+            Count::Finite(n) => RawCount::Finite(n),
+            Count::Overflow => RawCount::Unbounded("overflow".to_owned()),
+            Count::Infinite => RawCount::Unbounded("infinite".to_owned()),
+        }
+    }
+}
+
+impl std::ops::Add for Count {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (Count::Infinite, _) | (_, Count::Infinite) => Count::Infinite,
+            (Count::Finite(m), Count::Finite(n)) => {
+                m.checked_add(n).map_or(Count::Overflow, Count::Finite)
+            }
+            _ => Count::Overflow,
+        }
+    }
+}
+
+impl std::ops::Mul for Count {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        match (self, other) {
+            (Count::Finite(0), _) | (_, Count::Finite(0)) => Count::Finite(0),
+            (Count::Infinite, _) | (_, Count::Infinite) => Count::Infinite,
+            (Count::Finite(m), Count::Finite(n)) => {
+                m.checked_mul(n).map_or(Count::Overflow, Count::Finite)
+            }
+            _ => Count::Overflow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_overflow_instead_of_saturating() {
+        let max = Count::Finite(u64::MAX);
+
+        assert_eq!(max + Count::Finite(0), max);
+        assert_eq!(max + Count::Finite(1), Count::Overflow);
+        assert_eq!(max * Count::Finite(2), Count::Overflow);
+        assert_eq!(Count::Overflow + Count::Finite(1), Count::Overflow);
+        assert_eq!(Count::Overflow + Count::Infinite, Count::Infinite);
+        assert_eq!(Count::Overflow * Count::Infinite, Count::Infinite);
+        assert_eq!(Count::Overflow * Count::Finite(0), Count::Finite(0));
+        assert_eq!(Count::Infinite * Count::Finite(0), Count::Finite(0));
+
+        for count in [max, Count::Overflow, Count::Infinite] {
+            let json = serde_json::to_value(count).unwrap();
+            assert_eq!(serde_json::from_value::<Count>(json).unwrap(), count);
+        }
+        assert_eq!(
+            serde_json::to_value(Count::Overflow).unwrap(),
+            serde_json::json!("overflow")
+        );
+        assert!(
+            serde_json::from_value::<Count>(serde_json::json!("many")).is_err()
+        );
+    }
+}
@@ -1,5 +1,6 @@
 import ast
 import inspect
+import json
 import re
 from dataclasses import dataclass
 import subprocess
@@ -40,13 +41,28 @@ def _attribute_info(cls):
     return types, docs
 
 
+def _toml_string(s: str) -> str:
+    # JSON string escapes are a subset of TOML basic string escapes, except
+    # that TOML also requires DEL to be escaped
+    return json.dumps(s, ensure_ascii=False).replace("\x7f", "\\u007f")
+
+
 def _python_to_honeybee(type_name: str) -> str:
     if type_name == "str":
         return '"Str"'
     elif type_name == "int":
         return '"Int"'
+    elif type_name == "float":
+        return '"Float"'
     elif type_name == "bool":
         return '"Bool"'
+    elif type_name.startswith("Literal[") and type_name.endswith("]"):
+        options = ast.literal_eval(type_name[len("Literal[") : -1])
+        if isinstance(options, str):
+            options = (options,)
+        if not all(isinstance(o, str) for o in options):
+            raise ValueError("Only string literals are supported: " + type_name)
+        return "{ Enum = [" + ", ".join(_toml_string(o) for o in options) + "] }"
     else:
         raise ValueError("Unable to convert to Honeybee type: " + type_name)
 